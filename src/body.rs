@@ -0,0 +1,345 @@
+use core::fmt;
+use std::{
+    future::poll_fn,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf},
+    sync::mpsc,
+};
+
+use crate::chunked;
+
+/// Size of the reads done when streaming from an `AsyncRead`.
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// A source of body data that is produced piece by piece.
+///
+/// This is the streaming counterpart of a buffered body: each call yields the
+/// next chunk, `None` marks the end of the body.
+pub trait BodyStream: Send {
+    fn poll_chunk(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<io::Result<Bytes>>>;
+}
+
+/// The body of a response.
+///
+/// A body is either held in memory or streamed. Streamed bodies may declare
+/// their length up front, in which case they are sent with `Content-Length`;
+/// otherwise HTTP/1.1 responses use chunked transfer-encoding.
+pub struct Body {
+    kind: Kind,
+}
+
+enum Kind {
+    Empty,
+    Full(Bytes),
+    Stream {
+        stream: Pin<Box<dyn BodyStream>>,
+        len: Option<u64>,
+    },
+}
+
+/// The sending half of [`Body::channel`].
+pub struct Sender {
+    tx: mpsc::Sender<io::Result<Bytes>>,
+}
+
+impl Body {
+    #[inline]
+    pub fn empty() -> Body {
+        Body { kind: Kind::Empty }
+    }
+
+    /// Streams a body from `stream`. Pass `len` when the total size is known.
+    pub fn from_stream<S: BodyStream + 'static>(stream: S, len: Option<u64>) -> Body {
+        Body {
+            kind: Kind::Stream {
+                stream: Box::pin(stream),
+                len,
+            },
+        }
+    }
+
+    /// Streams a body from `reader`, such as an open file.
+    ///
+    /// With `Some(len)` exactly `len` bytes are sent, and a reader that ends
+    /// early is an error. With `None` the reader is read to its end.
+    pub fn from_reader<R>(reader: R, len: Option<u64>) -> Body
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+    {
+        Body::from_stream(
+            ReaderStream {
+                reader,
+                remaining: len,
+                buf: Vec::new(),
+            },
+            len,
+        )
+    }
+
+    /// Creates a body whose chunks are produced by another task.
+    ///
+    /// The body ends when the `Sender` is dropped.
+    pub fn channel(buffer: usize) -> (Sender, Body) {
+        let (tx, rx) = mpsc::channel(buffer);
+        (Sender { tx }, Body::from_stream(ChannelStream { rx }, None))
+    }
+
+    /// Exact length of the body, if it is known before it is sent.
+    pub fn size_hint(&self) -> Option<u64> {
+        match self.kind {
+            Kind::Empty => Some(0),
+            Kind::Full(ref bytes) => Some(bytes.len() as u64),
+            Kind::Stream { len, .. } => len,
+        }
+    }
+
+    /// Returns the next chunk of the body, or `None` once it is finished.
+    pub async fn next_chunk(&mut self) -> Option<io::Result<Bytes>> {
+        poll_fn(|cx| self.poll_next_chunk(cx)).await
+    }
+
+    fn poll_next_chunk(&mut self, cx: &mut Context<'_>) -> Poll<Option<io::Result<Bytes>>> {
+        match self.kind {
+            Kind::Empty => Poll::Ready(None),
+            Kind::Full(_) => match std::mem::replace(&mut self.kind, Kind::Empty) {
+                Kind::Full(bytes) if !bytes.is_empty() => Poll::Ready(Some(Ok(bytes))),
+                _ => Poll::Ready(None),
+            },
+            Kind::Stream { ref mut stream, .. } => stream.as_mut().poll_chunk(cx),
+        }
+    }
+
+    /// The data of a body held in memory, or the body itself if it is
+    /// streamed.
+    pub(crate) fn try_into_bytes(self) -> Result<Bytes, Body> {
+        match self.kind {
+            Kind::Empty => Ok(Bytes::new()),
+            Kind::Full(bytes) => Ok(bytes),
+            kind => Err(Body { kind }),
+        }
+    }
+
+    /// Collects the whole body into memory.
+    pub async fn collect(mut self) -> io::Result<Bytes> {
+        if let Kind::Full(bytes) = self.kind {
+            return Ok(bytes);
+        }
+        let mut buf = Vec::new();
+        while let Some(chunk) = self.next_chunk().await {
+            buf.extend_from_slice(&chunk?);
+        }
+        Ok(buf.into())
+    }
+
+    /// Writes the body to `writer`, framed with chunked transfer-encoding if
+    /// `chunked` is set.
+    ///
+    /// A body with a declared length that yields a different number of bytes
+    /// fails with `InvalidData`, since the framing already sent is wrong.
+    pub async fn write_to<W>(mut self, writer: &mut W, chunked: bool) -> io::Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let expected = self.size_hint();
+        let mut written = 0_u64;
+        while let Some(chunk) = self.next_chunk().await {
+            let chunk = chunk?;
+            if chunk.is_empty() {
+                continue;
+            }
+            written += chunk.len() as u64;
+            if expected.is_some_and(|len| written > len) {
+                return Err(length_mismatch());
+            }
+            if chunked {
+                writer
+                    .write_all(chunked::chunk_size_line(chunk.len()).as_bytes())
+                    .await?;
+                writer.write_all(&chunk).await?;
+                writer.write_all(b"\r\n").await?;
+            } else {
+                writer.write_all(&chunk).await?;
+            }
+        }
+        if expected.is_some_and(|len| written != len) {
+            return Err(length_mismatch());
+        }
+        if chunked {
+            writer.write_all(chunked::LAST_CHUNK).await?;
+        }
+        Ok(())
+    }
+}
+
+fn length_mismatch() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "body length does not match its declared size",
+    )
+}
+
+impl Default for Body {
+    fn default() -> Self {
+        Body::empty()
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            Kind::Empty => f.write_str("Body(Empty)"),
+            Kind::Full(ref bytes) => f.debug_tuple("Body").field(bytes).finish(),
+            Kind::Stream { len, .. } => f.debug_struct("Body").field("len", &len).finish(),
+        }
+    }
+}
+
+impl From<Bytes> for Body {
+    fn from(bytes: Bytes) -> Self {
+        Body {
+            kind: Kind::Full(bytes),
+        }
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(vec: Vec<u8>) -> Self {
+        Bytes::from(vec).into()
+    }
+}
+
+impl From<String> for Body {
+    fn from(s: String) -> Self {
+        Bytes::from(s).into()
+    }
+}
+
+impl From<&'static [u8]> for Body {
+    fn from(slice: &'static [u8]) -> Self {
+        Bytes::from_static(slice).into()
+    }
+}
+
+impl From<&'static str> for Body {
+    fn from(s: &'static str) -> Self {
+        Bytes::from_static(s.as_bytes()).into()
+    }
+}
+
+impl Sender {
+    /// Sends the next chunk. Fails if the body was dropped, for example
+    /// because the client went away.
+    pub async fn send_data(&self, chunk: Bytes) -> io::Result<()> {
+        self.tx
+            .send(Ok(chunk))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "body receiver dropped"))
+    }
+
+    /// Aborts the body. The connection is closed without finishing the
+    /// response.
+    pub async fn abort(self, err: io::Error) {
+        let _ = self.tx.send(Err(err)).await;
+    }
+}
+
+/// A body is itself a stream, so it can be wrapped to transform its data.
+impl BodyStream for Body {
+    fn poll_chunk(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<io::Result<Bytes>>> {
+        self.get_mut().poll_next_chunk(cx)
+    }
+}
+
+struct ReaderStream<R> {
+    reader: R,
+    remaining: Option<u64>,
+    buf: Vec<u8>,
+}
+
+impl<R: AsyncRead + Send + Unpin> BodyStream for ReaderStream<R> {
+    fn poll_chunk(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<io::Result<Bytes>>> {
+        let this = self.get_mut();
+        let limit = match this.remaining {
+            Some(0) => return Poll::Ready(None),
+            Some(remaining) => remaining.min(READ_CHUNK_SIZE as u64) as usize,
+            None => READ_CHUNK_SIZE,
+        };
+
+        // The buffer is kept across `Pending` polls and handed out as the chunk
+        // once the read completes.
+        if this.buf.len() != limit {
+            this.buf = vec![0_u8; limit];
+        }
+        let mut read_buf = ReadBuf::new(&mut this.buf);
+        match Pin::new(&mut this.reader).poll_read(cx, &mut read_buf) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(Err(e)) => Poll::Ready(Some(Err(e))),
+            Poll::Ready(Ok(())) => {
+                let len = read_buf.filled().len();
+                if len == 0 {
+                    // A reader that ends before the declared length is an error.
+                    let truncated = this
+                        .remaining
+                        .map(|_| Err(io::ErrorKind::UnexpectedEof.into()));
+                    return Poll::Ready(truncated);
+                }
+                if let Some(ref mut remaining) = this.remaining {
+                    *remaining -= len as u64;
+                }
+                let mut chunk = std::mem::take(&mut this.buf);
+                chunk.truncate(len);
+                Poll::Ready(Some(Ok(chunk.into())))
+            }
+        }
+    }
+}
+
+struct ChannelStream {
+    rx: mpsc::Receiver<io::Result<Bytes>>,
+}
+
+impl BodyStream for ChannelStream {
+    fn poll_chunk(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<io::Result<Bytes>>> {
+        self.get_mut().rx.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Body;
+
+    #[tokio::test]
+    async fn write_with_known_length() {
+        let body = Body::from_reader(&b"hello world"[..], Some(5));
+        assert_eq!(body.size_hint(), Some(5));
+        let mut out = Vec::new();
+        body.write_to(&mut out, false).await.unwrap();
+        assert_eq!(out, b"hello");
+    }
+
+    #[tokio::test]
+    async fn write_chunked_from_channel() {
+        let (tx, body) = Body::channel(4);
+        assert_eq!(body.size_hint(), None);
+        tokio::spawn(async move {
+            tx.send_data("hello ".into()).await.unwrap();
+            tx.send_data("world".into()).await.unwrap();
+        });
+        let mut out = Vec::new();
+        body.write_to(&mut out, true).await.unwrap();
+        assert_eq!(out, b"6\r\nhello \r\n5\r\nworld\r\n0\r\n\r\n");
+    }
+
+    #[tokio::test]
+    async fn short_reader_is_an_error() {
+        let body = Body::from_reader(&b"abc"[..], Some(5));
+        let mut out = Vec::new();
+        assert!(body.write_to(&mut out, false).await.is_err());
+    }
+}
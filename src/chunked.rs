@@ -0,0 +1,295 @@
+use thiserror::Error;
+
+use crate::header::HeaderMap;
+use crate::help::parse_header_line;
+
+/// Longest chunk-size line (size plus extensions) that is accepted.
+const MAX_CHUNK_LINE: usize = 4096;
+/// Upper bound on the size of the whole trailer section.
+const MAX_TRAILER_SIZE: usize = 16 * 1024;
+
+/// The zero-size chunk and empty trailer section that end a chunked body.
+pub const LAST_CHUNK: &[u8] = b"0\r\n\r\n";
+
+/// Formats the `chunk-size CRLF` line that starts a chunk of `len` bytes.
+pub fn chunk_size_line(len: usize) -> String {
+    format!("{:x}\r\n", len)
+}
+
+/// Errors raised while decoding a chunked body.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ChunkedError {
+    #[error("invalid chunk size line")]
+    InvalidSize,
+    #[error("chunk size line is too long")]
+    LineTooLong,
+    #[error("chunk data is not followed by CRLF")]
+    MissingCrlf,
+    #[error("invalid trailer field")]
+    InvalidTrailer,
+    #[error("trailer section is too large")]
+    TrailersTooLarge,
+    #[error("body is larger than {0} bytes")]
+    TooLarge(usize),
+}
+
+/// Incremental decoder for `Transfer-Encoding: chunked` message bodies
+/// (RFC 9112, section 7.1).
+///
+/// The decoder is fed whatever bytes have arrived so far. It consumes as much
+/// as it can and keeps its position, so the caller only has to hold on to the
+/// bytes that were not consumed and call [`ChunkedDecoder::decode`] again once
+/// more data is available. Chunk extensions are skipped; trailer fields are
+/// collected and handed out by [`ChunkedDecoder::into_trailers`].
+#[derive(Debug)]
+pub struct ChunkedDecoder {
+    state: State,
+    max_body_size: usize,
+    decoded: usize,
+    trailers: HeaderMap,
+    trailer_size: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Size,
+    Data(u64),
+    DataEnd,
+    Trailers,
+    Done,
+}
+
+impl ChunkedDecoder {
+    pub fn new(max_body_size: usize) -> Self {
+        ChunkedDecoder {
+            state: State::Size,
+            max_body_size,
+            decoded: 0,
+            trailers: HeaderMap::new(),
+            trailer_size: 0,
+        }
+    }
+
+    /// Decodes chunk data from `src` into `dst` and returns how many bytes of
+    /// `src` were consumed.
+    pub fn decode(&mut self, src: &[u8], dst: &mut Vec<u8>) -> Result<usize, ChunkedError> {
+        let mut pos = 0;
+        loop {
+            let rest = &src[pos..];
+            match self.state {
+                State::Size => {
+                    let Some(line) = take_line(
+                        rest,
+                        MAX_CHUNK_LINE,
+                        ChunkedError::InvalidSize,
+                        ChunkedError::LineTooLong,
+                    )?
+                    else {
+                        return Ok(pos);
+                    };
+                    pos += line.len() + 2;
+                    let size = parse_chunk_size(line)?;
+                    if size == 0 {
+                        self.state = State::Trailers;
+                    } else {
+                        if self.decoded as u64 + size > self.max_body_size as u64 {
+                            return Err(ChunkedError::TooLarge(self.max_body_size));
+                        }
+                        self.state = State::Data(size);
+                    }
+                }
+                State::Data(remaining) => {
+                    if rest.is_empty() {
+                        return Ok(pos);
+                    }
+                    let take = remaining.min(rest.len() as u64) as usize;
+                    dst.extend_from_slice(&rest[..take]);
+                    self.decoded += take;
+                    pos += take;
+                    let remaining = remaining - take as u64;
+                    self.state = if remaining == 0 {
+                        State::DataEnd
+                    } else {
+                        State::Data(remaining)
+                    };
+                }
+                State::DataEnd => {
+                    if rest.len() < 2 {
+                        if rest.first().is_some_and(|&b| b != b'\r') {
+                            return Err(ChunkedError::MissingCrlf);
+                        }
+                        return Ok(pos);
+                    }
+                    if &rest[..2] != b"\r\n" {
+                        return Err(ChunkedError::MissingCrlf);
+                    }
+                    pos += 2;
+                    self.state = State::Size;
+                }
+                State::Trailers => {
+                    let limit = MAX_TRAILER_SIZE - self.trailer_size;
+                    let Some(line) = take_line(
+                        rest,
+                        limit,
+                        ChunkedError::InvalidTrailer,
+                        ChunkedError::TrailersTooLarge,
+                    )?
+                    else {
+                        return Ok(pos);
+                    };
+                    pos += line.len() + 2;
+                    self.trailer_size += line.len() + 2;
+                    if line.is_empty() {
+                        self.state = State::Done;
+                    } else {
+                        let (name, value) =
+                            parse_header_line(line).map_err(|_| ChunkedError::InvalidTrailer)?;
+                        self.trailers.append(name, value);
+                    }
+                }
+                State::Done => return Ok(pos),
+            }
+        }
+    }
+
+    /// Whether the last chunk and the trailer section have been read.
+    #[inline]
+    pub fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+
+    /// Trailer fields sent after the last chunk.
+    pub fn into_trailers(self) -> HeaderMap {
+        self.trailers
+    }
+}
+
+/// Returns the next CRLF terminated line without its CRLF, or `None` if the
+/// line is not complete yet. Bare LF is rejected with `malformed`.
+fn take_line(
+    src: &[u8],
+    limit: usize,
+    malformed: ChunkedError,
+    too_long: ChunkedError,
+) -> Result<Option<&[u8]>, ChunkedError> {
+    match src.iter().position(|&b| b == b'\n') {
+        Some(0) => Err(malformed),
+        Some(lf) if src[lf - 1] != b'\r' => Err(malformed),
+        Some(lf) if lf + 1 > limit => Err(too_long),
+        Some(lf) => Ok(Some(&src[..lf - 1])),
+        None if src.len() >= limit => Err(too_long),
+        None => Ok(None),
+    }
+}
+
+/// Parses `chunk-size [ BWS chunk-ext ]`.
+fn parse_chunk_size(line: &[u8]) -> Result<u64, ChunkedError> {
+    let digits = line.iter().take_while(|b| b.is_ascii_hexdigit()).count();
+    if digits == 0 || digits > 16 {
+        return Err(ChunkedError::InvalidSize);
+    }
+
+    let ext = &line[digits..];
+    let ext_start = ext
+        .iter()
+        .position(|&b| b != b' ' && b != b'\t')
+        .unwrap_or(ext.len());
+    match ext.get(ext_start) {
+        None => {}
+        // Extensions are ignored, but they must not smuggle control bytes.
+        Some(b';') if !ext.iter().any(|&b| b < 0x20 && b != b'\t' || b == 0x7f) => {}
+        Some(_) => return Err(ChunkedError::InvalidSize),
+    }
+
+    let digits = std::str::from_utf8(&line[..digits]).map_err(|_| ChunkedError::InvalidSize)?;
+    u64::from_str_radix(digits, 16).map_err(|_| ChunkedError::InvalidSize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChunkedDecoder, ChunkedError};
+
+    fn decode_all(input: &[u8]) -> Result<(Vec<u8>, ChunkedDecoder), ChunkedError> {
+        let mut decoder = ChunkedDecoder::new(1024);
+        let mut body = Vec::new();
+        let consumed = decoder.decode(input, &mut body)?;
+        assert_eq!(consumed, input.len());
+        assert!(decoder.is_done());
+        Ok((body, decoder))
+    }
+
+    #[test]
+    fn decode_simple_body() {
+        let (body, _) = decode_all(b"4\r\nWiki\r\n5\r\npedia\r\n0\r\n\r\n").unwrap();
+        assert_eq!(body, b"Wikipedia");
+    }
+
+    #[test]
+    fn decode_extensions_and_trailers() {
+        let input = b"4;name=\"value\"\r\nWiki\r\nE ; last\r\n in\r\n\r\nchunks.\r\n0\r\nExpires: never\r\nX-Sum: 1\r\n\r\n";
+        let (body, decoder) = decode_all(input).unwrap();
+        assert_eq!(body, b"Wiki in\r\n\r\nchunks.");
+        let trailers = decoder.into_trailers();
+        assert_eq!(trailers.get("expires").unwrap(), "never");
+        assert_eq!(trailers.get("x-sum").unwrap(), "1");
+    }
+
+    #[test]
+    fn decode_one_byte_at_a_time() {
+        let input = b"3\r\nfoo\r\n3;x=1\r\nbar\r\n0\r\nX-Trailer: yes\r\n\r\n";
+        let mut decoder = ChunkedDecoder::new(1024);
+        let mut body = Vec::new();
+        let mut pending = Vec::new();
+        for &b in input.iter() {
+            pending.push(b);
+            let consumed = decoder.decode(&pending, &mut body).unwrap();
+            pending.drain(..consumed);
+        }
+        assert!(decoder.is_done());
+        assert!(pending.is_empty());
+        assert_eq!(body, b"foobar");
+        assert_eq!(decoder.into_trailers().get("x-trailer").unwrap(), "yes");
+    }
+
+    #[test]
+    fn stops_after_last_chunk() {
+        let mut decoder = ChunkedDecoder::new(1024);
+        let mut body = Vec::new();
+        let input = b"1\r\na\r\n0\r\n\r\nGET / HTTP/1.1\r\n";
+        assert_eq!(decoder.decode(input, &mut body).unwrap(), 11);
+        assert!(decoder.is_done());
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        assert_eq!(decode_all(b"x\r\n").unwrap_err(), ChunkedError::InvalidSize);
+        assert_eq!(
+            decode_all(b"3\nfoo").unwrap_err(),
+            ChunkedError::InvalidSize
+        );
+        assert_eq!(
+            decode_all(b"3\r\nfooX\r\n").unwrap_err(),
+            ChunkedError::MissingCrlf
+        );
+        assert_eq!(
+            decode_all(b"fffffffffffffffff\r\n").unwrap_err(),
+            ChunkedError::InvalidSize
+        );
+        assert_eq!(
+            decode_all(b"401\r\n").unwrap_err(),
+            ChunkedError::TooLarge(1024)
+        );
+        assert_eq!(
+            decode_all(b"0\r\nbad trailer\r\n\r\n").unwrap_err(),
+            ChunkedError::InvalidTrailer
+        );
+        assert_eq!(
+            decode_all(b"0\r\nX-Sum: 1\n\r\n").unwrap_err(),
+            ChunkedError::InvalidTrailer
+        );
+        assert_eq!(
+            decode_all(b"0\r\n\n").unwrap_err(),
+            ChunkedError::InvalidTrailer
+        );
+    }
+}
@@ -0,0 +1,366 @@
+//! Command-line options. Every option can also be set from the environment;
+//! a flag on the command line wins over its variable, and both win over the
+//! config file.
+
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser};
+use http_server_starter_rust::config::{self, FileConfig};
+use http_server_starter_rust::header::HeaderValue;
+use http_server_starter_rust::server::{self, Config};
+use http_server_starter_rust::tls::{CertificateFiles, ClientAuth, TlsSettings};
+
+#[derive(Debug, Parser)]
+#[command(version, about = "A small HTTP/1.1 and HTTP/2 server")]
+pub struct Args {
+    /// Config file with listeners, virtual hosts and per-location settings.
+    #[arg(short, long, value_name = "FILE", env = "HTTP_SERVER_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Address to listen on, such as `0.0.0.0:4221` or `[::1]:4221`. Repeat
+    /// the flag, or separate addresses with commas, to listen on several.
+    #[arg(
+        short,
+        long,
+        value_name = "ADDR",
+        env = "HTTP_SERVER_LISTEN",
+        value_delimiter = ',',
+        default_value = server::DEFAULT_ADDR
+    )]
+    pub listen: Vec<SocketAddr>,
+
+    /// Address to listen on for HTTPS. Repeat the flag, or separate
+    /// addresses with commas, to listen on several.
+    #[arg(
+        long,
+        value_name = "ADDR",
+        env = "HTTP_SERVER_TLS_LISTEN",
+        value_delimiter = ','
+    )]
+    pub tls_listen: Vec<SocketAddr>,
+
+    /// Certificate chain for HTTPS, as PEM. Replaces the `[tls]` table of the
+    /// config file.
+    #[arg(
+        long,
+        value_name = "FILE",
+        env = "HTTP_SERVER_TLS_CERT",
+        requires = "tls_key"
+    )]
+    pub tls_cert: Option<PathBuf>,
+
+    /// Private key of `--tls-cert`, as PEM.
+    #[arg(
+        long,
+        value_name = "FILE",
+        env = "HTTP_SERVER_TLS_KEY",
+        requires = "tls_cert"
+    )]
+    pub tls_key: Option<PathBuf>,
+
+    /// CAs, as PEM, that HTTPS clients must present a certificate from.
+    #[arg(
+        long,
+        value_name = "FILE",
+        env = "HTTP_SERVER_TLS_CLIENT_CA",
+        requires = "tls_cert"
+    )]
+    pub tls_client_ca: Option<PathBuf>,
+
+    /// Directory served under `/files`. Without it there are no `/files`
+    /// routes.
+    #[arg(
+        short,
+        long,
+        value_name = "DIR",
+        env = "HTTP_SERVER_DIRECTORY",
+        value_parser = directory
+    )]
+    pub directory: Option<PathBuf>,
+
+    /// `Cache-Control` value sent with the files under `/files`, such as
+    /// `max-age=3600`.
+    #[arg(
+        long,
+        value_name = "VALUE",
+        env = "HTTP_SERVER_CACHE_CONTROL",
+        value_parser = header_value
+    )]
+    pub cache_control: Option<HeaderValue>,
+
+    /// Largest request header section, in bytes. Accepts K, M and G suffixes.
+    #[arg(
+        long,
+        value_name = "SIZE",
+        env = "HTTP_SERVER_MAX_HEADER_SIZE",
+        value_parser = header_size,
+        default_value_t = server::DEFAULT_MAX_HEADER_SIZE
+    )]
+    pub max_header_size: usize,
+
+    /// Largest request body, in bytes, after decompression. Accepts K, M and
+    /// G suffixes.
+    #[arg(
+        long,
+        value_name = "SIZE",
+        env = "HTTP_SERVER_MAX_BODY_SIZE",
+        value_parser = config::parse_size,
+        default_value_t = server::DEFAULT_MAX_BODY_SIZE
+    )]
+    pub max_body_size: usize,
+
+    /// Seconds a connection may stay silent before it is closed.
+    #[arg(
+        long,
+        value_name = "SECS",
+        env = "HTTP_SERVER_IDLE_TIMEOUT",
+        value_parser = seconds,
+        default_value_t = server::DEFAULT_IDLE_TIMEOUT.as_secs()
+    )]
+    pub idle_timeout: u64,
+
+    /// Seconds a client may take to send a request head once it started it.
+    #[arg(
+        long,
+        value_name = "SECS",
+        env = "HTTP_SERVER_HEADER_TIMEOUT",
+        value_parser = seconds,
+        default_value_t = server::DEFAULT_HEADER_TIMEOUT.as_secs()
+    )]
+    pub header_timeout: u64,
+
+    /// Seconds a shutdown waits for requests in flight before it drops the
+    /// connections still open.
+    #[arg(
+        long,
+        value_name = "SECS",
+        env = "HTTP_SERVER_SHUTDOWN_TIMEOUT",
+        value_parser = seconds,
+        default_value_t = server::DEFAULT_SHUTDOWN_TIMEOUT.as_secs()
+    )]
+    pub shutdown_timeout: u64,
+
+    /// Worker threads. Defaults to the number of CPUs.
+    #[arg(long, value_name = "N", env = "HTTP_SERVER_WORKERS")]
+    pub workers: Option<NonZeroUsize>,
+
+    /// Most connections served at the same time.
+    #[arg(
+        long,
+        value_name = "N",
+        env = "HTTP_SERVER_MAX_CONNECTIONS",
+        default_value_t = NonZeroUsize::new(server::DEFAULT_MAX_CONNECTIONS).unwrap()
+    )]
+    pub max_connections: NonZeroUsize,
+
+    /// The options given on the command line or in the environment, rather
+    /// than left at their defaults.
+    #[arg(skip)]
+    given: HashSet<String>,
+}
+
+impl Args {
+    /// Parses the command line, exiting with a usage message if it is
+    /// invalid.
+    pub fn parse() -> Args {
+        Args::from_matches(&Args::command().get_matches()).unwrap_or_else(|e| e.exit())
+    }
+
+    fn from_matches(matches: &ArgMatches) -> Result<Args, clap::Error> {
+        let mut args = Args::from_arg_matches(matches)?;
+        args.given = matches
+            .ids()
+            .filter(|id| {
+                matches!(
+                    matches.value_source(id.as_str()),
+                    Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+                )
+            })
+            .map(|id| id.to_string())
+            .collect();
+        Ok(args)
+    }
+
+    /// The server settings these options describe, on top of the settings in
+    /// `file`.
+    pub fn server_config(&self, file: Option<&FileConfig>) -> Config {
+        let mut config = Config {
+            listen: self.listen.clone(),
+            tls_listen: self.tls_listen.clone(),
+            max_connections: self.max_connections.get(),
+            idle_timeout: Duration::from_secs(self.idle_timeout),
+            header_timeout: Duration::from_secs(self.header_timeout),
+            shutdown_timeout: Duration::from_secs(self.shutdown_timeout),
+            max_header_size: self.max_header_size,
+            max_body_size: self.max_body_size,
+            ..Config::default()
+        };
+        if let Some(file) = file {
+            file.server.apply(&mut config);
+            if !file.listeners.is_empty() || !file.tls_listeners.is_empty() {
+                config.listen = file.listeners.clone();
+                config.tls_listen = file.tls_listeners.clone();
+            }
+        }
+
+        // Options that were given beat the file.
+        let given = |id: &str| self.given.contains(id);
+        if given("listen") {
+            config.listen = self.listen.clone();
+        }
+        if given("tls_listen") {
+            config.tls_listen = self.tls_listen.clone();
+        }
+        if given("max_connections") {
+            config.max_connections = self.max_connections.get();
+        }
+        if given("idle_timeout") {
+            config.idle_timeout = Duration::from_secs(self.idle_timeout);
+        }
+        if given("header_timeout") {
+            config.header_timeout = Duration::from_secs(self.header_timeout);
+        }
+        if given("shutdown_timeout") {
+            config.shutdown_timeout = Duration::from_secs(self.shutdown_timeout);
+        }
+        if given("max_header_size") {
+            config.max_header_size = self.max_header_size;
+        }
+        if given("max_body_size") {
+            config.max_body_size = self.max_body_size;
+        }
+        if let Some(workers) = self.workers {
+            config.workers = workers.get();
+        }
+        config
+    }
+
+    /// The certificates for HTTPS: those given as options, or else the
+    /// `[tls]` table of `file`.
+    pub fn tls_settings(&self, file: Option<&FileConfig>) -> Option<TlsSettings> {
+        let (Some(cert), Some(key)) = (&self.tls_cert, &self.tls_key) else {
+            return file.and_then(|file| file.tls.clone());
+        };
+        Some(TlsSettings {
+            certificates: vec![CertificateFiles {
+                names: Vec::new(),
+                cert: cert.clone(),
+                key: key.clone(),
+            }],
+            client_auth: match &self.tls_client_ca {
+                Some(ca) => ClientAuth::Required(ca.clone()),
+                None => ClientAuth::Off,
+            },
+            ..TlsSettings::default()
+        })
+    }
+}
+
+/// An existing directory.
+fn directory(value: &str) -> Result<PathBuf, String> {
+    let path = PathBuf::from(value);
+    match std::fs::metadata(&path) {
+        Ok(meta) if meta.is_dir() => Ok(path),
+        Ok(_) => Err("not a directory".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// A valid header field value.
+fn header_value(value: &str) -> Result<HeaderValue, String> {
+    HeaderValue::from_str(value).map_err(|e| e.to_string())
+}
+
+/// A header limit large enough for an ordinary request.
+fn header_size(value: &str) -> Result<usize, String> {
+    let n = config::parse_size(value)?;
+    if n < config::MIN_HEADER_SIZE {
+        return Err(format!(
+            "must be at least {} bytes",
+            config::MIN_HEADER_SIZE
+        ));
+    }
+    Ok(n)
+}
+
+/// A positive number of seconds.
+fn seconds(value: &str) -> Result<u64, String> {
+    match value.trim().parse() {
+        Ok(0) => Err("must be at least 1 second".to_string()),
+        Ok(secs) => Ok(secs),
+        Err(_) => Err(format!("expected a number of seconds, got {:?}", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Args;
+    use clap::{CommandFactory, Parser};
+    use http_server_starter_rust::config::{FileConfig, ServerSettings};
+    use std::time::Duration;
+
+    #[test]
+    fn parse_and_validate_args() {
+        let args = Args::try_parse_from([
+            "server",
+            "--listen",
+            "0.0.0.0:80,[::1]:8080",
+            "--max-body-size",
+            "1M",
+            "--workers",
+            "2",
+            "--shutdown-timeout",
+            "5",
+        ])
+        .unwrap();
+        let config = args.server_config(None);
+        assert_eq!(config.listen.len(), 2);
+        assert!(config.listen[1].is_ipv6());
+        assert_eq!(config.max_body_size, 1 << 20);
+        assert_eq!(config.workers, 2);
+        assert_eq!(config.shutdown_timeout, Duration::from_secs(5));
+
+        let args = Args::try_parse_from(["server", "--cache-control", "max-age=60"]).unwrap();
+        assert_eq!(args.cache_control.unwrap(), "max-age=60");
+
+        for bad in [
+            &["server", "--listen", "localhost"][..],
+            &["server", "--workers", "0"],
+            &["server", "--idle-timeout", "0"],
+            &["server", "--max-header-size", "10"],
+            &["server", "--directory", "/no/such/dir"],
+            &["server", "--directory", "Cargo.toml"],
+            &["server", "--cache-control", "max-age=60\n"],
+            &["server", "--unknown"],
+        ] {
+            assert!(Args::try_parse_from(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn given_options_win_over_the_file() {
+        let file = FileConfig {
+            server: ServerSettings {
+                idle_timeout: Some(Duration::from_secs(5)),
+                max_body_size: Some(10),
+                ..ServerSettings::default()
+            },
+            listeners: vec!["[::]:80".parse().unwrap()],
+            ..FileConfig::default()
+        };
+        let matches = Args::command()
+            .try_get_matches_from(["server", "--idle-timeout", "7"])
+            .unwrap();
+        let config = Args::from_matches(&matches)
+            .unwrap()
+            .server_config(Some(&file));
+        assert_eq!(config.idle_timeout, Duration::from_secs(7));
+        assert_eq!(config.max_body_size, 10);
+        assert_eq!(config.listen, file.listeners);
+    }
+}
@@ -0,0 +1,525 @@
+//! Response compression negotiated from `Accept-Encoding`.
+
+use std::io::{self, Write};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use anyhow::Result;
+use bytes::Bytes;
+use flate2::write::{GzEncoder, ZlibEncoder};
+
+use crate::body::{Body, BodyStream};
+use crate::conditional::EntityTag;
+use crate::handler::{BoxFuture, BoxHandler, Handler};
+use crate::header::{
+    HeaderMap, HeaderValue, ACCEPT_ENCODING, ACCEPT_RANGES, CONTENT_ENCODING, CONTENT_LENGTH,
+    CONTENT_TYPE, ETAG, VARY,
+};
+use crate::help::tokens;
+use crate::request::Request;
+use crate::response::Response;
+use crate::status::StatusCode;
+
+/// Bodies shorter than this are sent as they are: compressing them saves
+/// little or even makes them longer.
+pub const DEFAULT_MIN_SIZE: u64 = 256;
+
+/// Brotli quality used for responses. The higher levels are too slow to run
+/// on every request.
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW: u32 = 22;
+
+/// Compresses the responses of another handler.
+///
+/// The coding is picked from the request's `Accept-Encoding`, preferring
+/// `br`, then `gzip`, then `deflate` among those with the highest q-value.
+/// Bodies that are already compressed, too small, or partial are left
+/// alone, and a compressed response no longer offers `Accept-Ranges`, since
+/// ranges are served from the unencoded body. Streamed bodies are
+/// compressed as they are sent, and each chunk is flushed so that slow
+/// streams still reach the client promptly.
+///
+/// ```ignore
+/// server::serve(config, Compression::new(router)).await
+/// ```
+pub struct Compression<H = BoxHandler> {
+    inner: Arc<H>,
+    min_size: u64,
+}
+
+/// A content coding the server can produce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Coding {
+    Brotli,
+    Gzip,
+    Deflate,
+}
+
+impl<H: Handler> Compression<H> {
+    pub fn new(inner: H) -> Compression<H> {
+        Compression {
+            inner: Arc::new(inner),
+            min_size: DEFAULT_MIN_SIZE,
+        }
+    }
+
+    /// Sets the smallest body, in bytes, that is compressed. Bodies of
+    /// unknown length are always compressed.
+    pub fn min_size(mut self, min_size: u64) -> Compression<H> {
+        self.min_size = min_size;
+        self
+    }
+}
+
+impl<H: Handler> Handler for Compression<H> {
+    fn call(&self, req: Request) -> BoxFuture<'static, Result<Response>> {
+        let coding = negotiate(req.headers());
+        let inner = self.inner.call(req);
+        let min_size = self.min_size;
+        Box::pin(async move {
+            let res = inner.await?;
+            compress(res, coding, min_size)
+        })
+    }
+}
+
+impl Coding {
+    /// The token used in `Accept-Encoding` and `Content-Encoding`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Coding::Brotli => "br",
+            Coding::Gzip => "gzip",
+            Coding::Deflate => "deflate",
+        }
+    }
+
+    /// Codings in the order the server prefers them.
+    const ALL: [Coding; 3] = [Coding::Brotli, Coding::Gzip, Coding::Deflate];
+
+    fn encoder(self) -> Encoder {
+        match self {
+            Coding::Brotli => Encoder::Brotli(Box::new(brotli::CompressorWriter::new(
+                Vec::new(),
+                0,
+                BROTLI_QUALITY,
+                BROTLI_WINDOW,
+            ))),
+            Coding::Gzip => {
+                Encoder::Gzip(GzEncoder::new(Vec::new(), flate2::Compression::default()))
+            }
+            Coding::Deflate => {
+                Encoder::Deflate(ZlibEncoder::new(Vec::new(), flate2::Compression::default()))
+            }
+        }
+    }
+}
+
+/// Picks a coding from `Accept-Encoding`, or `None` to send the body as it
+/// is. Without the header no coding is used.
+pub fn negotiate(headers: &HeaderMap) -> Option<Coding> {
+    let mut any = None;
+    let mut weights = [None; Coding::ALL.len()];
+    for item in tokens(headers, ACCEPT_ENCODING) {
+        let mut parts = item.split(|&b| b == b';');
+        let name = trim(parts.next().unwrap_or_default());
+        let mut q = Some(1000);
+        for param in parts {
+            let param = trim(param);
+            if param.len() > 2 && param[..2].eq_ignore_ascii_case(b"q=") {
+                q = qvalue(&param[2..]);
+            }
+        }
+        // An element with a malformed weight is ignored.
+        let Some(q) = q else { continue };
+
+        if name == b"*" {
+            any = Some(q);
+        } else if let Some(i) = Coding::ALL
+            .iter()
+            .position(|coding| name.eq_ignore_ascii_case(coding.as_str().as_bytes()))
+        {
+            weights[i] = Some(q);
+        } else if name.eq_ignore_ascii_case(b"x-gzip") {
+            weights[1] = weights[1].or(Some(q));
+        }
+    }
+
+    let mut best: Option<(Coding, u16)> = None;
+    for (coding, weight) in Coding::ALL.into_iter().zip(weights) {
+        let q = match weight.or(any) {
+            Some(q) if q > 0 => q,
+            _ => continue,
+        };
+        if !matches!(best, Some((_, best)) if best >= q) {
+            best = Some((coding, q));
+        }
+    }
+    best.map(|(coding, _)| coding)
+}
+
+/// Parses a weight into thousandths: `0.5` is 500.
+fn qvalue(value: &[u8]) -> Option<u16> {
+    match value {
+        [b'0'] => Some(0),
+        [b'1'] => Some(1000),
+        [b'1', b'.', rest @ ..] if rest.len() <= 3 && rest.iter().all(|&b| b == b'0') => Some(1000),
+        [b'0', b'.', rest @ ..] if rest.len() <= 3 && rest.iter().all(u8::is_ascii_digit) => {
+            let mut q = 0;
+            for i in 0..3 {
+                q = q * 10 + rest.get(i).map_or(0, |&b| u16::from(b - b'0'));
+            }
+            Some(q)
+        }
+        _ => None,
+    }
+}
+
+fn trim(value: &[u8]) -> &[u8] {
+    let is_ows = |b: &u8| *b == b' ' || *b == b'\t';
+    let start = value.iter().position(|b| !is_ows(b)).unwrap_or(value.len());
+    let end = value
+        .iter()
+        .rposition(|b| !is_ows(b))
+        .map_or(start, |i| i + 1);
+    &value[start..end]
+}
+
+/// Compresses the body of `res` with `coding` if it is worth it.
+fn compress(mut res: Response, coding: Option<Coding>, min_size: u64) -> Result<Response> {
+    // A 304 stands in for the response it validates, which may have been
+    // compressed, so it has to vary the same way.
+    if res.status() == StatusCode::NOT_MODIFIED {
+        if has_compressible_type(&res) {
+            add_vary(res.headers_mut());
+        }
+        return Ok(res);
+    }
+    if !is_compressible(&res) {
+        return Ok(res);
+    }
+    add_vary(res.headers_mut());
+
+    let Some(coding) = coding else {
+        return Ok(res);
+    };
+    if matches!(res.body().size_hint(), Some(len) if len < min_size) {
+        return Ok(res);
+    }
+
+    // The compressed body is a different representation, so a strong
+    // validator no longer describes it byte for byte.
+    if let Some(etag) = res.headers().get(ETAG).and_then(|v| v.to_str().ok()) {
+        if let Some(etag) = EntityTag::parse(etag).filter(|etag| !etag.is_weak()) {
+            let weak = EntityTag::weak(etag.tag());
+            res.headers_mut().insert(ETAG, weak.to_header_value());
+        }
+    }
+    res.headers_mut()
+        .insert(CONTENT_ENCODING, HeaderValue::from_static(coding.as_str()));
+    res.headers_mut().remove(CONTENT_LENGTH);
+    // Ranges are taken from the unencoded body, so they cannot be used to
+    // resume a compressed one.
+    res.headers_mut().remove(ACCEPT_RANGES);
+
+    let body = std::mem::take(res.body_mut());
+    *res.body_mut() = match body.try_into_bytes() {
+        Ok(bytes) => {
+            let mut encoder = coding.encoder();
+            encoder.write_all(&bytes)?;
+            Body::from(encoder.finish()?)
+        }
+        Err(body) => Body::from_stream(
+            Encode {
+                body,
+                encoder: Some(coding.encoder()),
+            },
+            None,
+        ),
+    };
+    Ok(res)
+}
+
+/// Whether `res` carries a whole body that could be compressed.
+fn is_compressible(res: &Response) -> bool {
+    if res.status() == StatusCode::PARTIAL_CONTENT
+        || res.status() == StatusCode::NO_CONTENT
+        || res.status() == StatusCode::NOT_MODIFIED
+        || res.status().is_informational()
+        || res.headers().contains_key(CONTENT_ENCODING)
+        || res.body().size_hint() == Some(0)
+    {
+        return false;
+    }
+    has_compressible_type(res)
+}
+
+/// Whether the `Content-Type` of `res`, if any, is worth compressing.
+fn has_compressible_type(res: &Response) -> bool {
+    let content_type = match res.headers().get(CONTENT_TYPE) {
+        Some(value) => value.as_bytes(),
+        None => return true,
+    };
+    let essence = content_type
+        .split(|&b| b == b';')
+        .next()
+        .map(trim)
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    !is_compressed_type(&essence)
+}
+
+/// Media types whose data is already compressed. Arbitrary binary data is
+/// counted with them, since it mostly is an archive of some kind.
+fn is_compressed_type(essence: &[u8]) -> bool {
+    if essence == b"image/svg+xml" {
+        return false;
+    }
+    essence.starts_with(b"image/")
+        || essence.starts_with(b"audio/")
+        || essence.starts_with(b"video/")
+        || essence.starts_with(b"font/woff")
+        || essence.starts_with(b"multipart/")
+        || matches!(
+            essence,
+            b"application/gzip"
+                | b"application/zip"
+                | b"application/zstd"
+                | b"application/x-bzip2"
+                | b"application/x-xz"
+                | b"application/x-7z-compressed"
+                | b"application/pdf"
+                | b"application/wasm"
+                | b"application/octet-stream"
+        )
+}
+
+/// Adds `Accept-Encoding` to `Vary` unless it is already covered.
+fn add_vary(headers: &mut HeaderMap) {
+    let covered = tokens(headers, VARY)
+        .any(|token| token == b"*" || token.eq_ignore_ascii_case(b"accept-encoding"));
+    if !covered {
+        headers.append(VARY, HeaderValue::from_static("accept-encoding"));
+    }
+}
+
+enum Encoder {
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+    fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        match self {
+            Encoder::Brotli(w) => w.write_all(data),
+            Encoder::Gzip(w) => w.write_all(data),
+            Encoder::Deflate(w) => w.write_all(data),
+        }
+    }
+
+    /// Flushes what has been written so far and takes the output.
+    fn flush(&mut self) -> io::Result<Vec<u8>> {
+        let out = match self {
+            Encoder::Brotli(w) => {
+                w.flush()?;
+                w.get_mut()
+            }
+            Encoder::Gzip(w) => {
+                w.flush()?;
+                w.get_mut()
+            }
+            Encoder::Deflate(w) => {
+                w.flush()?;
+                w.get_mut()
+            }
+        };
+        Ok(std::mem::take(out))
+    }
+
+    /// Ends the stream and takes the remaining output.
+    fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Encoder::Brotli(w) => Ok(w.into_inner()),
+            Encoder::Gzip(w) => w.finish(),
+            Encoder::Deflate(w) => w.finish(),
+        }
+    }
+}
+
+/// Compresses a streamed body chunk by chunk.
+struct Encode {
+    body: Body,
+    encoder: Option<Encoder>,
+}
+
+impl BodyStream for Encode {
+    fn poll_chunk(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<io::Result<Bytes>>> {
+        let this = self.get_mut();
+        loop {
+            let Some(encoder) = this.encoder.as_mut() else {
+                return Poll::Ready(None);
+            };
+            let out = match Pin::new(&mut this.body).poll_chunk(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(Some(Ok(chunk))) => {
+                    encoder.write_all(&chunk).and_then(|()| encoder.flush())
+                }
+                Poll::Ready(None) => this.encoder.take().unwrap().finish(),
+            };
+            match out {
+                Ok(out) if out.is_empty() => continue,
+                Ok(out) => return Poll::Ready(Some(Ok(out.into()))),
+                Err(e) => return Poll::Ready(Some(Err(e))),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{negotiate, Coding, Compression};
+    use crate::body::Body;
+    use crate::handler::Handler;
+    use crate::header::{
+        HeaderMap, HeaderValue, ACCEPT_RANGES, CONTENT_ENCODING, CONTENT_TYPE, ETAG, VARY,
+    };
+    use crate::request::Request;
+    use crate::response::Response;
+    use crate::status::StatusCode;
+    use bytes::Bytes;
+    use std::io::Read;
+    use std::sync::Mutex;
+
+    fn accept(value: &'static str) -> Option<Coding> {
+        let mut headers = HeaderMap::new();
+        headers.insert("accept-encoding", value.parse().unwrap());
+        negotiate(&headers)
+    }
+
+    #[test]
+    fn negotiate_by_qvalue_and_preference() {
+        assert_eq!(negotiate(&HeaderMap::new()), None);
+        assert_eq!(accept("gzip, deflate, br"), Some(Coding::Brotli));
+        assert_eq!(accept("gzip;q=1.0, br;q=0.8"), Some(Coding::Gzip));
+        assert_eq!(accept("deflate, gzip;q=0.5"), Some(Coding::Deflate));
+        assert_eq!(accept("*;q=0.5, br;q=0"), Some(Coding::Gzip));
+        assert_eq!(accept("identity"), None);
+        assert_eq!(accept("gzip;q=0"), None);
+        assert_eq!(accept("gzip;q=2, deflate"), Some(Coding::Deflate));
+        assert_eq!(accept("x-gzip"), Some(Coding::Gzip));
+    }
+
+    async fn respond(accept: &'static str, content_type: &'static str, body: Body) -> Response {
+        let res = Response::builder()
+            .header(CONTENT_TYPE, content_type)
+            .header(ETAG, "\"v1\"")
+            .body(body)
+            .unwrap();
+        layer(accept, res).await
+    }
+
+    /// Passes `res` through the layer as the answer to a request accepting
+    /// `accept`.
+    async fn layer(accept: &'static str, res: Response) -> Response {
+        let res = Mutex::new(Some(res));
+        let handler = Compression::new(move |_req: Request| {
+            let res = res.lock().unwrap().take().unwrap();
+            async move { Ok(res) }
+        })
+        .min_size(16);
+        let mut req = Request::new(Bytes::new());
+        req.headers_mut()
+            .insert("accept-encoding", accept.parse().unwrap());
+        handler.call(req).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn compress_buffered_and_streamed_bodies() {
+        let text = "hello hello hello hello hello hello hello".repeat(10);
+
+        let res = respond("gzip", "text/plain", Body::from(text.clone())).await;
+        assert_eq!(res.headers().get(CONTENT_ENCODING).unwrap(), "gzip");
+        assert_eq!(res.headers().get(VARY).unwrap(), "accept-encoding");
+        assert_eq!(res.headers().get(ETAG).unwrap(), "W/\"v1\"");
+        assert!(res.body().size_hint().is_some());
+        let data = res.into_body().collect().await.unwrap();
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(&data[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, text);
+
+        let (tx, body) = Body::channel(4);
+        let res = respond("br", "text/plain", body).await;
+        assert_eq!(res.headers().get(CONTENT_ENCODING).unwrap(), "br");
+        assert_eq!(res.body().size_hint(), None);
+        let sent = text.clone();
+        tokio::spawn(async move {
+            for part in sent.as_bytes().chunks(100) {
+                tx.send_data(Bytes::copy_from_slice(part)).await.unwrap();
+            }
+        });
+        let data = res.into_body().collect().await.unwrap();
+        let mut decoded = String::new();
+        brotli::Decompressor::new(&data[..], 4096)
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, text);
+    }
+
+    #[tokio::test]
+    async fn skip_small_and_compressed_bodies() {
+        let res = respond("gzip", "text/plain", Body::from("tiny")).await;
+        assert!(res.headers().get(CONTENT_ENCODING).is_none());
+        assert_eq!(res.headers().get(VARY).unwrap(), "accept-encoding");
+
+        let png = vec![0_u8; 1024];
+        let res = respond("gzip", "image/png", Body::from(png)).await;
+        assert!(res.headers().get(CONTENT_ENCODING).is_none());
+        assert!(res.headers().get(VARY).is_none());
+
+        let data = vec![0_u8; 1024];
+        let res = respond("gzip", "application/octet-stream", Body::from(data)).await;
+        assert!(res.headers().get(CONTENT_ENCODING).is_none());
+    }
+
+    #[tokio::test]
+    async fn compressed_bodies_do_not_offer_ranges() {
+        let text = "hello ".repeat(100);
+        let ranged = |content_type| {
+            Response::builder()
+                .header(CONTENT_TYPE, content_type)
+                .header(ACCEPT_RANGES, "bytes")
+                .body(Body::from(text.clone()))
+                .unwrap()
+        };
+
+        let res = layer("gzip", ranged("text/plain")).await;
+        assert_eq!(res.headers().get(CONTENT_ENCODING).unwrap(), "gzip");
+        assert!(res.headers().get(ACCEPT_RANGES).is_none());
+
+        let res = layer("identity", ranged("text/plain")).await;
+        assert!(res.headers().get(CONTENT_ENCODING).is_none());
+        assert_eq!(res.headers().get(ACCEPT_RANGES).unwrap(), "bytes");
+
+        let res = layer("gzip", ranged("application/octet-stream")).await;
+        assert!(res.headers().get(CONTENT_ENCODING).is_none());
+        assert_eq!(res.headers().get(ACCEPT_RANGES).unwrap(), "bytes");
+    }
+
+    #[tokio::test]
+    async fn not_modified_varies_like_its_response() {
+        let res = layer("gzip", Response::with_status(StatusCode::NOT_MODIFIED)).await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert!(res.headers().get(CONTENT_ENCODING).is_none());
+        assert_eq!(res.headers().get(VARY).unwrap(), "accept-encoding");
+
+        let mut res = Response::with_status(StatusCode::NOT_MODIFIED);
+        res.headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static("image/png"));
+        let res = layer("gzip", res).await;
+        assert!(res.headers().get(VARY).is_none());
+    }
+}
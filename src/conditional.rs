@@ -0,0 +1,411 @@
+//! Validators and conditional requests, as described in RFC 9110 sections
+//! 8.8 and 13.
+
+use core::fmt;
+use std::fs::Metadata;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::header::{
+    HeaderMap, HeaderName, HeaderValue, ETAG, IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE,
+    IF_UNMODIFIED_SINCE, LAST_MODIFIED,
+};
+use crate::method::Method;
+
+/// An entity tag, the opaque validator sent in `ETag`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntityTag {
+    weak: bool,
+    tag: String,
+}
+
+/// The validators of the current representation of a resource.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Validators {
+    pub etag: Option<EntityTag>,
+    /// Modification time, truncated to whole seconds as it is sent.
+    pub last_modified: Option<SystemTime>,
+}
+
+/// The result of evaluating the preconditions of a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// Every precondition held: perform the method.
+    Proceed,
+    /// Answer `304 Not Modified`.
+    NotModified,
+    /// Answer `412 Precondition Failed`.
+    PreconditionFailed,
+}
+
+/// The parsed value of `If-Match` or `If-None-Match`.
+#[derive(Debug)]
+enum TagList {
+    Any,
+    Tags(Vec<EntityTag>),
+}
+
+impl EntityTag {
+    /// A strong tag. `tag` goes between the quotes and must not contain `"`.
+    pub fn strong(tag: impl Into<String>) -> EntityTag {
+        EntityTag::new(false, tag.into())
+    }
+
+    /// A weak tag, sent as `W/"tag"`.
+    pub fn weak(tag: impl Into<String>) -> EntityTag {
+        EntityTag::new(true, tag.into())
+    }
+
+    fn new(weak: bool, tag: String) -> EntityTag {
+        assert!(tag.bytes().all(is_etagc), "invalid entity tag: {:?}", tag);
+        EntityTag { weak, tag }
+    }
+
+    /// A strong tag derived from a file's size and modification time.
+    pub fn from_metadata(metadata: &Metadata) -> EntityTag {
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        EntityTag::strong(format!(
+            "{:x}-{:x}.{:x}",
+            metadata.len(),
+            modified.as_secs(),
+            modified.subsec_nanos()
+        ))
+    }
+
+    pub fn is_weak(&self) -> bool {
+        self.weak
+    }
+
+    /// The opaque tag, without quotes.
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    /// Both tags are strong and identical.
+    pub fn strong_eq(&self, other: &EntityTag) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+
+    /// The tags are identical, ignoring whether they are weak.
+    pub fn weak_eq(&self, other: &EntityTag) -> bool {
+        self.tag == other.tag
+    }
+
+    /// Parses a single entity tag such as `"abc"` or `W/"abc"`.
+    pub fn parse(src: &str) -> Option<EntityTag> {
+        match parse_tag(src.as_bytes()) {
+            Some((tag, [])) => Some(tag),
+            _ => None,
+        }
+    }
+
+    pub fn to_header_value(&self) -> HeaderValue {
+        HeaderValue::from_str(&self.to_string()).expect("entity tags are valid header values")
+    }
+}
+
+impl fmt::Display for EntityTag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.weak {
+            f.write_str("W/")?;
+        }
+        write!(f, "\"{}\"", self.tag)
+    }
+}
+
+impl Validators {
+    /// The `ETag` and `Last-Modified` of a file.
+    pub fn from_metadata(metadata: &Metadata) -> Validators {
+        Validators {
+            etag: Some(EntityTag::from_metadata(metadata)),
+            last_modified: metadata.modified().ok().map(truncate),
+        }
+    }
+
+    /// Sets `ETag` and `Last-Modified` on a response.
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        if let Some(etag) = &self.etag {
+            headers.insert(ETAG, etag.to_header_value());
+        }
+        if let Some(time) = self.last_modified {
+            let date = httpdate::fmt_http_date(time);
+            headers.insert(
+                LAST_MODIFIED,
+                HeaderValue::from_str(&date).expect("HTTP dates are valid header values"),
+            );
+        }
+    }
+}
+
+/// Evaluates `If-Match`, `If-Unmodified-Since`, `If-None-Match` and
+/// `If-Modified-Since` in the order of RFC 9110 section 13.2.2.
+///
+/// `current` holds the validators of the selected representation, or `None`
+/// if the resource has none yet.
+pub fn evaluate(method: &Method, headers: &HeaderMap, current: Option<&Validators>) -> Outcome {
+    let safe = *method == Method::GET || *method == Method::HEAD;
+    let etag = current.and_then(|v| v.etag.as_ref());
+    let last_modified = current.and_then(|v| v.last_modified);
+
+    if let Some(list) = tag_list(headers, IF_MATCH) {
+        let matched = match list {
+            TagList::Any => current.is_some(),
+            TagList::Tags(tags) => etag.is_some_and(|etag| tags.iter().any(|t| t.strong_eq(etag))),
+        };
+        if !matched {
+            return Outcome::PreconditionFailed;
+        }
+    } else if let (Some(since), Some(modified)) =
+        (date(headers, IF_UNMODIFIED_SINCE), last_modified)
+    {
+        if modified > since {
+            return Outcome::PreconditionFailed;
+        }
+    }
+
+    if let Some(list) = tag_list(headers, IF_NONE_MATCH) {
+        let matched = match list {
+            TagList::Any => current.is_some(),
+            TagList::Tags(tags) => etag.is_some_and(|etag| tags.iter().any(|t| t.weak_eq(etag))),
+        };
+        if matched {
+            return if safe {
+                Outcome::NotModified
+            } else {
+                Outcome::PreconditionFailed
+            };
+        }
+    } else if let (true, Some(since), Some(modified)) =
+        (safe, date(headers, IF_MODIFIED_SINCE), last_modified)
+    {
+        if modified <= since {
+            return Outcome::NotModified;
+        }
+    }
+
+    Outcome::Proceed
+}
+
+/// Evaluates `If-Range`: whether the `Range` header of a request may be
+/// honoured. An entity tag must match strongly and a date exactly.
+pub fn if_range(headers: &HeaderMap, current: &Validators) -> bool {
+    let Some(value) = headers.get(IF_RANGE) else {
+        return true;
+    };
+    let Ok(value) = value.to_str() else {
+        return false;
+    };
+    if let Some(tag) = EntityTag::parse(value) {
+        return current
+            .etag
+            .as_ref()
+            .is_some_and(|etag| etag.strong_eq(&tag));
+    }
+    match httpdate::parse_http_date(value) {
+        Ok(date) => current.last_modified == Some(date),
+        Err(_) => false,
+    }
+}
+
+/// Reads an HTTP date header. Invalid dates are ignored, as the RFC asks.
+pub(crate) fn date(headers: &HeaderMap, name: HeaderName) -> Option<SystemTime> {
+    let value = headers.get(name)?.to_str().ok()?;
+    httpdate::parse_http_date(value).ok()
+}
+
+/// Reads `If-Match` or `If-None-Match`, combining repeated fields. A list
+/// that does not parse matches nothing.
+fn tag_list(headers: &HeaderMap, name: HeaderName) -> Option<TagList> {
+    let mut values = headers.get_all(name).peekable();
+    values.peek()?;
+
+    let mut tags = Vec::new();
+    for value in values {
+        let mut rest = value.as_bytes();
+        loop {
+            rest = skip_separators(rest);
+            if rest.is_empty() {
+                break;
+            }
+            if rest[0] == b'*' {
+                return Some(TagList::Any);
+            }
+            match parse_tag(rest) {
+                Some((tag, next)) => {
+                    tags.push(tag);
+                    rest = next;
+                }
+                None => return Some(TagList::Tags(Vec::new())),
+            }
+        }
+    }
+    Some(TagList::Tags(tags))
+}
+
+/// Parses an entity tag at the start of `src`, returning it with the rest.
+fn parse_tag(src: &[u8]) -> Option<(EntityTag, &[u8])> {
+    let (weak, src) = match src.strip_prefix(b"W/") {
+        Some(rest) => (true, rest),
+        None => (false, src),
+    };
+    let src = src.strip_prefix(b"\"")?;
+    let end = src.iter().position(|&b| b == b'"')?;
+    let tag = &src[..end];
+    if !tag.iter().all(|&b| is_etagc(b)) {
+        return None;
+    }
+    let tag = EntityTag {
+        weak,
+        tag: String::from_utf8_lossy(tag).into_owned(),
+    };
+    Some((tag, &src[end + 1..]))
+}
+
+fn skip_separators(src: &[u8]) -> &[u8] {
+    let start = src
+        .iter()
+        .position(|b| !matches!(b, b' ' | b'\t' | b','))
+        .unwrap_or(src.len());
+    &src[start..]
+}
+
+/// `etagc`: visible ASCII other than `"`, or obs-text.
+fn is_etagc(b: u8) -> bool {
+    b == 0x21 || (0x23..=0x7e).contains(&b) || b >= 0x80
+}
+
+/// Drops the sub-second part of `time`, which HTTP dates cannot carry.
+fn truncate(time: SystemTime) -> SystemTime {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => UNIX_EPOCH + Duration::from_secs(since.as_secs()),
+        Err(_) => time,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{evaluate, if_range, EntityTag, Outcome, Validators};
+    use crate::header::HeaderMap;
+    use crate::method::Method;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn headers(fields: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in fields {
+            map.append(*name, value.parse().unwrap());
+        }
+        map
+    }
+
+    #[test]
+    fn parse_and_compare_tags() {
+        let strong = EntityTag::parse("\"abc\"").unwrap();
+        let weak = EntityTag::parse("W/\"abc\"").unwrap();
+        assert!(weak.is_weak());
+        assert_eq!(weak.to_string(), "W/\"abc\"");
+        assert!(strong.strong_eq(&strong));
+        assert!(!strong.strong_eq(&weak));
+        assert!(strong.weak_eq(&weak));
+        assert!(EntityTag::parse("abc").is_none());
+        assert!(EntityTag::parse("\"a\"b").is_none());
+    }
+
+    #[test]
+    fn evaluate_in_rfc_order() {
+        let current = Validators {
+            etag: Some(EntityTag::strong("v1")),
+            last_modified: Some(UNIX_EPOCH + Duration::from_secs(784_111_777)),
+        };
+        let check = |method: Method, fields: &[(&'static str, &'static str)]| {
+            evaluate(&method, &headers(fields), Some(&current))
+        };
+
+        assert_eq!(check(Method::GET, &[]), Outcome::Proceed);
+        assert_eq!(
+            check(Method::GET, &[("if-none-match", "\"x\", W/\"v1\"")]),
+            Outcome::NotModified
+        );
+        assert_eq!(
+            check(Method::PUT, &[("if-none-match", "*")]),
+            Outcome::PreconditionFailed
+        );
+        assert_eq!(
+            check(Method::GET, &[("if-match", "W/\"v1\"")]),
+            Outcome::PreconditionFailed
+        );
+        assert_eq!(
+            check(
+                Method::GET,
+                &[("if-match", "\"x\""), ("if-match", "\"v1\"")]
+            ),
+            Outcome::Proceed
+        );
+
+        // If-None-Match takes precedence over If-Modified-Since.
+        let date = "Sun, 06 Nov 1994 08:49:37 GMT";
+        assert_eq!(
+            check(Method::GET, &[("if-modified-since", date)]),
+            Outcome::NotModified
+        );
+        assert_eq!(
+            check(
+                Method::GET,
+                &[("if-none-match", "\"x\""), ("if-modified-since", date)]
+            ),
+            Outcome::Proceed
+        );
+        assert_eq!(
+            check(
+                Method::GET,
+                &[("if-unmodified-since", "Sun, 06 Nov 1994 08:49:36 GMT")]
+            ),
+            Outcome::PreconditionFailed
+        );
+        // If-Match takes precedence over If-Unmodified-Since.
+        assert_eq!(
+            check(
+                Method::GET,
+                &[
+                    ("if-match", "*"),
+                    ("if-unmodified-since", "Sun, 06 Nov 1994 08:49:36 GMT")
+                ]
+            ),
+            Outcome::Proceed
+        );
+        assert_eq!(
+            check(Method::GET, &[("if-modified-since", "garbage")]),
+            Outcome::Proceed
+        );
+        assert_eq!(
+            check(Method::POST, &[("if-modified-since", date)]),
+            Outcome::Proceed
+        );
+    }
+
+    #[test]
+    fn if_range_needs_an_exact_match() {
+        let current = Validators {
+            etag: Some(EntityTag::strong("v1")),
+            last_modified: Some(UNIX_EPOCH + Duration::from_secs(784_111_777)),
+        };
+        let check = |fields: &[(&'static str, &'static str)]| if_range(&headers(fields), &current);
+        assert!(check(&[]));
+        assert!(check(&[("if-range", "\"v1\"")]));
+        assert!(!check(&[("if-range", "W/\"v1\"")]));
+        assert!(check(&[("if-range", "Sun, 06 Nov 1994 08:49:37 GMT")]));
+        assert!(!check(&[("if-range", "Sun, 06 Nov 1994 08:49:38 GMT")]));
+        assert!(!check(&[("if-range", "junk")]));
+    }
+
+    #[test]
+    fn evaluate_without_a_representation() {
+        let none = |fields: &[(&'static str, &'static str)]| {
+            evaluate(&Method::PUT, &headers(fields), None)
+        };
+        assert_eq!(none(&[("if-none-match", "*")]), Outcome::Proceed);
+        assert_eq!(none(&[("if-match", "*")]), Outcome::PreconditionFailed);
+    }
+}
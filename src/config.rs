@@ -0,0 +1,730 @@
+//! The server config file.
+//!
+//! The file is TOML. Every section and key is optional:
+//!
+//! ```toml
+//! [server]
+//! workers = 4
+//! max_connections = 10000
+//! idle_timeout = 60            # seconds
+//! header_timeout = 30          # seconds
+//! shutdown_timeout = 30        # seconds
+//! max_header_size = "64K"
+//! max_body_size = "16M"
+//!
+//! [[listener]]
+//! address = "0.0.0.0:4221"
+//!
+//! [[listener]]
+//! address = "[::]:4221"
+//!
+//! [[listener]]
+//! address = "0.0.0.0:4443"
+//! tls = true                   # HTTPS, with the certificates of `[tls]`
+//!
+//! [tls]
+//! client_ca = "clients.pem"    # ask clients for a certificate from these CAs
+//! client_auth = "optional"     # or "required", the default with `client_ca`
+//!
+//! # Certificates are picked by the name the client asks for (SNI). The
+//! # certificate without `hosts` serves every other name.
+//! [[tls.certificate]]
+//! hosts = ["example.com", "*.example.com"]
+//! cert = "example.pem"         # the chain, as PEM
+//! key = "example.key"
+//!
+//! # Requests are matched to a vhost by their `Host`. The vhost without
+//! # `hosts` serves every other request.
+//! [[vhost]]
+//! hosts = ["example.com", "*.example.com"]
+//!
+//! [[vhost.location]]
+//! path = "/static"
+//! root = "/srv/static"         # serve this directory under `path`
+//! headers = { cache-control = "max-age=3600" }
+//!
+//! [[vhost.location]]
+//! path = "/upload"
+//! root = "uploads"             # relative to the config file
+//! writable = true              # accept POST, PUT and DELETE
+//! max_body_size = "1M"
+//! ```
+//!
+//! Sizes are a number of bytes or a string with a `K`, `M` or `G` suffix.
+//! [`load`] checks the whole file before anything is used, and each error
+//! names the line and column it was found at.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::de::{self, Deserializer, Visitor};
+use serde::Deserialize;
+use thiserror::Error;
+use toml::Spanned;
+
+use crate::header::{HeaderMap, HeaderName, HeaderValue};
+use crate::server::Config;
+use crate::tls::{CertificateFiles, ClientAuth, TlsSettings};
+
+/// Smallest `max_header_size` that still fits an ordinary request.
+pub const MIN_HEADER_SIZE: usize = 1024;
+
+/// A checked config file.
+#[derive(Debug, Clone, Default)]
+pub struct FileConfig {
+    pub server: ServerSettings,
+    /// Addresses to listen on, from the `[[listener]]` tables.
+    pub listeners: Vec<SocketAddr>,
+    /// Addresses to listen on for HTTPS, from the `[[listener]]` tables with
+    /// `tls = true`.
+    pub tls_listeners: Vec<SocketAddr>,
+    /// The `[tls]` table, with paths checked to be files.
+    pub tls: Option<TlsSettings>,
+    /// The `[[vhost]]` tables, in file order.
+    pub hosts: Vec<HostConfig>,
+}
+
+/// The `[server]` table. Unset values keep their defaults.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServerSettings {
+    pub workers: Option<usize>,
+    pub max_connections: Option<usize>,
+    pub idle_timeout: Option<Duration>,
+    pub header_timeout: Option<Duration>,
+    pub shutdown_timeout: Option<Duration>,
+    pub max_header_size: Option<usize>,
+    pub max_body_size: Option<usize>,
+}
+
+/// A `[[vhost]]` table.
+#[derive(Debug, Clone)]
+pub struct HostConfig {
+    /// The host names served, in lowercase. Empty for the default vhost.
+    pub names: Vec<String>,
+    pub locations: Vec<LocationConfig>,
+}
+
+/// A `[[vhost.location]]` table.
+#[derive(Debug, Clone)]
+pub struct LocationConfig {
+    /// The path prefix, starting with `/` and without a trailing `/`
+    /// unless it is `/`.
+    pub path: String,
+    /// Directory served under `path`, as an existing directory.
+    pub root: Option<PathBuf>,
+    /// Whether files under `root` can be uploaded, replaced and deleted.
+    pub writable: bool,
+    pub max_body_size: Option<usize>,
+    /// Headers set on every response.
+    pub headers: HeaderMap,
+}
+
+/// Why a config file was rejected.
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("failed to read {}", path.display())]
+    Read { path: PathBuf, source: io::Error },
+    #[error("{}:{line}:{column}: {message}", path.display())]
+    Invalid {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+}
+
+impl ServerSettings {
+    /// Sets the values given in the file on `config`.
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(workers) = self.workers {
+            config.workers = workers;
+        }
+        if let Some(max) = self.max_connections {
+            config.max_connections = max;
+        }
+        if let Some(timeout) = self.idle_timeout {
+            config.idle_timeout = timeout;
+        }
+        if let Some(timeout) = self.header_timeout {
+            config.header_timeout = timeout;
+        }
+        if let Some(timeout) = self.shutdown_timeout {
+            config.shutdown_timeout = timeout;
+        }
+        if let Some(max) = self.max_header_size {
+            config.max_header_size = max;
+        }
+        if let Some(max) = self.max_body_size {
+            config.max_body_size = max;
+        }
+    }
+}
+
+/// Reads and checks the config file at `path`.
+pub fn load(path: &Path) -> Result<FileConfig, ConfigError> {
+    let src = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    parse(&src, path)
+}
+
+/// Checks the config in `src`. Errors name `path`, and relative roots are
+/// resolved against its directory.
+pub fn parse(src: &str, path: &Path) -> Result<FileConfig, ConfigError> {
+    let invalid = |span: Option<Range<usize>>, message: String| {
+        let (line, column) = line_column(src, span.map_or(0, |span| span.start));
+        ConfigError::Invalid {
+            path: path.to_path_buf(),
+            line,
+            column,
+            message,
+        }
+    };
+    let raw: RawFile =
+        toml::from_str(src).map_err(|e| invalid(e.span(), e.message().to_string()))?;
+    let base = path.parent().unwrap_or(Path::new(""));
+    raw.check(base)
+        .map_err(|(span, message)| invalid(Some(span), message))
+}
+
+/// Parses a byte count with an optional binary `K`, `M` or `G` suffix.
+pub fn parse_size(value: &str) -> Result<usize, String> {
+    let value = value.trim();
+    let (digits, shift) = match value.as_bytes().last() {
+        Some(b'k' | b'K') => (&value[..value.len() - 1], 10),
+        Some(b'm' | b'M') => (&value[..value.len() - 1], 20),
+        Some(b'g' | b'G') => (&value[..value.len() - 1], 30),
+        _ => (value, 0),
+    };
+    let n: usize = digits
+        .parse()
+        .map_err(|_| format!("expected a size such as 4096 or 64K, got {:?}", value))?;
+    n.checked_mul(1 << shift)
+        .ok_or_else(|| format!("{} is too large", value))
+}
+
+/// The 1-based line and column of byte `offset` in `src`.
+fn line_column(src: &str, offset: usize) -> (usize, usize) {
+    let before = src.get(..offset).unwrap_or(src);
+    let line_start = before.rfind('\n').map_or(0, |nl| nl + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+/// A problem found after parsing, with the span of the value at fault.
+type CheckError = (Range<usize>, String);
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawFile {
+    #[serde(default)]
+    server: RawServer,
+    #[serde(default)]
+    listener: Vec<Spanned<RawListener>>,
+    tls: Option<Spanned<RawTls>>,
+    #[serde(default)]
+    vhost: Vec<Spanned<RawHost>>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawServer {
+    workers: Option<NonZeroUsize>,
+    max_connections: Option<NonZeroUsize>,
+    idle_timeout: Option<Seconds>,
+    header_timeout: Option<Seconds>,
+    shutdown_timeout: Option<Seconds>,
+    max_header_size: Option<Spanned<Size>>,
+    max_body_size: Option<Size>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawListener {
+    address: SocketAddr,
+    #[serde(default)]
+    tls: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawTls {
+    #[serde(default)]
+    certificate: Vec<Spanned<RawCertificate>>,
+    client_ca: Option<Spanned<PathBuf>>,
+    client_auth: Option<Spanned<ClientAuthMode>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ClientAuthMode {
+    Optional,
+    Required,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawCertificate {
+    #[serde(default)]
+    hosts: Vec<Spanned<HostName>>,
+    cert: Spanned<PathBuf>,
+    key: Spanned<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawHost {
+    #[serde(default)]
+    hosts: Vec<Spanned<HostName>>,
+    #[serde(default)]
+    location: Vec<RawLocation>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawLocation {
+    path: Spanned<UrlPath>,
+    root: Option<Spanned<PathBuf>>,
+    writable: Option<Spanned<bool>>,
+    max_body_size: Option<Size>,
+    #[serde(default)]
+    headers: BTreeMap<String, Spanned<String>>,
+}
+
+impl RawFile {
+    /// Checks what the types alone cannot: files on disk, duplicates and
+    /// settings that only make sense together.
+    fn check(self, base: &Path) -> Result<FileConfig, CheckError> {
+        let server = ServerSettings {
+            workers: self.server.workers.map(NonZeroUsize::get),
+            max_connections: self.server.max_connections.map(NonZeroUsize::get),
+            idle_timeout: self.server.idle_timeout.map(|s| s.0),
+            header_timeout: self.server.header_timeout.map(|s| s.0),
+            shutdown_timeout: self.server.shutdown_timeout.map(|s| s.0),
+            max_header_size: match self.server.max_header_size {
+                Some(size) if size.get_ref().0 < MIN_HEADER_SIZE => {
+                    return Err((
+                        size.span(),
+                        format!("max_header_size must be at least {}", MIN_HEADER_SIZE),
+                    ));
+                }
+                size => size.map(|size| size.into_inner().0),
+            },
+            max_body_size: self.server.max_body_size.map(|s| s.0),
+        };
+
+        let mut names = HashSet::new();
+        let mut has_default = false;
+        let mut hosts = Vec::with_capacity(self.vhost.len());
+        for host in self.vhost {
+            let span = host.span();
+            let host = host.into_inner();
+            if host.hosts.is_empty() {
+                if has_default {
+                    return Err((span, "only one vhost may leave out `hosts`".to_string()));
+                }
+                has_default = true;
+            }
+            let mut host_names = Vec::with_capacity(host.hosts.len());
+            for name in host.hosts {
+                if !names.insert(name.get_ref().0.clone()) {
+                    let message = format!("host {:?} is listed twice", name.get_ref().0);
+                    return Err((name.span(), message));
+                }
+                host_names.push(name.into_inner().0);
+            }
+
+            let mut paths = HashSet::new();
+            let mut locations = Vec::with_capacity(host.location.len());
+            for location in host.location {
+                locations.push(location.check(base, &mut paths)?);
+            }
+            hosts.push(HostConfig {
+                names: host_names,
+                locations,
+            });
+        }
+
+        let tls = match self.tls {
+            Some(tls) => Some(RawTls::check(tls, base)?),
+            None => None,
+        };
+        let mut listeners = Vec::new();
+        let mut tls_listeners = Vec::new();
+        for listener in self.listener {
+            let span = listener.span();
+            let listener = listener.into_inner();
+            if !listener.tls {
+                listeners.push(listener.address);
+            } else if tls.is_some() {
+                tls_listeners.push(listener.address);
+            } else {
+                return Err((span, "a `tls` listener needs a `[tls]` table".to_string()));
+            }
+        }
+
+        Ok(FileConfig {
+            server,
+            listeners,
+            tls_listeners,
+            tls,
+            hosts,
+        })
+    }
+}
+
+impl RawTls {
+    fn check(tls: Spanned<RawTls>, base: &Path) -> Result<TlsSettings, CheckError> {
+        let span = tls.span();
+        let tls = tls.into_inner();
+        let mut names = HashSet::new();
+        let mut has_default = false;
+        let mut certificates = Vec::with_capacity(tls.certificate.len());
+        for certificate in &tls.certificate {
+            if certificate.get_ref().hosts.is_empty() {
+                if has_default {
+                    let message = "only one certificate may leave out `hosts`";
+                    return Err((certificate.span(), message.to_string()));
+                }
+                has_default = true;
+            }
+            let certificate = certificate.get_ref();
+            for name in &certificate.hosts {
+                if !names.insert(name.get_ref().0.clone()) {
+                    let message = format!("host {:?} has two certificates", name.get_ref().0);
+                    return Err((name.span(), message));
+                }
+            }
+            certificates.push(CertificateFiles {
+                names: certificate
+                    .hosts
+                    .iter()
+                    .map(|n| n.get_ref().0.clone())
+                    .collect(),
+                cert: existing(base, &certificate.cert, false)?,
+                key: existing(base, &certificate.key, false)?,
+            });
+        }
+
+        let client_auth = match (tls.client_ca, tls.client_auth) {
+            (None, None) => ClientAuth::Off,
+            (None, Some(mode)) => {
+                return Err((mode.span(), "`client_auth` needs a `client_ca`".to_string()));
+            }
+            (Some(ca), mode) => {
+                let ca = existing(base, &ca, false)?;
+                match mode.map(Spanned::into_inner) {
+                    Some(ClientAuthMode::Optional) => ClientAuth::Optional(ca),
+                    Some(ClientAuthMode::Required) | None => ClientAuth::Required(ca),
+                }
+            }
+        };
+        if certificates.is_empty() {
+            let message = "`[tls]` needs at least one `[[tls.certificate]]`";
+            return Err((span, message.to_string()));
+        }
+        Ok(TlsSettings {
+            certificates,
+            client_auth,
+            ..TlsSettings::default()
+        })
+    }
+}
+
+/// `path`, relative to `base`, if it names an existing directory or file.
+fn existing(base: &Path, path: &Spanned<PathBuf>, dir: bool) -> Result<PathBuf, CheckError> {
+    let full = base.join(path.get_ref());
+    match std::fs::metadata(&full) {
+        Ok(meta) if meta.is_dir() == dir => Ok(full),
+        Ok(_) if dir => Err((
+            path.span(),
+            format!("{} is not a directory", full.display()),
+        )),
+        Ok(_) => Err((path.span(), format!("{} is not a file", full.display()))),
+        Err(e) => Err((path.span(), format!("{}: {}", full.display(), e))),
+    }
+}
+
+impl RawLocation {
+    fn check(self, base: &Path, paths: &mut HashSet<String>) -> Result<LocationConfig, CheckError> {
+        let path = self.path.get_ref().0.clone();
+        if !paths.insert(path.clone()) {
+            return Err((
+                self.path.span(),
+                format!("location {:?} is listed twice", path),
+            ));
+        }
+
+        let root = match &self.root {
+            Some(root) => Some(existing(base, root, true)?),
+            None => None,
+        };
+        let writable = match self.writable {
+            Some(writable) if *writable.get_ref() && root.is_none() => {
+                return Err((writable.span(), "`writable` needs a `root`".to_string()));
+            }
+            writable => writable.is_some_and(Spanned::into_inner),
+        };
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in self.headers {
+            let Ok(header) = HeaderName::try_from(name.as_str()) else {
+                return Err((value.span(), format!("invalid header name {:?}", name)));
+            };
+            let Ok(header_value) = HeaderValue::try_from(value.get_ref().as_str()) else {
+                return Err((value.span(), format!("invalid value for header {:?}", name)));
+            };
+            headers.insert(header, header_value);
+        }
+
+        Ok(LocationConfig {
+            path,
+            root,
+            writable,
+            max_body_size: self.max_body_size.map(|s| s.0),
+            headers,
+        })
+    }
+}
+
+/// A size in bytes, written as a number or as a string such as `"64K"`.
+struct Size(usize);
+
+impl<'de> Deserialize<'de> for Size {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Size, D::Error> {
+        struct SizeVisitor;
+
+        impl Visitor<'_> for SizeVisitor {
+            type Value = Size;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a size such as 4096 or \"64K\"")
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Size, E> {
+                usize::try_from(v)
+                    .map(Size)
+                    .map_err(|_| E::custom("size cannot be negative"))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Size, E> {
+                parse_size(v).map(Size).map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(SizeVisitor)
+    }
+}
+
+/// A positive number of seconds.
+struct Seconds(Duration);
+
+impl<'de> Deserialize<'de> for Seconds {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Seconds, D::Error> {
+        match u64::deserialize(deserializer)? {
+            0 => Err(de::Error::custom("must be at least 1 second")),
+            secs => Ok(Seconds(Duration::from_secs(secs))),
+        }
+    }
+}
+
+/// A host name, optionally starting with `*.`, in lowercase.
+struct HostName(String);
+
+impl<'de> Deserialize<'de> for HostName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<HostName, D::Error> {
+        let name = String::deserialize(deserializer)?.to_ascii_lowercase();
+        let bare = name.strip_prefix("*.").unwrap_or(&name);
+        let valid = !bare.is_empty()
+            && !bare.starts_with('.')
+            && !bare.contains("..")
+            && bare
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b':'));
+        if !valid {
+            return Err(de::Error::custom(format!("invalid host name {:?}", name)));
+        }
+        Ok(HostName(name))
+    }
+}
+
+/// A location path prefix.
+struct UrlPath(String);
+
+impl<'de> Deserialize<'de> for UrlPath {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<UrlPath, D::Error> {
+        let path = String::deserialize(deserializer)?;
+        let valid = path.starts_with('/')
+            && !path.contains("//")
+            && path
+                .bytes()
+                .all(|b| b.is_ascii_graphic() && !matches!(b, b'?' | b'#' | b'{' | b'}'));
+        if !valid {
+            return Err(de::Error::custom(format!(
+                "location path must be an absolute path, got {:?}",
+                path
+            )));
+        }
+        let trimmed = path.trim_end_matches('/');
+        Ok(UrlPath(
+            if trimmed.is_empty() { "/" } else { trimmed }.to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, parse_size, ConfigError};
+    use crate::tls::ClientAuth;
+    use std::path::Path;
+    use std::time::Duration;
+
+    fn error(src: &str) -> (usize, usize, String) {
+        match parse(src, Path::new("test.toml")).unwrap_err() {
+            ConfigError::Invalid {
+                line,
+                column,
+                message,
+                ..
+            } => (line, column, message),
+            e => panic!("unexpected error {}", e),
+        }
+    }
+
+    #[test]
+    fn parse_sizes() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("64K"), Ok(64 * 1024));
+        assert_eq!(parse_size("2m"), Ok(2 << 20));
+        assert!(parse_size("").is_err());
+        assert!(parse_size("1.5M").is_err());
+        assert!(parse_size("-1").is_err());
+    }
+
+    #[test]
+    fn parse_a_full_config() {
+        let dir = std::env::temp_dir().join(format!("config-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("static")).unwrap();
+        for file in ["site.pem", "site.key", "ca.pem"] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+        let src = r#"
+            [server]
+            workers = 2
+            idle_timeout = 5
+            shutdown_timeout = 10
+            max_body_size = "1M"
+
+            [[listener]]
+            address = "0.0.0.0:80"
+            [[listener]]
+            address = "[::]:80"
+            [[listener]]
+            address = "[::]:443"
+            tls = true
+
+            [tls]
+            client_ca = "ca.pem"
+            client_auth = "optional"
+            [[tls.certificate]]
+            hosts = ["example.com"]
+            cert = "site.pem"
+            key = "site.key"
+
+            [[vhost]]
+            hosts = ["Example.com", "*.example.com"]
+            [[vhost.location]]
+            path = "/static/"
+            root = "static"
+            headers = { cache-control = "max-age=60" }
+
+            [[vhost]]
+            [[vhost.location]]
+            path = "/"
+            max_body_size = 1024
+        "#;
+        let config = parse(src, &dir.join("server.toml")).unwrap();
+        assert_eq!(config.server.workers, Some(2));
+        assert_eq!(config.server.idle_timeout, Some(Duration::from_secs(5)));
+        assert_eq!(
+            config.server.shutdown_timeout,
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(config.server.max_body_size, Some(1 << 20));
+        assert_eq!(config.listeners.len(), 2);
+        assert!(config.listeners[1].is_ipv6());
+        assert_eq!(config.tls_listeners, ["[::]:443".parse().unwrap()]);
+
+        let tls = config.tls.unwrap();
+        assert_eq!(tls.certificates[0].names, ["example.com"]);
+        assert_eq!(tls.certificates[0].key, dir.join("site.key"));
+        assert_eq!(tls.client_auth, ClientAuth::Optional(dir.join("ca.pem")));
+
+        let host = &config.hosts[0];
+        assert_eq!(host.names, ["example.com", "*.example.com"]);
+        let location = &host.locations[0];
+        assert_eq!(location.path, "/static");
+        assert_eq!(location.root.as_deref(), Some(dir.join("static").as_path()));
+        assert!(!location.writable);
+        assert_eq!(location.headers.get("cache-control").unwrap(), "max-age=60");
+
+        assert!(config.hosts[1].names.is_empty());
+        assert_eq!(config.hosts[1].locations[0].path, "/");
+        assert_eq!(config.hosts[1].locations[0].max_body_size, Some(1024));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn report_errors_with_positions() {
+        assert_eq!(error("[server]\nworkers = 0\n").0, 2);
+        assert_eq!(error("[server]\nbogus = 1\n").0, 2);
+        assert_eq!(error("[server]\nmax_body_size = \"lots\"\n").0, 2);
+        assert_eq!(error("[server]\nmax_header_size = 10\n").0, 2);
+        assert_eq!(error("[server]\nidle_timeout = 0\n").0, 2);
+        assert_eq!(error("[server\n").0, 1);
+
+        let (line, column, message) = error("[[listener]]\naddress = \"localhost\"\n");
+        assert_eq!((line, column), (2, 11));
+        assert!(message.contains("address"), "{}", message);
+
+        assert_eq!(error("[[vhost]]\nhosts = [\"a b\"]\n").0, 2);
+        assert_eq!(
+            error("[[vhost]]\nhosts = [\"a.com\"]\n[[vhost]]\nhosts = [\"x.org\", \"A.com\"]\n"),
+            (4, 19, "host \"a.com\" is listed twice".to_string())
+        );
+        assert_eq!(error("[[vhost]]\n[[vhost]]\n").0, 2);
+
+        let location = |body: &str| error(&format!("[[vhost]]\n[[vhost.location]]\n{}", body));
+        assert_eq!(location("path = \"relative\"\n").0, 3);
+        assert_eq!(location("path = \"/a\"\nroot = \"/no/such/dir\"\n").0, 4);
+        assert_eq!(location("path = \"/a\"\nwritable = true\n").0, 4);
+        assert_eq!(
+            location("path = \"/a\"\nheaders = { \"bad name\" = \"x\" }\n").0,
+            4
+        );
+        assert_eq!(location("root = \"/tmp\"\n").0, 2);
+
+        assert_eq!(
+            error("[[listener]]\naddress = \"[::]:443\"\ntls = true\n").2,
+            "a `tls` listener needs a `[tls]` table"
+        );
+        assert_eq!(error("[tls]\n").0, 1);
+        assert_eq!(error("[tls]\nclient_auth = \"required\"\n").0, 2);
+        assert_eq!(error("[tls]\nclient_auth = \"sometimes\"\n").0, 2);
+        let certificate = |body: &str| error(&format!("[tls]\n[[tls.certificate]]\n{}", body));
+        assert_eq!(certificate("cert = \"/tmp\"\nkey = \"/tmp\"\n").0, 3);
+        assert_eq!(
+            certificate("cert = \"/no/such.pem\"\nkey = \"/tmp\"\n").0,
+            3
+        );
+    }
+}
@@ -0,0 +1,419 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use bytes::{Buf, Bytes, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::chunked::{ChunkedDecoder, ChunkedError};
+use crate::h2;
+use crate::handler::Handler;
+use crate::header::{HeaderMap, HeaderValue, CONNECTION, UPGRADE};
+use crate::help::{tokens, HeadParser, ParseLimits, RequestHead};
+use crate::method::Method;
+use crate::request::Request;
+use crate::response::{Framing, Response};
+use crate::server::{Config, Draining};
+use crate::status::StatusCode;
+use crate::tls::{Tls, TlsInfo, ALPN_H2};
+use crate::version::Version;
+
+const READ_BUF_SIZE: usize = 4096;
+
+/// Serves a connection accepted on a listener, over TLS if `tls` is given.
+/// HTTP/2 is spoken if it was agreed on with ALPN.
+pub async fn serve_connection(
+    stream: TcpStream,
+    peer: SocketAddr,
+    tls: Option<Tls>,
+    config: Arc<Config>,
+    handler: Arc<dyn Handler>,
+    draining: Draining,
+) -> Result<()> {
+    let Some(tls) = tls else {
+        return handle_client_request(stream, peer, None, config, handler, draining).await;
+    };
+    // The handshake gets as long as a request head would.
+    let (stream, info) = tokio::time::timeout(config.header_timeout, tls.accept(stream))
+        .await
+        .context("TLS handshake timed out")?
+        .context("TLS handshake failed")?;
+    let h2 = info.alpn_protocol() == Some(ALPN_H2);
+    let info = Some(Arc::new(info));
+    if h2 {
+        return h2::serve(
+            stream,
+            BytesMut::new(),
+            None,
+            info,
+            config,
+            handler,
+            draining,
+        )
+        .await;
+    }
+    handle_client_request(stream, peer, info, config, handler, draining).await
+}
+
+/// Serves HTTP/1.x requests on `stream` until the client closes it, goes idle,
+/// or asks to close, or the server shuts down. Requests carry `tls` if the
+/// stream is a TLS session.
+///
+/// A client may switch to HTTP/2 by starting with the HTTP/2 preface, or on
+/// a cleartext connection by asking for `Upgrade: h2c`.
+pub async fn handle_client_request<S>(
+    mut stream: S,
+    _peer: SocketAddr,
+    tls: Option<Arc<TlsInfo>>,
+    config: Arc<Config>,
+    handler: Arc<dyn Handler>,
+    mut draining: Draining,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut buf = BytesMut::with_capacity(READ_BUF_SIZE);
+    let mut parser = HeadParser::new(ParseLimits {
+        max_header_size: config.max_header_size,
+        ..ParseLimits::default()
+    });
+    // When the first bytes of the head being read arrived.
+    let mut head_started = None;
+    // Until its first bytes say otherwise, the client may be starting HTTP/2
+    // with prior knowledge.
+    let mut maybe_h2 = true;
+    loop {
+        if maybe_h2 {
+            let len = buf.len().min(h2::PREFACE.len());
+            if buf[..len] != h2::PREFACE[..len] {
+                maybe_h2 = false;
+            } else if len == h2::PREFACE.len() {
+                return h2::serve(stream, buf, None, tls, config, handler, draining).await;
+            }
+        }
+        // Bytes left over from the previous request may already hold the next
+        // one, so try to parse before reading.
+        let parsed = if maybe_h2 {
+            Ok(None)
+        } else {
+            parser.parse(&mut buf)
+        };
+        let head = match parsed {
+            Ok(Some(head)) => head,
+            Ok(None) => {
+                // Once a head is started it must be finished within the
+                // header timeout, however steadily its bytes trickle in.
+                let idle = buf.is_empty();
+                let timeout = if idle {
+                    if draining.is_draining() {
+                        return Ok(());
+                    }
+                    config.idle_timeout
+                } else {
+                    let started = *head_started.get_or_insert_with(Instant::now);
+                    match config.header_timeout.checked_sub(started.elapsed()) {
+                        Some(left) => left.min(config.idle_timeout),
+                        None => return reject(&mut stream, StatusCode::REQUEST_TIMEOUT).await,
+                    }
+                };
+                // Between requests there is nothing to finish, so a shutdown
+                // closes the connection at once.
+                let read = read_more(&mut stream, &mut buf, timeout);
+                let more = if idle {
+                    tokio::select! {
+                        more = read => more?,
+                        _ = draining.wait() => false,
+                    }
+                } else {
+                    read.await?
+                };
+                if !more {
+                    let expired = head_started
+                        .is_some_and(|started: Instant| started.elapsed() >= config.header_timeout);
+                    if expired {
+                        return reject(&mut stream, StatusCode::REQUEST_TIMEOUT).await;
+                    }
+                    return Ok(());
+                }
+                continue;
+            }
+            Err(e) => {
+                println!("bad request: {}", e);
+                return reject(&mut stream, e.status()).await;
+            }
+        };
+        head_started = None;
+
+        if head.body_len > config.max_body_size {
+            return reject(&mut stream, StatusCode::PAYLOAD_TOO_LARGE).await;
+        }
+        if head.expects_continue() && (head.chunked || head.body_len > 0) && buf.is_empty() {
+            stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await?;
+        }
+
+        let (body, trailers) = if head.chunked {
+            // Decoded chunks are moved out of `buf` as they arrive so the body
+            // is only held once.
+            let mut decoder = ChunkedDecoder::new(config.max_body_size);
+            let mut decoded = Vec::new();
+            loop {
+                match decoder.decode(&buf, &mut decoded) {
+                    Ok(consumed) => buf.advance(consumed),
+                    Err(ChunkedError::TooLarge(_)) => {
+                        return reject(&mut stream, StatusCode::PAYLOAD_TOO_LARGE).await;
+                    }
+                    Err(_) => return reject(&mut stream, StatusCode::BAD_REQUEST).await,
+                }
+                if decoder.is_done() {
+                    break (Bytes::from(decoded), decoder.into_trailers());
+                }
+                if !read_more(&mut stream, &mut buf, config.idle_timeout).await? {
+                    return Ok(());
+                }
+            }
+        } else {
+            while buf.len() < head.body_len {
+                if !read_more(&mut stream, &mut buf, config.idle_timeout).await? {
+                    return Ok(());
+                }
+            }
+            (buf.split_to(head.body_len).freeze(), HeaderMap::new())
+        };
+
+        if tls.is_none() {
+            if let Some(settings) = h2c_upgrade(&head) {
+                stream
+                    .write_all(
+                        b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: h2c\r\n\r\n",
+                    )
+                    .await?;
+                let mut request = into_request(head, body, trailers);
+                for name in ["connection", "upgrade", "http2-settings"] {
+                    request.headers_mut().remove(name);
+                }
+                let upgrade = h2::Upgrade { request, settings };
+                return h2::serve(stream, buf, Some(upgrade), None, config, handler, draining)
+                    .await;
+            }
+        }
+
+        let mut res = Responder {
+            stream: &mut stream,
+            version: head.version,
+            keep_alive: head.keep_alive(),
+            head_only: head.method == Method::HEAD,
+        };
+        let mut req = into_request(head, body, trailers);
+        *req.tls_mut() = tls.clone();
+        let response = call(&*handler, req).await;
+        res.keep_alive &= !draining.is_draining();
+        res.send(response).await?;
+        let keep_alive = res.keep_alive;
+        stream.flush().await?;
+
+        if !keep_alive {
+            return Ok(());
+        }
+    }
+}
+
+/// Converts a parsed request head into the owned request handlers take.
+fn into_request(head: RequestHead, body: Bytes, trailers: HeaderMap) -> Request {
+    let mut request = Request::new(body);
+    *request.method_mut() = head.method;
+    *request.uri_mut() = head.target;
+    *request.version_mut() = head.version;
+    *request.headers_mut() = head.headers;
+    *request.trailers_mut() = trailers;
+    request
+}
+
+/// The settings of a request asking to switch to HTTP/2 with `Upgrade: h2c`,
+/// if it is one the connection can switch for.
+fn h2c_upgrade(head: &RequestHead) -> Option<Vec<(u16, u32)>> {
+    let has =
+        |name, token: &[u8]| tokens(&head.headers, name).any(|t| t.eq_ignore_ascii_case(token));
+    if head.version != Version::HTTP_11
+        || !has(UPGRADE, b"h2c")
+        || !has(CONNECTION, b"upgrade")
+        || !has(CONNECTION, b"http2-settings")
+    {
+        return None;
+    }
+    let mut settings = head.headers.get_all("http2-settings");
+    match (settings.next(), settings.next()) {
+        (Some(value), None) => h2::upgrade_settings(value.as_bytes()),
+        _ => None,
+    }
+}
+
+/// Runs `handler`, answering `500 Internal Server Error` if it fails.
+pub(crate) async fn call(handler: &dyn Handler, req: Request) -> Response {
+    let method = req.method().clone();
+    let uri = req.uri().clone();
+    match handler.call(req).await {
+        Ok(res) => res,
+        Err(e) => {
+            println!("{} {} failed: {:#}", method, uri, e);
+            Response::with_status(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Answers a request that cannot be read, then closes the connection.
+async fn reject<S>(stream: &mut S, status: StatusCode) -> Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let mut res = Responder {
+        stream,
+        version: Version::HTTP_11,
+        keep_alive: false,
+        head_only: false,
+    };
+    res.send(Response::with_status(status)).await?;
+    res.stream.flush().await?;
+    Ok(())
+}
+
+/// Reads more bytes from the client into `buf`.
+///
+/// Returns `false` if the client closed the connection or sent nothing for
+/// `idle_timeout`.
+async fn read_more<S>(stream: &mut S, buf: &mut BytesMut, idle_timeout: Duration) -> Result<bool>
+where
+    S: AsyncRead + Unpin,
+{
+    buf.reserve(READ_BUF_SIZE);
+    match tokio::time::timeout(idle_timeout, stream.read_buf(buf)).await {
+        Ok(len) => Ok(len? > 0),
+        Err(_) => Ok(false),
+    }
+}
+
+/// Writes responses on a connection and decides how their bodies are framed.
+struct Responder<'a, S> {
+    stream: &'a mut S,
+    version: Version,
+    /// Whether the connection stays open after this response. Cleared when
+    /// the body can only be delimited by closing the connection.
+    keep_alive: bool,
+    /// The request was `HEAD`: the head is sent as it would be for `GET`,
+    /// but the body is not.
+    head_only: bool,
+}
+
+impl<S: AsyncWrite + Unpin> Responder<'_, S> {
+    async fn send(&mut self, mut res: Response) -> Result<()> {
+        if tokens(res.headers(), CONNECTION).any(|t| t.eq_ignore_ascii_case(b"close")) {
+            self.keep_alive = false;
+        }
+
+        // Bodies of unknown length are sent chunked. HTTP/1.0 clients do not
+        // understand chunked encoding, so for them the body runs until the
+        // connection closes, unless this is a `HEAD` and no body follows.
+        let framing = match res.body().size_hint() {
+            _ if !res.may_have_body() => Framing::None,
+            Some(len) => Framing::Length(len),
+            None if self.version >= Version::HTTP_11 => Framing::Chunked,
+            None if self.head_only => Framing::None,
+            None => {
+                self.keep_alive = false;
+                Framing::None
+            }
+        };
+
+        if !self.keep_alive {
+            res.headers_mut()
+                .insert(CONNECTION, HeaderValue::from_static("close"));
+        } else if self.version == Version::HTTP_10 {
+            res.headers_mut()
+                .insert(CONNECTION, HeaderValue::from_static("keep-alive"));
+        }
+
+        let mut head = Vec::with_capacity(256);
+        res.encode_head(framing, &mut head);
+        self.stream.write_all(&head).await?;
+        if res.may_have_body() && !self.head_only {
+            res.into_body()
+                .write_to(self.stream, framing == Framing::Chunked)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::handle_client_request;
+    use crate::handler::{sync, Handler};
+    use crate::request::Request;
+    use crate::response::Response;
+    use crate::server::{Config, Draining};
+
+    /// Sends `input` on a fresh connection to a handler answering with the
+    /// path, and returns everything the server wrote before closing it.
+    async fn exchange(input: &[u8]) -> String {
+        let handler = sync(|req: Request| Ok(Response::new(req.path().to_string().into())));
+        exchange_with(Arc::new(handler), input).await
+    }
+
+    async fn exchange_with(handler: Arc<dyn Handler>, input: &[u8]) -> String {
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        let (_shutdown, draining) = Draining::channel();
+        let server = tokio::spawn(handle_client_request(
+            server,
+            "127.0.0.1:0".parse().unwrap(),
+            None,
+            Arc::new(Config::default()),
+            handler,
+            draining,
+        ));
+        client.write_all(input).await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut output = Vec::new();
+        client.read_to_end(&mut output).await.unwrap();
+        server.await.unwrap().unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[tokio::test]
+    async fn serve_pipelined_requests() {
+        let output = exchange(b"GET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\n\r\n").await;
+        assert_eq!(output.matches("HTTP/1.1 200 OK").count(), 2);
+        assert!(output.ends_with("/b"));
+    }
+
+    #[tokio::test]
+    async fn close_after_transfer_encoding_with_content_length() {
+        let output = exchange(
+            b"POST /a HTTP/1.1\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\nGET /b HTTP/1.1\r\n\r\n",
+        )
+        .await;
+        assert_eq!(output.matches("HTTP/1.1 200 OK").count(), 1);
+        assert!(output.contains("connection: close\r\n"));
+        assert!(output.ends_with("/a"));
+    }
+
+    #[tokio::test]
+    async fn hand_trailers_to_the_handler() {
+        let handler = sync(|req: Request| {
+            assert!(req.headers().get("x-sum").is_none());
+            let sum = req.trailers().get("x-sum").unwrap().to_str()?.to_string();
+            Ok(Response::new(sum.into()))
+        });
+        let output = exchange_with(
+            Arc::new(handler),
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n1\r\na\r\n0\r\nX-Sum: 42\r\n\r\n",
+        )
+        .await;
+        assert!(output.starts_with("HTTP/1.1 200 OK"));
+        assert!(output.ends_with("42"));
+    }
+}
@@ -0,0 +1,317 @@
+//! Request body decoding from `Content-Encoding`.
+
+use std::io::{self, Read};
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::Bytes;
+use flate2::bufread::{DeflateDecoder, MultiGzDecoder, ZlibDecoder};
+use thiserror::Error;
+
+use crate::handler::{BoxFuture, BoxHandler, Handler};
+use crate::header::{HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH};
+use crate::help::tokens;
+use crate::request::Request;
+use crate::response::Response;
+use crate::status::StatusCode;
+
+/// Largest decoded body accepted by default, in bytes.
+pub const DEFAULT_MAX_SIZE: usize = 64 * 1024 * 1024;
+
+/// Most codings applied to one body.
+const MAX_CODINGS: usize = 4;
+
+/// Decodes compressed request bodies before another handler sees them.
+///
+/// Bodies sent with `Content-Encoding: gzip` or `deflate` are decompressed,
+/// and the request reaches the inner handler without `Content-Encoding` and
+/// with the decoded `Content-Length`. Every member of a gzip body is decoded,
+/// and data left over after the compressed stream gets a `400`. Decoding
+/// stops once the body grows past the size limit, so a small compressed
+/// upload cannot expand into an unbounded amount of memory.
+///
+/// ```ignore
+/// server::serve(config, Decompression::new(router).max_size(1 << 20)).await
+/// ```
+pub struct Decompression<H = BoxHandler> {
+    inner: Arc<H>,
+    max_size: usize,
+}
+
+/// Why a request body could not be decoded.
+#[derive(Debug, Error)]
+pub enum DecodeError {
+    #[error("unsupported content coding {0:?}")]
+    Unsupported(String),
+    #[error("decoded body is larger than {0} bytes")]
+    TooLarge(usize),
+    #[error("malformed {coding} data: {source}")]
+    Corrupt {
+        coding: &'static str,
+        source: io::Error,
+    },
+}
+
+impl<H: Handler> Decompression<H> {
+    pub fn new(inner: H) -> Decompression<H> {
+        Decompression {
+            inner: Arc::new(inner),
+            max_size: DEFAULT_MAX_SIZE,
+        }
+    }
+
+    /// Sets the largest decoded body, in bytes.
+    pub fn max_size(mut self, max_size: usize) -> Decompression<H> {
+        self.max_size = max_size;
+        self
+    }
+}
+
+impl<H: Handler> Handler for Decompression<H> {
+    fn call(&self, mut req: Request) -> BoxFuture<'static, Result<Response>> {
+        let inner = Arc::clone(&self.inner);
+        let max_size = self.max_size;
+        Box::pin(async move {
+            if let Err(e) = decode_request(&mut req, max_size).await {
+                println!("rejecting request body: {}", e);
+                return Ok(e.into_response());
+            }
+            inner.call(req).await
+        })
+    }
+}
+
+impl DecodeError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            DecodeError::Unsupported(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            DecodeError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            DecodeError::Corrupt { .. } => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    /// The error response. A `415` lists the codings that are understood.
+    pub fn into_response(self) -> Response {
+        let mut res = Response::with_status(self.status());
+        if let DecodeError::Unsupported(_) = self {
+            res.headers_mut()
+                .insert(ACCEPT_ENCODING, HeaderValue::from_static("gzip, deflate"));
+        }
+        res
+    }
+}
+
+/// Decodes the body of `req` in place and drops its `Content-Encoding`.
+pub async fn decode_request(req: &mut Request, max_size: usize) -> Result<(), DecodeError> {
+    let mut codings = Vec::new();
+    for token in tokens(req.headers(), CONTENT_ENCODING) {
+        let coding = match token.to_ascii_lowercase().as_slice() {
+            b"identity" => continue,
+            b"gzip" | b"x-gzip" => Coding::Gzip,
+            b"deflate" => Coding::Deflate,
+            _ => {
+                return Err(DecodeError::Unsupported(
+                    String::from_utf8_lossy(token).into_owned(),
+                ))
+            }
+        };
+        codings.push(coding);
+    }
+    if codings.len() > MAX_CODINGS {
+        return Err(DecodeError::Unsupported(format!(
+            "{} stacked codings",
+            codings.len()
+        )));
+    }
+    req.headers_mut().remove(CONTENT_ENCODING);
+    if codings.is_empty() || req.body().is_empty() {
+        return Ok(());
+    }
+
+    // Inflating is CPU bound, so it runs off the connection tasks.
+    let body = std::mem::take(req.body_mut());
+    let decoded = tokio::task::spawn_blocking(move || {
+        // Codings are listed in the order they were applied.
+        codings
+            .iter()
+            .rev()
+            .try_fold(body, |data, coding| coding.decode(&data, max_size))
+    })
+    .await
+    .expect("body decoding panicked")?;
+
+    req.headers_mut()
+        .insert(CONTENT_LENGTH, HeaderValue::from(decoded.len()));
+    *req.body_mut() = decoded;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Coding {
+    Gzip,
+    Deflate,
+}
+
+impl Coding {
+    fn decode(self, data: &[u8], max_size: usize) -> Result<Bytes, DecodeError> {
+        let coding = match self {
+            Coding::Gzip => "gzip",
+            Coding::Deflate => "deflate",
+        };
+        // A gzip body may hold several members, which are decoded one after
+        // the other.
+        let (out, rest) = match self {
+            Coding::Gzip => {
+                let (out, decoder) = read_limited(MultiGzDecoder::new(data), coding, max_size)?;
+                (out, decoder.into_inner())
+            }
+            // `deflate` means a zlib stream, but some clients send raw
+            // deflate data instead.
+            Coding::Deflate if is_zlib_header(data) => {
+                let (out, decoder) = read_limited(ZlibDecoder::new(data), coding, max_size)?;
+                (out, decoder.into_inner())
+            }
+            Coding::Deflate => {
+                let (out, decoder) = read_limited(DeflateDecoder::new(data), coding, max_size)?;
+                (out, decoder.into_inner())
+            }
+        };
+        if !rest.is_empty() {
+            return Err(DecodeError::Corrupt {
+                coding,
+                source: io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "data after the end of the stream",
+                ),
+            });
+        }
+        Ok(out)
+    }
+}
+
+/// Reads `decoder` to its end, failing once more than `max_size` bytes come
+/// out of it. The decoder is handed back so that what is left of its input
+/// can be checked.
+fn read_limited<R: Read>(
+    mut decoder: R,
+    coding: &'static str,
+    max_size: usize,
+) -> Result<(Bytes, R), DecodeError> {
+    let mut out = Vec::new();
+    (&mut decoder)
+        .take(max_size as u64 + 1)
+        .read_to_end(&mut out)
+        .map_err(|source| DecodeError::Corrupt { coding, source })?;
+    if out.len() > max_size {
+        return Err(DecodeError::TooLarge(max_size));
+    }
+    Ok((out.into(), decoder))
+}
+
+/// Whether `data` starts with a zlib header using the deflate method.
+fn is_zlib_header(data: &[u8]) -> bool {
+    match data {
+        [cmf, flg, ..] => cmf & 0x0f == 8 && (u16::from(*cmf) << 8 | u16::from(*flg)) % 31 == 0,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_request, DecodeError};
+    use crate::request::Request;
+    use bytes::Bytes;
+    use flate2::write::{DeflateEncoder, GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn request(coding: &'static str, body: Vec<u8>) -> Request {
+        let mut req = Request::new(Bytes::from(body));
+        req.headers_mut()
+            .insert("content-encoding", coding.parse().unwrap());
+        req
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn zlib(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn raw(data: &[u8]) -> Vec<u8> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[tokio::test]
+    async fn decode_gzip_and_deflate() {
+        let mut req = request("gzip", gzip(b"hello"));
+        decode_request(&mut req, 100).await.unwrap();
+        assert_eq!(req.body(), "hello");
+        assert!(req.headers().get("content-encoding").is_none());
+        assert_eq!(req.headers().get("content-length").unwrap(), "5");
+
+        let mut req = request("deflate", zlib(b"zlib"));
+        decode_request(&mut req, 100).await.unwrap();
+        assert_eq!(req.body(), "zlib");
+
+        let mut req = request("deflate", raw(b"raw"));
+        decode_request(&mut req, 100).await.unwrap();
+        assert_eq!(req.body(), "raw");
+
+        let mut req = request("gzip, gzip", gzip(&gzip(b"twice")));
+        decode_request(&mut req, 100).await.unwrap();
+        assert_eq!(req.body(), "twice");
+    }
+
+    #[tokio::test]
+    async fn decode_every_gzip_member() {
+        let mut body = gzip(b"first ");
+        body.extend_from_slice(&gzip(b"second"));
+        let mut req = request("gzip", body);
+        decode_request(&mut req, 100).await.unwrap();
+        assert_eq!(req.body(), "first second");
+        assert_eq!(req.headers().get("content-length").unwrap(), "12");
+    }
+
+    #[tokio::test]
+    async fn reject_bombs_and_unknown_codings() {
+        let mut req = request("gzip", gzip(&[0; 10_000]));
+        let err = decode_request(&mut req, 1000).await.unwrap_err();
+        assert!(matches!(err, DecodeError::TooLarge(1000)));
+        assert_eq!(err.status().as_u16(), 413);
+
+        let mut req = request("br", b"data".to_vec());
+        let err = decode_request(&mut req, 1000).await.unwrap_err();
+        assert_eq!(err.status().as_u16(), 415);
+        let res = err.into_response();
+        assert_eq!(
+            res.headers().get("accept-encoding").unwrap(),
+            "gzip, deflate"
+        );
+
+        let mut req = request("gzip", b"not gzip".to_vec());
+        let err = decode_request(&mut req, 1000).await.unwrap_err();
+        assert_eq!(err.status().as_u16(), 400);
+
+        let mut body = gzip(b"data");
+        body.extend_from_slice(b"junk");
+        let mut req = request("gzip", body);
+        let err = decode_request(&mut req, 1000).await.unwrap_err();
+        assert_eq!(err.status().as_u16(), 400);
+
+        for mut body in [zlib(b"data"), raw(b"data")] {
+            body.extend_from_slice(b"junk");
+            let mut req = request("deflate", body);
+            let err = decode_request(&mut req, 1000).await.unwrap_err();
+            assert_eq!(err.status().as_u16(), 400);
+        }
+    }
+}
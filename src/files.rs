@@ -0,0 +1,850 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::Result;
+use thiserror::Error;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+use crate::body::Body;
+use crate::conditional::{self, Outcome, Validators};
+use crate::handler::{BoxFuture, Handler};
+use crate::header::{
+    HeaderValue, ACCEPT_RANGES, CACHE_CONTROL, CONTENT_RANGE, CONTENT_TYPE, LOCATION, RANGE,
+};
+use crate::method::Method;
+use crate::range::{self, Multipart, Ranges};
+use crate::request::Request;
+use crate::response::Response;
+use crate::status::StatusCode;
+
+/// Serves files from a directory.
+///
+/// Request paths are resolved against the root without ever leaving it:
+/// `.` and `..` segments are resolved lexically and may not climb above the
+/// root, and the resolved file must still be inside the root once symlinks
+/// are followed. As a [`Handler`] it serves the path captured by a
+/// `{*path}` route parameter.
+///
+/// Responses carry `ETag` and `Last-Modified` built from the file metadata,
+/// and conditional requests are answered with `304` or `412`. `GET`
+/// requests may ask for byte ranges of the file.
+///
+/// ```ignore
+/// router.get("/files/{*path}", StaticFiles::new("/srv/files"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct StaticFiles {
+    root: PathBuf,
+    cache_control: Option<HeaderValue>,
+    max_upload_size: Option<u64>,
+}
+
+/// Why a file could not be served.
+#[derive(Debug, Error)]
+pub enum FileError {
+    #[error("file not found")]
+    NotFound,
+    #[error("access to the file is forbidden")]
+    Forbidden,
+    /// The path cannot hold the file, such as a directory or a path whose
+    /// parent directory does not exist.
+    #[error("the path conflicts with an existing entry")]
+    Conflict,
+    /// An upload is larger than the configured limit, in bytes.
+    #[error("upload is larger than {0} bytes")]
+    TooLarge(u64),
+    /// The file system is out of space or quota.
+    #[error("no space left to store the file")]
+    InsufficientStorage,
+    #[error(transparent)]
+    Io(io::Error),
+}
+
+/// A file opened by [`StaticFiles::open`].
+#[derive(Debug)]
+pub struct OpenFile {
+    pub file: fs::File,
+    pub metadata: std::fs::Metadata,
+    /// The resolved path, with symlinks followed.
+    pub path: PathBuf,
+}
+
+impl StaticFiles {
+    pub fn new(root: impl Into<PathBuf>) -> StaticFiles {
+        StaticFiles {
+            root: root.into(),
+            cache_control: None,
+            max_upload_size: None,
+        }
+    }
+
+    /// Sets the largest file, in bytes, that `POST` and `PUT` may store.
+    pub fn max_upload_size(mut self, max: u64) -> StaticFiles {
+        self.max_upload_size = Some(max);
+        self
+    }
+
+    /// Sets the `Cache-Control` sent with files and `304` responses. None
+    /// is sent by default.
+    pub fn cache_control(mut self, value: HeaderValue) -> StaticFiles {
+        self.cache_control = Some(value);
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Resolves a decoded, `/` separated request path to a file under the
+    /// root, following symlinks.
+    pub async fn resolve(&self, path: &str) -> Result<PathBuf, FileError> {
+        let root = self.canonical_root().await?;
+        let joined = root.join(normalize(path)?);
+        let resolved = fs::canonicalize(&joined).await.map_err(FileError::from)?;
+        if !resolved.starts_with(&root) {
+            return Err(FileError::Forbidden);
+        }
+        Ok(resolved)
+    }
+
+    /// Resolves the path a new file would be written to. The file itself may
+    /// not exist yet, but its directory must, and neither may lead out of
+    /// the root.
+    pub async fn resolve_new(&self, path: &str) -> Result<PathBuf, FileError> {
+        let root = self.canonical_root().await?;
+        let target = self.resolve_entry(&root, path).await?;
+        match fs::symlink_metadata(&target).await {
+            // Writing through a symlink would follow it, so it has to stay
+            // inside the root as well.
+            Ok(meta) if meta.file_type().is_symlink() => match fs::canonicalize(&target).await {
+                Ok(resolved) if resolved.starts_with(&root) => Ok(resolved),
+                Ok(_) => Err(FileError::Forbidden),
+                Err(e) => Err(e.into()),
+            },
+            Ok(_) => Ok(target),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(target),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Resolves the directory entry for `path` without following it if it is
+    /// a symlink. Its directory must exist and be inside `root`.
+    async fn resolve_entry(&self, root: &Path, path: &str) -> Result<PathBuf, FileError> {
+        let relative = normalize(path)?;
+        let Some(name) = relative.file_name() else {
+            return Err(FileError::Forbidden);
+        };
+        let parent = root.join(relative.parent().unwrap_or(Path::new("")));
+        let parent = fs::canonicalize(&parent).await.map_err(FileError::from)?;
+        if !parent.starts_with(root) {
+            return Err(FileError::Forbidden);
+        }
+        Ok(parent.join(name))
+    }
+
+    /// Opens the regular file at `path`.
+    pub async fn open(&self, path: &str) -> Result<OpenFile, FileError> {
+        let path = self.resolve(path).await?;
+        let metadata = fs::metadata(&path).await?;
+        if metadata.is_dir() {
+            return Err(FileError::Forbidden);
+        }
+        if !metadata.is_file() {
+            return Err(FileError::NotFound);
+        }
+        let file = fs::File::open(&path).await?;
+        Ok(OpenFile {
+            file,
+            metadata,
+            path,
+        })
+    }
+
+    /// Answers `req` with the file at `path`: the file or the requested
+    /// ranges of it, `304` or `412` if a precondition says so, or `403` or
+    /// `404`.
+    pub async fn serve<B>(&self, req: &Request<B>, path: &str) -> Result<Response> {
+        let open = match self.open(path).await {
+            Ok(open) => open,
+            Err(e) => return e.into_response(),
+        };
+        let validators = Validators::from_metadata(&open.metadata);
+        let mut res = match conditional::evaluate(req.method(), req.headers(), Some(&validators)) {
+            Outcome::Proceed => file_response(req, open, &validators).await?,
+            Outcome::NotModified => Response::with_status(StatusCode::NOT_MODIFIED),
+            Outcome::PreconditionFailed => {
+                return Ok(Response::with_status(StatusCode::PRECONDITION_FAILED))
+            }
+        };
+        validators.apply(res.headers_mut());
+        if let Some(value) = &self.cache_control {
+            res.headers_mut().insert(CACHE_CONTROL, value.clone());
+        }
+        Ok(res)
+    }
+
+    /// Answers a `PUT` of `req`'s body to `path`: `201 Created` for a new
+    /// file, `204 No Content` for a replaced one, `409 Conflict` if the
+    /// directory is missing or the path is a directory, or `412` if a
+    /// precondition fails.
+    ///
+    /// The body is written to a temporary file that is renamed over the
+    /// target, so readers see either the old or the new file, never a
+    /// partial one.
+    pub async fn put<B: AsRef<[u8]>>(&self, req: &Request<B>, path: &str) -> Result<Response> {
+        let target = match self.resolve_new(path).await {
+            Ok(target) => target,
+            Err(FileError::NotFound) => return FileError::Conflict.into_response(),
+            Err(e) => return e.into_response(),
+        };
+        let current = match fs::metadata(&target).await {
+            Ok(meta) if meta.is_dir() => return FileError::Conflict.into_response(),
+            Ok(meta) => Some(Validators::from_metadata(&meta)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return FileError::from(e).into_response(),
+        };
+        if conditional::evaluate(req.method(), req.headers(), current.as_ref()) != Outcome::Proceed
+        {
+            return Ok(Response::with_status(StatusCode::PRECONDITION_FAILED));
+        }
+
+        if let Err(e) = self.check_size(req.body().as_ref()) {
+            return e.into_response();
+        }
+        if let Err(e) = write_atomic(&target, req.body().as_ref(), true).await {
+            return FileError::from(e).into_response();
+        }
+        let mut res = match current {
+            Some(_) => Response::with_status(StatusCode::NO_CONTENT),
+            None => {
+                let mut res = Response::with_status(StatusCode::CREATED);
+                res.headers_mut()
+                    .insert(LOCATION, HeaderValue::try_from(req.path())?);
+                res
+            }
+        };
+        if let Ok(meta) = fs::metadata(&target).await {
+            Validators::from_metadata(&meta).apply(res.headers_mut());
+        }
+        Ok(res)
+    }
+
+    /// Answers a `POST` that uploads `req`'s body as a new file at `path`:
+    /// `201 Created` with its `Location`, `409 Conflict` if the file exists
+    /// or its directory does not, `413` if it is over the upload limit, or
+    /// `507` if the disk is full.
+    pub async fn upload<B: AsRef<[u8]>>(&self, req: &Request<B>, path: &str) -> Result<Response> {
+        let validators = match self.create(path, req.body().as_ref()).await {
+            Ok(validators) => validators,
+            Err(e) => return e.into_response(),
+        };
+        let mut res = Response::with_status(StatusCode::CREATED);
+        res.headers_mut()
+            .insert(LOCATION, HeaderValue::try_from(req.path())?);
+        validators.apply(res.headers_mut());
+        Ok(res)
+    }
+
+    /// Stores `data` as a new file at `path`, creating the root directory if
+    /// it does not exist yet. An existing file is never replaced.
+    pub async fn create(&self, path: &str, data: &[u8]) -> Result<Validators, FileError> {
+        self.check_size(data)?;
+        fs::create_dir_all(&self.root).await?;
+        let target = match self.resolve_new(path).await {
+            Ok(target) => target,
+            Err(FileError::NotFound) => return Err(FileError::Conflict),
+            Err(e) => return Err(e),
+        };
+        match write_atomic(&target, data, false).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return Err(FileError::Conflict),
+            Err(e) => return Err(e.into()),
+        }
+        let meta = fs::metadata(&target).await?;
+        Ok(Validators::from_metadata(&meta))
+    }
+
+    fn check_size(&self, data: &[u8]) -> Result<(), FileError> {
+        match self.max_upload_size {
+            Some(max) if data.len() as u64 > max => Err(FileError::TooLarge(max)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Answers a `DELETE` of `path`: `204 No Content`, `404` if there is no
+    /// such file, `409 Conflict` for a directory, or `412` if a precondition
+    /// fails. A symlink is removed itself, not the file it points to.
+    pub async fn delete<B>(&self, req: &Request<B>, path: &str) -> Result<Response> {
+        let root = match self.canonical_root().await {
+            Ok(root) => root,
+            Err(e) => return e.into_response(),
+        };
+        let target = match self.resolve_entry(&root, path).await {
+            Ok(target) => target,
+            Err(e) => return e.into_response(),
+        };
+        let meta = match fs::symlink_metadata(&target).await {
+            Ok(meta) if meta.is_dir() => return FileError::Conflict.into_response(),
+            Ok(meta) => meta,
+            Err(e) => return FileError::from(e).into_response(),
+        };
+        let current = Validators::from_metadata(&meta);
+        if conditional::evaluate(req.method(), req.headers(), Some(&current)) != Outcome::Proceed {
+            return Ok(Response::with_status(StatusCode::PRECONDITION_FAILED));
+        }
+
+        match fs::remove_file(&target).await {
+            Ok(()) => Ok(Response::with_status(StatusCode::NO_CONTENT)),
+            Err(e) => FileError::from(e).into_response(),
+        }
+    }
+
+    async fn canonical_root(&self) -> Result<PathBuf, FileError> {
+        fs::canonicalize(&self.root).await.map_err(FileError::from)
+    }
+}
+
+/// The whole file, or the parts of it asked for with `Range`.
+async fn file_response<B>(
+    req: &Request<B>,
+    open: OpenFile,
+    validators: &Validators,
+) -> Result<Response> {
+    let len = open.metadata.len();
+    let content_type = content_type(&open.path);
+    let ranges = match req.headers().get(RANGE) {
+        Some(value)
+            if *req.method() == Method::GET && conditional::if_range(req.headers(), validators) =>
+        {
+            range::parse(value.as_bytes(), len)
+        }
+        _ => Ranges::Full,
+    };
+
+    let builder = Response::builder().header(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    let res = match ranges {
+        Ranges::Full => builder
+            .header(CONTENT_TYPE, HeaderValue::from_static(content_type))
+            .body(Body::from_reader(open.file, Some(len)))?,
+        Ranges::Partial(mut ranges) if ranges.len() == 1 => {
+            let range = ranges.remove(0);
+            builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(CONTENT_TYPE, HeaderValue::from_static(content_type))
+                .header(CONTENT_RANGE, range::content_range(&range, len))
+                .body(range::single_part(open.file, range).await?)?
+        }
+        Ranges::Partial(ranges) => {
+            let multipart = Multipart::new(&open.path, &ranges, content_type, len).await?;
+            builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(
+                    CONTENT_TYPE,
+                    format!("multipart/byteranges; boundary={}", multipart.boundary),
+                )
+                .body(multipart.body)?
+        }
+        Ranges::Unsatisfiable => builder
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(CONTENT_RANGE, range::unsatisfied_range(len))
+            .body(Body::empty())?,
+    };
+    Ok(res)
+}
+
+impl Handler for StaticFiles {
+    fn call(&self, req: Request) -> BoxFuture<'static, Result<Response>> {
+        let files = self.clone();
+        Box::pin(async move {
+            let path = req.params().get("path").unwrap_or_default();
+            files.serve(&req, path).await
+        })
+    }
+}
+
+impl FileError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            FileError::NotFound => StatusCode::NOT_FOUND,
+            FileError::Forbidden => StatusCode::FORBIDDEN,
+            FileError::Conflict => StatusCode::CONFLICT,
+            FileError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            FileError::InsufficientStorage => StatusCode::INSUFFICIENT_STORAGE,
+            FileError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// The error response for every error but I/O errors, which are passed
+    /// on.
+    pub fn into_response(self) -> Result<Response> {
+        match self {
+            FileError::Io(e) => Err(e.into()),
+            e => Ok(Response::with_status(e.status())),
+        }
+    }
+}
+
+impl From<io::Error> for FileError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::NotFound => FileError::NotFound,
+            io::ErrorKind::PermissionDenied => FileError::Forbidden,
+            _ => match e.raw_os_error() {
+                // ENOTDIR: a file was used as a directory in the path.
+                Some(20) if cfg!(unix) => FileError::NotFound,
+                // ENOSPC, and EDQUOT on Linux.
+                Some(28) if cfg!(unix) => FileError::InsufficientStorage,
+                Some(122) if cfg!(target_os = "linux") => FileError::InsufficientStorage,
+                _ => FileError::Io(e),
+            },
+        }
+    }
+}
+
+/// Writes `data` to a temporary file next to `target` and moves it into
+/// place, so `target` never holds a partial file.
+///
+/// With `replace` an existing file is replaced. Otherwise the file is linked
+/// into place, which fails with `AlreadyExists` if `target` exists.
+async fn write_atomic(target: &Path, data: &[u8], replace: bool) -> io::Result<()> {
+    // The name is fixed length, so it fits wherever the target's name does.
+    let random = RandomState::new().build_hasher().finish();
+    let temp = target.with_file_name(format!(".upload.{:016x}.tmp", random));
+
+    let result = async {
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp)
+            .await?;
+        file.write_all(data).await?;
+        file.sync_all().await?;
+        if replace {
+            fs::rename(&temp, target).await
+        } else {
+            fs::hard_link(&temp, target).await
+        }
+    }
+    .await;
+    if result.is_err() || !replace {
+        let _ = fs::remove_file(&temp).await;
+    }
+    result
+}
+
+/// Turns a request path into a relative path with no `.` or `..` segments.
+///
+/// Leading slashes are ignored, so an absolute path stays under the root,
+/// and `..` may not go above it. Backslashes and NUL bytes are refused since
+/// they could be read as separators or end the path early.
+fn normalize(path: &str) -> Result<PathBuf, FileError> {
+    if path.contains(['\\', '\0']) {
+        return Err(FileError::Forbidden);
+    }
+    let mut normalized = PathBuf::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                if !normalized.pop() {
+                    return Err(FileError::Forbidden);
+                }
+            }
+            segment => {
+                // Anything else, such as a Windows drive prefix, would not be
+                // a plain file name.
+                let mut components = Path::new(segment).components();
+                match (components.next(), components.next()) {
+                    (Some(Component::Normal(_)), None) => normalized.push(segment),
+                    _ => return Err(FileError::Forbidden),
+                }
+            }
+        }
+    }
+    Ok(normalized)
+}
+
+/// The media type for a file, from its extension.
+pub fn content_type(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+    match ext.as_deref() {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("md") => "text/markdown; charset=utf-8",
+        Some("csv") => "text/csv; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        Some("avif") => "image/avif",
+        Some("pdf") => "application/pdf",
+        Some("wasm") => "application/wasm",
+        Some("zip") => "application/zip",
+        Some("gz") => "application/gzip",
+        Some("mp3") => "audio/mpeg",
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{content_type, normalize, FileError, StaticFiles};
+    use crate::header::{
+        HeaderValue, ACCEPT_RANGES, CACHE_CONTROL, CONTENT_RANGE, CONTENT_TYPE, ETAG,
+        LAST_MODIFIED, LOCATION,
+    };
+    use crate::method::Method;
+    use crate::request::Request;
+    use crate::status::StatusCode;
+    use std::path::{Path, PathBuf};
+
+    fn temp_root(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("static-files-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("root/sub")).unwrap();
+        std::fs::write(dir.join("root/index.html"), "<p>hi</p>").unwrap();
+        std::fs::write(dir.join("root/sub/data"), "data").unwrap();
+        std::fs::write(dir.join("secret"), "secret").unwrap();
+        dir
+    }
+
+    #[test]
+    fn normalize_confines_paths() {
+        assert_eq!(normalize("a/./b//c").unwrap(), Path::new("a/b/c"));
+        assert_eq!(normalize("/etc/passwd").unwrap(), Path::new("etc/passwd"));
+        assert_eq!(normalize("a/../b").unwrap(), Path::new("b"));
+        assert!(matches!(normalize("../secret"), Err(FileError::Forbidden)));
+        assert!(matches!(normalize("a/../../b"), Err(FileError::Forbidden)));
+        assert!(matches!(normalize("a\\..\\b"), Err(FileError::Forbidden)));
+        assert!(matches!(normalize("a\0b"), Err(FileError::Forbidden)));
+    }
+
+    #[tokio::test]
+    async fn serve_files_under_the_root() {
+        let dir = temp_root("serve");
+        let files = StaticFiles::new(dir.join("root"));
+
+        let res = files.serve(&Request::new(()), "index.html").await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().get("content-type").unwrap(),
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(res.into_body().collect().await.unwrap(), "<p>hi</p>");
+
+        let res = files.serve(&Request::new(()), "sub/data").await.unwrap();
+        assert_eq!(
+            res.headers().get("content-type").unwrap(),
+            "application/octet-stream"
+        );
+
+        let status = |path: &'static str| {
+            let files = files.clone();
+            async move { files.serve(&Request::new(()), path).await.unwrap().status() }
+        };
+        assert_eq!(status("missing").await, StatusCode::NOT_FOUND);
+        assert_eq!(status("index.html/x").await, StatusCode::NOT_FOUND);
+        assert_eq!(status("../secret").await, StatusCode::FORBIDDEN);
+        assert_eq!(status("sub").await, StatusCode::FORBIDDEN);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn answer_conditional_requests() {
+        let dir = temp_root("conditional");
+        let files =
+            StaticFiles::new(dir.join("root")).cache_control(HeaderValue::from_static("no-cache"));
+
+        let res = files.serve(&Request::new(()), "index.html").await.unwrap();
+        let etag = res.headers().get(ETAG).unwrap().clone();
+        let modified = res.headers().get(LAST_MODIFIED).unwrap().clone();
+        assert_eq!(res.headers().get(CACHE_CONTROL).unwrap(), "no-cache");
+
+        let mut req = Request::new(());
+        req.headers_mut().insert("if-none-match", etag.clone());
+        let res = files.serve(&req, "index.html").await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(res.headers().get(ETAG).unwrap(), &etag);
+        assert_eq!(res.headers().get(CACHE_CONTROL).unwrap(), "no-cache");
+        assert!(res.headers().get("content-type").is_none());
+
+        let mut req = Request::new(());
+        req.headers_mut().insert("if-modified-since", modified);
+        let res = files.serve(&req, "index.html").await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+
+        let mut req = Request::new(());
+        *req.method_mut() = Method::POST;
+        req.headers_mut().insert("if-none-match", etag);
+        let res = files.serve(&req, "index.html").await.unwrap();
+        assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+
+        let mut req = Request::new(());
+        req.headers_mut()
+            .insert("if-match", HeaderValue::from_static("\"stale\""));
+        let res = files.serve(&req, "index.html").await.unwrap();
+        assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn answer_range_requests() {
+        let dir = temp_root("range");
+        std::fs::write(dir.join("root/digits"), "0123456789").unwrap();
+        let files = StaticFiles::new(dir.join("root"));
+        let get = |range: &'static str, if_range: Option<HeaderValue>| {
+            let files = files.clone();
+            async move {
+                let mut req = Request::new(());
+                req.headers_mut()
+                    .insert("range", HeaderValue::from_static(range));
+                if let Some(value) = if_range {
+                    req.headers_mut().insert("if-range", value);
+                }
+                files.serve(&req, "digits").await.unwrap()
+            }
+        };
+
+        let res = get("bytes=2-4", None).await;
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.headers().get(ACCEPT_RANGES).unwrap(), "bytes");
+        assert_eq!(res.headers().get(CONTENT_RANGE).unwrap(), "bytes 2-4/10");
+        assert_eq!(res.body().size_hint(), Some(3));
+        assert_eq!(res.into_body().collect().await.unwrap(), "234");
+
+        let res = get("bytes=0-0,-2", None).await;
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        let content_type = res.headers().get(CONTENT_TYPE).unwrap().to_str().unwrap();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap()
+            .to_string();
+        let len = res.body().size_hint().unwrap();
+        let body = res.into_body().collect().await.unwrap();
+        assert_eq!(body.len() as u64, len);
+        assert_eq!(
+            body,
+            format!(
+                "\r\n--{b}\r\ncontent-type: application/octet-stream\r\ncontent-range: bytes 0-0/10\r\n\r\n0\
+                 \r\n--{b}\r\ncontent-type: application/octet-stream\r\ncontent-range: bytes 8-9/10\r\n\r\n89\
+                 \r\n--{b}--\r\n",
+                b = boundary
+            )
+        );
+
+        let res = get("bytes=10-", None).await;
+        assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(res.headers().get(CONTENT_RANGE).unwrap(), "bytes */10");
+
+        let etag = get("bytes=0-1", None)
+            .await
+            .headers()
+            .get(ETAG)
+            .unwrap()
+            .clone();
+        assert_eq!(
+            get("bytes=0-1", Some(etag)).await.status(),
+            StatusCode::PARTIAL_CONTENT
+        );
+        let stale = HeaderValue::from_static("\"stale\"");
+        let res = get("bytes=0-1", Some(stale)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.into_body().collect().await.unwrap(), "0123456789");
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn put_and_delete_files() {
+        let dir = temp_root("put");
+        let files = StaticFiles::new(dir.join("root"));
+        let request = |method: Method, body: &'static str| {
+            let mut req = Request::new(body);
+            *req.method_mut() = method;
+            *req.uri_mut() = "/files/new".parse().unwrap();
+            req
+        };
+
+        let res = files
+            .put(&request(Method::PUT, "one"), "new")
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(res.headers().get(LOCATION).unwrap(), "/files/new");
+        let etag = res.headers().get(ETAG).unwrap().clone();
+        assert_eq!(std::fs::read(dir.join("root/new")).unwrap(), b"one");
+
+        let mut req = request(Method::PUT, "two");
+        req.headers_mut().insert("if-match", etag);
+        let res = files.put(&req, "new").await.unwrap();
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(std::fs::read(dir.join("root/new")).unwrap(), b"two");
+
+        let mut req = request(Method::PUT, "three");
+        req.headers_mut()
+            .insert("if-none-match", HeaderValue::from_static("*"));
+        let res = files.put(&req, "new").await.unwrap();
+        assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+
+        let put = |path: &'static str| {
+            let files = files.clone();
+            async move {
+                files
+                    .put(&request(Method::PUT, "x"), path)
+                    .await
+                    .unwrap()
+                    .status()
+            }
+        };
+        assert_eq!(put("missing/new").await, StatusCode::CONFLICT);
+        assert_eq!(put("sub").await, StatusCode::CONFLICT);
+        assert_eq!(put("../escape").await, StatusCode::FORBIDDEN);
+
+        let delete = |path: &'static str| {
+            let files = files.clone();
+            async move {
+                files
+                    .delete(&request(Method::DELETE, ""), path)
+                    .await
+                    .unwrap()
+                    .status()
+            }
+        };
+        assert_eq!(delete("new").await, StatusCode::NO_CONTENT);
+        assert!(!dir.join("root/new").exists());
+        assert_eq!(delete("new").await, StatusCode::NOT_FOUND);
+        assert_eq!(delete("sub").await, StatusCode::CONFLICT);
+        assert_eq!(delete("../secret").await, StatusCode::FORBIDDEN);
+        // No temporary files are left behind.
+        assert_eq!(std::fs::read_dir(dir.join("root")).unwrap().count(), 2);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn upload_new_files() {
+        let dir = temp_root("upload");
+        // The root is created on the first upload.
+        let files = StaticFiles::new(dir.join("uploads")).max_upload_size(8);
+        let upload = |path: &'static str, body: &'static str| {
+            let files = files.clone();
+            async move {
+                let mut req = Request::new(body);
+                *req.method_mut() = Method::POST;
+                *req.uri_mut() = format!("/files/{}", path).parse().unwrap();
+                files.upload(&req, path).await.unwrap()
+            }
+        };
+
+        let res = upload("new", "one").await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(res.headers().get(LOCATION).unwrap(), "/files/new");
+        assert!(res.headers().get(ETAG).is_some());
+        assert_eq!(std::fs::read(dir.join("uploads/new")).unwrap(), b"one");
+
+        // The temporary file must fit in the longest name the target can have.
+        let longest: &'static str = Box::leak("n".repeat(255).into_boxed_str());
+        assert_eq!(upload(longest, "x").await.status(), StatusCode::CREATED);
+        std::fs::remove_file(dir.join("uploads").join(longest)).unwrap();
+
+        assert_eq!(upload("new", "two").await.status(), StatusCode::CONFLICT);
+        assert_eq!(std::fs::read(dir.join("uploads/new")).unwrap(), b"one");
+        assert_eq!(
+            upload("missing/new", "x").await.status(),
+            StatusCode::CONFLICT
+        );
+        assert_eq!(
+            upload("big", "more than eight").await.status(),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+        assert_eq!(
+            upload("../escape", "x").await.status(),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(std::fs::read_dir(dir.join("uploads")).unwrap().count(), 1);
+
+        std::fs::create_dir(dir.join("uploads/sub")).unwrap();
+        assert_eq!(upload("sub", "x").await.status(), StatusCode::CONFLICT);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn storage_errors_are_typed() {
+        let full = FileError::from(std::io::Error::from_raw_os_error(28));
+        assert_eq!(full.status(), StatusCode::INSUFFICIENT_STORAGE);
+        #[cfg(target_os = "linux")]
+        {
+            let quota = FileError::from(std::io::Error::from_raw_os_error(122));
+            assert_eq!(quota.status(), StatusCode::INSUFFICIENT_STORAGE);
+        }
+        #[cfg(unix)]
+        assert!(matches!(
+            FileError::from(std::io::Error::from_raw_os_error(20)),
+            FileError::NotFound
+        ));
+        let other = FileError::from(std::io::Error::new(std::io::ErrorKind::InvalidData, "boom"));
+        assert!(matches!(other, FileError::Io(_)));
+        assert!(other.into_response().is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn refuse_symlinks_out_of_the_root() {
+        let dir = temp_root("symlink");
+        std::os::unix::fs::symlink(dir.join("secret"), dir.join("root/escape")).unwrap();
+        std::os::unix::fs::symlink(dir.join("root/sub/data"), dir.join("root/inside")).unwrap();
+        let files = StaticFiles::new(dir.join("root"));
+
+        assert_eq!(
+            files
+                .serve(&Request::new(()), "escape")
+                .await
+                .unwrap()
+                .status(),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            files
+                .serve(&Request::new(()), "inside")
+                .await
+                .unwrap()
+                .status(),
+            StatusCode::OK
+        );
+        assert!(matches!(
+            files.resolve_new("escape").await,
+            Err(FileError::Forbidden)
+        ));
+        assert!(files.resolve_new("sub/new").await.is_ok());
+        assert!(matches!(
+            files.resolve_new("nodir/new").await,
+            Err(FileError::NotFound)
+        ));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn content_type_from_extension() {
+        assert_eq!(content_type(Path::new("a/b.PNG")), "image/png");
+        assert_eq!(
+            content_type(Path::new("style.css")),
+            "text/css; charset=utf-8"
+        );
+        assert_eq!(content_type(Path::new("noext")), "application/octet-stream");
+    }
+}
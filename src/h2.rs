@@ -0,0 +1,1483 @@
+//! HTTP/2 (RFC 9113): frames, HPACK, streams and flow control.
+//!
+//! Each stream's request is answered on its own task by the same handler
+//! HTTP/1.x requests go to. The connection task reads frames, writes what the
+//! stream tasks send back as flow control allows, and owns all of the
+//! connection state.
+
+mod frame;
+mod hpack;
+mod huffman;
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use bytes::{Buf, Bytes, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task::AbortHandle;
+
+use crate::conn::call;
+use crate::handler::Handler;
+use crate::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_LENGTH, COOKIE, HOST, TE};
+use crate::help::is_sensitive;
+use crate::method::Method;
+use crate::request::Request;
+use crate::response::{Framing, Response};
+use crate::server::{Config, Draining};
+use crate::status::StatusCode;
+use crate::tls::TlsInfo;
+use crate::uri::Uri;
+use crate::version::Version;
+
+pub(crate) use frame::PREFACE;
+use frame::{Error, Frame, Reason, DEFAULT_MAX_FRAME_SIZE, DEFAULT_WINDOW, MAX_WINDOW};
+
+const READ_BUF_SIZE: usize = 16 * 1024;
+
+/// Streams a client may have open at once.
+const MAX_CONCURRENT_STREAMS: usize = 100;
+
+/// Response bytes a stream may queue ahead of its flow-control window before
+/// its handler has to wait.
+const STREAM_BUFFER: usize = 64 * 1024;
+
+/// Request body bytes a connection holds for its streams before only the
+/// oldest stream still sending a body is given more window, so that one
+/// request can always finish.
+const RECEIVE_BUFFER: usize = 1024 * 1024;
+
+/// Largest piece a response body is queued in.
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// Header fields that only mean something to an HTTP/1.1 connection.
+const CONNECTION_SPECIFIC: [&str; 5] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// A request that arrived over HTTP/1.1 with `Upgrade: h2c`. It is answered
+/// on stream 1 once the connection has switched.
+pub(crate) struct Upgrade {
+    pub(crate) request: Request,
+    /// The settings from its `HTTP2-Settings` header.
+    pub(crate) settings: Vec<(u16, u32)>,
+}
+
+/// Decodes the `HTTP2-Settings` header of an h2c upgrade: a `SETTINGS`
+/// payload in unpadded base64url.
+pub(crate) fn upgrade_settings(value: &[u8]) -> Option<Vec<(u16, u32)>> {
+    let mut payload = Vec::with_capacity(value.len() * 3 / 4);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for &c in value.strip_suffix(b"=").unwrap_or(value) {
+        let sextet = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'-' => 62,
+            b'_' => 63,
+            _ => return None,
+        };
+        acc = ((acc << 6) | u32::from(sextet)) & 0xffff;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            payload.push((acc >> bits) as u8);
+        }
+    }
+    if payload.len() % 6 != 0 {
+        return None;
+    }
+    let params = payload
+        .chunks(6)
+        .map(|p| {
+            let id = u16::from_be_bytes([p[0], p[1]]);
+            (id, u32::from_be_bytes([p[2], p[3], p[4], p[5]]))
+        })
+        .collect();
+    Some(params)
+}
+
+/// Serves HTTP/2 on `stream` until the client closes it or goes idle, the
+/// server shuts down, or either side breaks the protocol.
+///
+/// `buf` holds whatever was already read from the client. Unless the
+/// connection was upgraded from HTTP/1.1, the client preface comes first.
+pub(crate) async fn serve<S>(
+    mut stream: S,
+    mut buf: BytesMut,
+    upgrade: Option<Upgrade>,
+    tls: Option<Arc<TlsInfo>>,
+    config: Arc<Config>,
+    handler: Arc<dyn Handler>,
+    mut draining: Draining,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (events, mut responses) = mpsc::unbounded_channel();
+    let mut conn = Connection::new(tls, config.clone(), handler, events);
+    let upgraded = match upgrade.map(|upgrade| conn.upgrade(upgrade)) {
+        Some(Err(Error::Connection(reason))) => {
+            frame::go_away(&mut conn.out, 0, reason);
+            false
+        }
+        Some(Err(Error::Stream(id, reason))) => {
+            conn.reset(id, reason);
+            true
+        }
+        _ => true,
+    };
+    stream.write_all(&conn.out).await?;
+    stream.flush().await?;
+    conn.out.clear();
+    if !upgraded {
+        return Ok(());
+    }
+
+    let preface = tokio::time::timeout(config.header_timeout, async {
+        while buf.len() < PREFACE.len() {
+            if !PREFACE.starts_with(&buf) || stream.read_buf(&mut buf).await? == 0 {
+                return Ok(false);
+            }
+        }
+        anyhow::Ok(buf.starts_with(PREFACE))
+    });
+    if !preface.await.context("HTTP/2 preface timed out")?? {
+        return Ok(());
+    }
+    buf.advance(PREFACE.len());
+
+    loop {
+        conn.send_pending();
+        if !conn.out.is_empty() {
+            stream.write_all(&conn.out).await?;
+            stream.flush().await?;
+            conn.out.clear();
+        }
+        if conn.is_done() {
+            return Ok(());
+        }
+
+        let idle = conn.streams.is_empty();
+        buf.reserve(READ_BUF_SIZE);
+        tokio::select! {
+            read = stream.read_buf(&mut buf) => {
+                if read? == 0 {
+                    return Ok(());
+                }
+                if let Err(reason) = conn.receive(&mut buf) {
+                    frame::go_away(&mut conn.out, conn.last_stream, reason);
+                    stream.write_all(&conn.out).await?;
+                    stream.flush().await?;
+                    return Ok(());
+                }
+            }
+            Some(event) = responses.recv() => conn.event(event),
+            _ = draining.wait(), if conn.accepting => conn.go_away(),
+            _ = tokio::time::sleep(config.idle_timeout), if idle => conn.go_away(),
+        }
+    }
+}
+
+/// What a stream's task sends back to the connection.
+enum Event {
+    Head {
+        stream: u32,
+        head: Response<()>,
+        framing: Framing,
+        end_stream: bool,
+    },
+    Data {
+        stream: u32,
+        data: Bytes,
+        end_stream: bool,
+    },
+    Reset {
+        stream: u32,
+        reason: Reason,
+    },
+}
+
+struct Stream {
+    /// The request head while its body is still arriving.
+    request: Option<Request<()>>,
+    body: BytesMut,
+    content_length: Option<u64>,
+    /// The client has sent `END_STREAM`.
+    remote_closed: bool,
+    /// DATA the client may still send before the next `WINDOW_UPDATE`.
+    recv_window: i64,
+    send_window: i64,
+    /// Response data waiting for flow-control window.
+    pending: VecDeque<Bytes>,
+    /// The response ends after `pending`.
+    end_pending: bool,
+    /// Room left in `pending`, which the task waits on before queuing more.
+    credit: Arc<Semaphore>,
+    task: Option<AbortHandle>,
+}
+
+impl Stream {
+    fn new(send_window: i64) -> Stream {
+        Stream {
+            request: None,
+            body: BytesMut::new(),
+            content_length: None,
+            remote_closed: false,
+            recv_window: DEFAULT_WINDOW,
+            send_window,
+            pending: VecDeque::new(),
+            end_pending: false,
+            credit: Arc::new(Semaphore::new(STREAM_BUFFER)),
+            task: None,
+        }
+    }
+
+    /// Stops the task answering the stream.
+    fn cancel(self) {
+        if let Some(task) = self.task {
+            task.abort();
+        }
+        self.credit.close();
+    }
+}
+
+/// A header block split over `CONTINUATION` frames.
+struct Continuation {
+    stream: u32,
+    end_stream: bool,
+    self_dependent: bool,
+    block: BytesMut,
+}
+
+struct Connection {
+    tls: Option<Arc<TlsInfo>>,
+    config: Arc<Config>,
+    handler: Arc<dyn Handler>,
+    events: mpsc::UnboundedSender<Event>,
+    decoder: hpack::Decoder,
+    encoder: hpack::Encoder,
+    /// Frames to write at the end of this turn of the loop.
+    out: BytesMut,
+    streams: HashMap<u32, Stream>,
+    /// The highest stream the client has opened.
+    last_stream: u32,
+    continuation: Option<Continuation>,
+    /// The client has sent its first `SETTINGS`.
+    settings_received: bool,
+    /// New streams are served. Cleared by a `GOAWAY` from either side.
+    accepting: bool,
+    recv_window: i64,
+    send_window: i64,
+    peer_initial_window: i64,
+    peer_max_frame_size: usize,
+}
+
+impl Connection {
+    fn new(
+        tls: Option<Arc<TlsInfo>>,
+        config: Arc<Config>,
+        handler: Arc<dyn Handler>,
+        events: mpsc::UnboundedSender<Event>,
+    ) -> Connection {
+        let mut out = BytesMut::new();
+        frame::settings(
+            &mut out,
+            &[
+                (
+                    frame::SETTINGS_MAX_CONCURRENT_STREAMS,
+                    MAX_CONCURRENT_STREAMS as u32,
+                ),
+                (
+                    frame::SETTINGS_MAX_HEADER_LIST_SIZE,
+                    u32::try_from(config.max_header_size).unwrap_or(u32::MAX),
+                ),
+            ],
+        );
+        Connection {
+            tls,
+            config,
+            handler,
+            events,
+            decoder: hpack::Decoder::new(hpack::TABLE_SIZE),
+            encoder: hpack::Encoder,
+            out,
+            streams: HashMap::new(),
+            last_stream: 0,
+            continuation: None,
+            settings_received: false,
+            accepting: true,
+            recv_window: DEFAULT_WINDOW,
+            send_window: DEFAULT_WINDOW,
+            peer_initial_window: DEFAULT_WINDOW,
+            peer_max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        }
+    }
+
+    /// Whether the connection has nothing left to do.
+    fn is_done(&self) -> bool {
+        !self.accepting && self.streams.is_empty()
+    }
+
+    /// Answers the request of an h2c upgrade as stream 1, which the client
+    /// has already half-closed.
+    fn upgrade(&mut self, upgrade: Upgrade) -> Result<(), Error> {
+        self.apply_settings(&upgrade.settings)?;
+        self.last_stream = 1;
+        let mut stream = Stream::new(self.peer_initial_window);
+        let body = upgrade.request.body().clone();
+        stream.request = Some(upgrade.request.map(|_| ()));
+        stream.body.extend_from_slice(&body);
+        self.streams.insert(1, stream);
+        self.end_of_request(1)
+    }
+
+    /// Handles every whole frame in `buf`. Fails with the reason to close the
+    /// connection for.
+    fn receive(&mut self, buf: &mut BytesMut) -> Result<(), Reason> {
+        loop {
+            let result = match frame::decode(buf, DEFAULT_MAX_FRAME_SIZE) {
+                Ok(Some(frame)) => self.frame(frame),
+                Ok(None) => {
+                    self.replenish();
+                    return Ok(());
+                }
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => {}
+                Err(Error::Connection(reason)) => return Err(reason),
+                Err(Error::Stream(id, reason)) => self.reset(id, reason),
+            }
+        }
+    }
+
+    fn frame(&mut self, frame: Frame) -> Result<(), Error> {
+        if !self.settings_received && !matches!(frame, Frame::Settings { ack: false, .. }) {
+            return Err(Error::Connection(Reason::PROTOCOL_ERROR));
+        }
+        if let Some(continuation) = &self.continuation {
+            let expected = matches!(
+                frame,
+                Frame::Continuation { stream, .. } if stream == continuation.stream
+            );
+            if !expected {
+                return Err(Error::Connection(Reason::PROTOCOL_ERROR));
+            }
+        }
+
+        match frame {
+            Frame::Data {
+                stream,
+                data,
+                end_stream,
+                flow_len,
+            } => self.data(stream, data, end_stream, flow_len),
+            Frame::Headers {
+                stream,
+                block,
+                end_stream,
+                end_headers,
+                dependency,
+            } => {
+                let self_dependent = dependency == Some(stream);
+                if end_headers {
+                    return self.header_block(stream, &block, end_stream, self_dependent);
+                }
+                self.continuation = Some(Continuation {
+                    stream,
+                    end_stream,
+                    self_dependent,
+                    block: BytesMut::from(&block[..]),
+                });
+                Ok(())
+            }
+            Frame::Continuation {
+                block, end_headers, ..
+            } => {
+                let Some(mut continuation) = self.continuation.take() else {
+                    return Err(Error::Connection(Reason::PROTOCOL_ERROR));
+                };
+                continuation.block.extend_from_slice(&block);
+                // Field lines hardly grow when encoded, so a block this large
+                // can only decode to a list over the limit.
+                if continuation.block.len() > 2 * self.config.max_header_size.max(READ_BUF_SIZE) {
+                    return Err(Error::Connection(Reason::ENHANCE_YOUR_CALM));
+                }
+                if !end_headers {
+                    self.continuation = Some(continuation);
+                    return Ok(());
+                }
+                self.header_block(
+                    continuation.stream,
+                    &continuation.block,
+                    continuation.end_stream,
+                    continuation.self_dependent,
+                )
+            }
+            Frame::Priority { stream, dependency } if stream == dependency => {
+                Err(Error::Stream(stream, Reason::PROTOCOL_ERROR))
+            }
+            Frame::Priority { .. } | Frame::Unknown => Ok(()),
+            Frame::RstStream { stream, .. } => {
+                if self.is_idle(stream) {
+                    return Err(Error::Connection(Reason::PROTOCOL_ERROR));
+                }
+                if let Some(stream) = self.streams.remove(&stream) {
+                    stream.cancel();
+                }
+                Ok(())
+            }
+            Frame::Settings { ack: true, .. } => Ok(()),
+            Frame::Settings { ack: false, params } => {
+                self.settings_received = true;
+                self.apply_settings(&params)?;
+                frame::settings_ack(&mut self.out);
+                Ok(())
+            }
+            Frame::PushPromise => Err(Error::Connection(Reason::PROTOCOL_ERROR)),
+            Frame::Ping { ack, payload } => {
+                if !ack {
+                    frame::ping_ack(&mut self.out, payload);
+                }
+                Ok(())
+            }
+            Frame::GoAway { .. } => {
+                // The client opens no more streams; the open ones finish.
+                self.accepting = false;
+                Ok(())
+            }
+            Frame::WindowUpdate { stream, increment } => self.window_update(stream, increment),
+        }
+    }
+
+    /// Whether the client never opened `id`.
+    fn is_idle(&self, id: u32) -> bool {
+        id % 2 == 0 || id > self.last_stream
+    }
+
+    fn header_block(
+        &mut self,
+        id: u32,
+        block: &[u8],
+        end_stream: bool,
+        self_dependent: bool,
+    ) -> Result<(), Error> {
+        // The block is decoded whatever becomes of the stream, to keep the
+        // table in step with the client's.
+        let fields = self.decoder.decode(block).map_err(|e| {
+            println!("bad HTTP/2 header block: {}", e);
+            Error::Connection(Reason::COMPRESSION_ERROR)
+        })?;
+        if self_dependent {
+            return Err(Error::Stream(id, Reason::PROTOCOL_ERROR));
+        }
+
+        if let Some(stream) = self.streams.get(&id) {
+            // Trailers, which are checked and dropped.
+            if stream.remote_closed {
+                return Err(Error::Stream(id, Reason::STREAM_CLOSED));
+            }
+            if !end_stream || fields.iter().any(|(name, _)| name.starts_with(b":")) {
+                return Err(Error::Stream(id, Reason::PROTOCOL_ERROR));
+            }
+            return self.end_of_request(id);
+        }
+
+        if self.is_idle(id) {
+            self.last_stream = id;
+        } else {
+            return Err(Error::Connection(Reason::PROTOCOL_ERROR));
+        }
+        if !self.accepting {
+            return Ok(());
+        }
+        if self.streams.len() >= MAX_CONCURRENT_STREAMS {
+            return Err(Error::Stream(id, Reason::REFUSED_STREAM));
+        }
+
+        let request = match request(fields, self.config.max_header_size) {
+            Ok(request) => request,
+            Err(BadRequest::TooLarge) => {
+                self.respond_now(id, StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE, end_stream);
+                return Ok(());
+            }
+            Err(BadRequest::Malformed) => return Err(Error::Stream(id, Reason::PROTOCOL_ERROR)),
+        };
+        let content_length = match request.headers().get(CONTENT_LENGTH) {
+            Some(value) => match value.to_str().ok().and_then(|v| v.parse::<u64>().ok()) {
+                Some(len) => Some(len),
+                None => return Err(Error::Stream(id, Reason::PROTOCOL_ERROR)),
+            },
+            None => None,
+        };
+        if content_length.is_some_and(|len| len > self.config.max_body_size as u64) {
+            self.respond_now(id, StatusCode::PAYLOAD_TOO_LARGE, end_stream);
+            return Ok(());
+        }
+
+        let mut stream = Stream::new(self.peer_initial_window);
+        stream.request = Some(request);
+        stream.content_length = content_length;
+        self.streams.insert(id, stream);
+        if end_stream {
+            return self.end_of_request(id);
+        }
+        Ok(())
+    }
+
+    fn data(
+        &mut self,
+        id: u32,
+        data: Bytes,
+        end_stream: bool,
+        flow_len: usize,
+    ) -> Result<(), Error> {
+        // Every DATA frame counts against the connection window, whatever
+        // becomes of its stream.
+        let flow_len = flow_len as i64;
+        if flow_len > self.recv_window {
+            return Err(Error::Connection(Reason::FLOW_CONTROL_ERROR));
+        }
+        self.recv_window -= flow_len;
+        if self.is_idle(id) {
+            return Err(Error::Connection(Reason::PROTOCOL_ERROR));
+        }
+        let Some(stream) = self.streams.get_mut(&id) else {
+            return Err(Error::Stream(id, Reason::STREAM_CLOSED));
+        };
+        if stream.remote_closed {
+            return Err(Error::Stream(id, Reason::STREAM_CLOSED));
+        }
+        if flow_len > stream.recv_window {
+            return Err(Error::Stream(id, Reason::FLOW_CONTROL_ERROR));
+        }
+        stream.recv_window -= flow_len;
+        if stream.body.len() + data.len() > self.config.max_body_size {
+            if let Some(stream) = self.streams.remove(&id) {
+                stream.cancel();
+            }
+            self.respond_now(id, StatusCode::PAYLOAD_TOO_LARGE, end_stream);
+            return Ok(());
+        }
+        stream.body.extend_from_slice(&data);
+        if end_stream {
+            return self.end_of_request(id);
+        }
+        Ok(())
+    }
+
+    /// Hands back window for the DATA received so far, once half of a window
+    /// is used up.
+    ///
+    /// The connection window is topped up regardless, since the stream
+    /// windows bound what the client can send. A stream's window is only
+    /// topped up while the bodies held for the connection fit in
+    /// [`RECEIVE_BUFFER`], except for the oldest stream still receiving,
+    /// which is always let through so that its body is handed on and frees
+    /// room for the others.
+    fn replenish(&mut self) {
+        if self.recv_window <= DEFAULT_WINDOW / 2 {
+            frame::window_update(&mut self.out, 0, (DEFAULT_WINDOW - self.recv_window) as u32);
+            self.recv_window = DEFAULT_WINDOW;
+        }
+        let receiving = |stream: &Stream| stream.request.is_some() && !stream.remote_closed;
+        let buffered: usize = self.streams.values().map(|s| s.body.len()).sum();
+        let oldest = self
+            .streams
+            .iter()
+            .filter(|(_, stream)| receiving(stream))
+            .map(|(&id, _)| id)
+            .min();
+        for (&id, stream) in self.streams.iter_mut() {
+            if !receiving(stream) || stream.recv_window > DEFAULT_WINDOW / 2 {
+                continue;
+            }
+            if buffered < RECEIVE_BUFFER || Some(id) == oldest {
+                let increment = DEFAULT_WINDOW - stream.recv_window;
+                frame::window_update(&mut self.out, id, increment as u32);
+                stream.recv_window = DEFAULT_WINDOW;
+            }
+        }
+    }
+
+    /// Hands a stream's whole request to a task running the handler.
+    fn end_of_request(&mut self, id: u32) -> Result<(), Error> {
+        let Some(stream) = self.streams.get_mut(&id) else {
+            return Ok(());
+        };
+        stream.remote_closed = true;
+        let Some(request) = stream.request.take() else {
+            return Ok(());
+        };
+        let body = std::mem::take(&mut stream.body).freeze();
+        if stream
+            .content_length
+            .is_some_and(|len| len != body.len() as u64)
+        {
+            return Err(Error::Stream(id, Reason::PROTOCOL_ERROR));
+        }
+        let mut request = request.map(|()| body);
+        *request.tls_mut() = self.tls.clone();
+        let task = tokio::spawn(respond(
+            self.handler.clone(),
+            request,
+            id,
+            self.events.clone(),
+            stream.credit.clone(),
+        ));
+        stream.task = Some(task.abort_handle());
+        Ok(())
+    }
+
+    fn window_update(&mut self, id: u32, increment: u32) -> Result<(), Error> {
+        if increment == 0 {
+            return Err(match id {
+                0 => Error::Connection(Reason::PROTOCOL_ERROR),
+                id => Error::Stream(id, Reason::PROTOCOL_ERROR),
+            });
+        }
+        if id == 0 {
+            self.send_window += i64::from(increment);
+            if self.send_window > MAX_WINDOW {
+                return Err(Error::Connection(Reason::FLOW_CONTROL_ERROR));
+            }
+            return Ok(());
+        }
+        if self.is_idle(id) {
+            return Err(Error::Connection(Reason::PROTOCOL_ERROR));
+        }
+        if let Some(stream) = self.streams.get_mut(&id) {
+            stream.send_window += i64::from(increment);
+            if stream.send_window > MAX_WINDOW {
+                return Err(Error::Stream(id, Reason::FLOW_CONTROL_ERROR));
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_settings(&mut self, params: &[(u16, u32)]) -> Result<(), Error> {
+        for &(id, value) in params {
+            match id {
+                frame::SETTINGS_ENABLE_PUSH if value > 1 => {
+                    return Err(Error::Connection(Reason::PROTOCOL_ERROR));
+                }
+                frame::SETTINGS_INITIAL_WINDOW_SIZE => {
+                    let value = i64::from(value);
+                    if value > MAX_WINDOW {
+                        return Err(Error::Connection(Reason::FLOW_CONTROL_ERROR));
+                    }
+                    let delta = value - self.peer_initial_window;
+                    self.peer_initial_window = value;
+                    for stream in self.streams.values_mut() {
+                        stream.send_window += delta;
+                        if stream.send_window > MAX_WINDOW {
+                            return Err(Error::Connection(Reason::FLOW_CONTROL_ERROR));
+                        }
+                    }
+                }
+                frame::SETTINGS_MAX_FRAME_SIZE => {
+                    let value = value as usize;
+                    if !(DEFAULT_MAX_FRAME_SIZE..=frame::MAX_MAX_FRAME_SIZE).contains(&value) {
+                        return Err(Error::Connection(Reason::PROTOCOL_ERROR));
+                    }
+                    self.peer_max_frame_size = value;
+                }
+                // The encoder keeps no dynamic table, so its size does not
+                // matter.
+                frame::SETTINGS_HEADER_TABLE_SIZE => {}
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn event(&mut self, event: Event) {
+        match event {
+            Event::Head {
+                stream,
+                head,
+                framing,
+                end_stream,
+            } => {
+                if !self.streams.contains_key(&stream) {
+                    return;
+                }
+                self.send_head(stream, &head, framing, end_stream);
+                if end_stream {
+                    self.streams.remove(&stream);
+                }
+            }
+            Event::Data {
+                stream,
+                data,
+                end_stream,
+            } => {
+                if let Some(stream) = self.streams.get_mut(&stream) {
+                    if !data.is_empty() {
+                        stream.pending.push_back(data);
+                    }
+                    stream.end_pending |= end_stream;
+                }
+            }
+            Event::Reset { stream, reason } => self.reset(stream, reason),
+        }
+    }
+
+    fn send_head(&mut self, id: u32, head: &Response<()>, framing: Framing, end_stream: bool) {
+        let mut block = Vec::with_capacity(128);
+        let status = head.status().as_u16().to_string();
+        self.encoder
+            .encode(b":status", status.as_bytes(), false, &mut block);
+        let encoder = &mut self.encoder;
+        head.for_each_field(framing, |name, value, sensitive| {
+            if !CONNECTION_SPECIFIC.contains(&name) {
+                encoder.encode(name.as_bytes(), value, sensitive, &mut block);
+            }
+        });
+        frame::headers(
+            &mut self.out,
+            id,
+            &block,
+            end_stream,
+            self.peer_max_frame_size,
+        );
+    }
+
+    /// Answers a stream that will not reach the handler. If the client is
+    /// still sending the request, it is told to stop.
+    fn respond_now(&mut self, id: u32, status: StatusCode, remote_closed: bool) {
+        let head = Response::with_status(status).map(|_| ());
+        self.send_head(id, &head, Framing::Length(0), true);
+        if !remote_closed {
+            frame::rst_stream(&mut self.out, id, Reason::NO_ERROR);
+        }
+    }
+
+    /// Writes as much queued response data as the flow-control windows
+    /// allow, a frame per stream at a time.
+    fn send_pending(&mut self) {
+        let mut done = Vec::new();
+        loop {
+            let mut progress = false;
+            for (&id, stream) in self.streams.iter_mut() {
+                let Some(front) = stream.pending.front_mut() else {
+                    if stream.end_pending {
+                        frame::data(&mut self.out, id, &[], true);
+                        done.push(id);
+                    }
+                    continue;
+                };
+                let window = stream.send_window.min(self.send_window).max(0) as usize;
+                let len = front.len().min(window).min(self.peer_max_frame_size);
+                if len == 0 {
+                    continue;
+                }
+                let chunk = front.split_to(len);
+                if front.is_empty() {
+                    stream.pending.pop_front();
+                }
+                let last = stream.pending.is_empty() && stream.end_pending;
+                frame::data(&mut self.out, id, &chunk, last);
+                stream.send_window -= len as i64;
+                self.send_window -= len as i64;
+                stream.credit.add_permits(len);
+                if last {
+                    done.push(id);
+                }
+                progress = true;
+            }
+            for id in done.drain(..) {
+                self.streams.remove(&id);
+            }
+            if !progress {
+                return;
+            }
+        }
+    }
+
+    fn reset(&mut self, id: u32, reason: Reason) {
+        frame::rst_stream(&mut self.out, id, reason);
+        if let Some(stream) = self.streams.remove(&id) {
+            stream.cancel();
+        }
+    }
+
+    /// Stops taking new streams. The open ones are finished.
+    fn go_away(&mut self) {
+        frame::go_away(&mut self.out, self.last_stream, Reason::NO_ERROR);
+        self.accepting = false;
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        for (_, stream) in self.streams.drain() {
+            stream.cancel();
+        }
+    }
+}
+
+/// Why a header block is not a request to hand to the handler.
+enum BadRequest {
+    /// Over the header list size limit: answered with `431`.
+    TooLarge,
+    /// Breaks the rules of RFC 9113, section 8: the stream is reset.
+    Malformed,
+}
+
+/// Turns the fields of a header block into a request head.
+fn request(fields: Vec<(Bytes, Bytes)>, max_header_size: usize) -> Result<Request<()>, BadRequest> {
+    if hpack::list_size(&fields) > max_header_size {
+        return Err(BadRequest::TooLarge);
+    }
+    let mut method = None;
+    let mut scheme = None;
+    let mut authority = None;
+    let mut path = None;
+    let mut headers = HeaderMap::with_capacity(fields.len());
+    let mut cookies = Vec::new();
+    let mut regular = false;
+    for (name, value) in fields {
+        if let Some(pseudo) = name.strip_prefix(b":") {
+            let slot = match pseudo {
+                b"method" => &mut method,
+                b"scheme" => &mut scheme,
+                b"authority" => &mut authority,
+                b"path" => &mut path,
+                _ => return Err(BadRequest::Malformed),
+            };
+            if regular || slot.replace(value).is_some() {
+                return Err(BadRequest::Malformed);
+            }
+            continue;
+        }
+        regular = true;
+        let name = HeaderName::from_lowercase(&name).map_err(|_| BadRequest::Malformed)?;
+        let connection_specific = CONNECTION_SPECIFIC.contains(&name.as_str());
+        if connection_specific || (name == TE && value != "trailers") {
+            return Err(BadRequest::Malformed);
+        }
+        if name == COOKIE {
+            cookies.push(value);
+            continue;
+        }
+        let mut value = HeaderValue::from_shared(value).map_err(|_| BadRequest::Malformed)?;
+        value.set_sensitive(is_sensitive(&name));
+        headers.append(name, value);
+    }
+    // Cookie crumbs may come as separate fields, but handlers expect one.
+    if !cookies.is_empty() {
+        let mut value = HeaderValue::from_bytes(&cookies.join(&b"; "[..]))
+            .map_err(|_| BadRequest::Malformed)?;
+        value.set_sensitive(true);
+        headers.insert(COOKIE, value);
+    }
+
+    let method = Method::from_bytes(&method.ok_or(BadRequest::Malformed)?)
+        .map_err(|_| BadRequest::Malformed)?;
+    let target = if method == Method::CONNECT {
+        if scheme.is_some() || path.is_some() {
+            return Err(BadRequest::Malformed);
+        }
+        authority.clone()
+    } else {
+        scheme.ok_or(BadRequest::Malformed)?;
+        path
+    };
+    let uri = Uri::from_shared(target.ok_or(BadRequest::Malformed)?)
+        .map_err(|_| BadRequest::Malformed)?;
+    if let Some(authority) = authority {
+        if !headers.contains_key(HOST) {
+            let value = HeaderValue::from_shared(authority).map_err(|_| BadRequest::Malformed)?;
+            headers.insert(HOST, value);
+        }
+    }
+
+    let mut request = Request::new(());
+    *request.method_mut() = method;
+    *request.uri_mut() = uri;
+    *request.version_mut() = Version::HTTP_2;
+    *request.headers_mut() = headers;
+    Ok(request)
+}
+
+/// Runs the handler for one stream and sends its response back to the
+/// connection, waiting for room in the stream's buffer as the body streams.
+async fn respond(
+    handler: Arc<dyn Handler>,
+    req: Request,
+    stream: u32,
+    events: mpsc::UnboundedSender<Event>,
+    credit: Arc<Semaphore>,
+) {
+    let head_only = req.method() == Method::HEAD;
+    let res = call(&*handler, req).await;
+    let framing = match res.body().size_hint() {
+        _ if !res.may_have_body() => Framing::None,
+        Some(len) => Framing::Length(len),
+        None => Framing::None,
+    };
+    let empty = !res.may_have_body() || head_only || framing == Framing::Length(0);
+    let mut body = None;
+    let head = res.map(|b| body = Some(b));
+    let head = Event::Head {
+        stream,
+        head,
+        framing,
+        end_stream: empty,
+    };
+    if events.send(head).is_err() || empty {
+        return;
+    }
+
+    let Some(mut body) = body else {
+        return;
+    };
+    while let Some(chunk) = body.next_chunk().await {
+        let mut chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                println!("response body of stream {} failed: {}", stream, e);
+                let _ = events.send(Event::Reset {
+                    stream,
+                    reason: Reason::INTERNAL_ERROR,
+                });
+                return;
+            }
+        };
+        while !chunk.is_empty() {
+            let data = chunk.split_to(chunk.len().min(CHUNK_SIZE));
+            // Fails once the stream is gone.
+            match credit.acquire_many(data.len() as u32).await {
+                Ok(permit) => permit.forget(),
+                Err(_) => return,
+            }
+            let data = Event::Data {
+                stream,
+                data,
+                end_stream: false,
+            };
+            if events.send(data).is_err() {
+                return;
+            }
+        }
+    }
+    let _ = events.send(Event::Data {
+        stream,
+        data: Bytes::new(),
+        end_stream: true,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, VecDeque};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use bytes::BytesMut;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+    use tokio::sync::watch;
+    use tokio::task::JoinHandle;
+
+    use super::frame::{self, Frame, Reason, DEFAULT_MAX_FRAME_SIZE, DEFAULT_WINDOW, HEADER_LEN};
+    use super::hpack::{Decoder, Encoder, TABLE_SIZE};
+    use super::{serve, upgrade_settings, MAX_CONCURRENT_STREAMS, PREFACE, RECEIVE_BUFFER};
+    use crate::conn::handle_client_request;
+    use crate::handler::{sync, Handler};
+    use crate::request::Request;
+    use crate::response::Response;
+    use crate::server::{Config, Draining};
+
+    /// How long to wait for a frame that should come.
+    const WAIT: Duration = Duration::from_secs(5);
+    /// How long to wait before deciding that no window is coming.
+    const STALL: Duration = Duration::from_millis(300);
+
+    #[test]
+    fn decode_upgrade_settings() {
+        assert_eq!(
+            upgrade_settings(b"AAMAAABkAAQCAAAAAAIAAAAA"),
+            Some(vec![(0x3, 100), (0x4, 0x0200_0000), (0x2, 0)])
+        );
+        assert_eq!(upgrade_settings(b""), Some(Vec::new()));
+        assert_eq!(upgrade_settings(b"AAMAAABk+AQC"), None);
+        assert_eq!(upgrade_settings(b"AAMAAAB"), None);
+    }
+
+    fn request_block(path: &str, extra: &[(&str, &str)]) -> Vec<u8> {
+        let mut block = Vec::new();
+        let pseudo = [
+            (":method", "POST"),
+            (":scheme", "http"),
+            (":authority", "localhost"),
+            (":path", path),
+        ];
+        for (name, value) in pseudo.iter().chain(extra) {
+            Encoder.encode(name.as_bytes(), value.as_bytes(), false, &mut block);
+        }
+        block
+    }
+
+    /// Answers with the path and the length of the body.
+    fn echo() -> Arc<dyn Handler> {
+        Arc::new(sync(|req: Request| {
+            let body = format!("{} {}", req.path(), req.body().len());
+            Ok(Response::new(body.into()))
+        }))
+    }
+
+    /// The client end of a connection, which keeps track of the windows the
+    /// server hands out.
+    struct Client {
+        io: DuplexStream,
+        buf: BytesMut,
+        decoder: Decoder,
+        /// Frames read but not asked for yet.
+        frames: VecDeque<Frame>,
+        conn_window: i64,
+        windows: HashMap<u32, i64>,
+    }
+
+    impl Client {
+        fn new(io: DuplexStream) -> Client {
+            Client {
+                io,
+                buf: BytesMut::new(),
+                decoder: Decoder::new(TABLE_SIZE),
+                frames: VecDeque::new(),
+                conn_window: DEFAULT_WINDOW,
+                windows: HashMap::new(),
+            }
+        }
+
+        async fn send(&mut self, out: &[u8]) {
+            self.io.write_all(out).await.unwrap();
+        }
+
+        /// Sends the preface and empty settings.
+        async fn handshake(&mut self) {
+            let mut out = BytesMut::from(PREFACE);
+            frame::settings(&mut out, &[]);
+            self.send(&out).await;
+        }
+
+        /// Opens `stream` with a `POST` to `path`.
+        async fn open(&mut self, stream: u32, path: &str, end_stream: bool) {
+            let mut out = BytesMut::new();
+            let block = request_block(path, &[]);
+            frame::headers(&mut out, stream, &block, end_stream, DEFAULT_MAX_FRAME_SIZE);
+            self.windows.insert(stream, DEFAULT_WINDOW);
+            self.send(&out).await;
+        }
+
+        /// Ends the body of `stream`.
+        async fn end(&mut self, stream: u32) {
+            let mut out = BytesMut::new();
+            frame::data(&mut out, stream, &[], true);
+            self.send(&out).await;
+        }
+
+        /// Reads the next frame, applying it to the windows if it is a
+        /// `WINDOW_UPDATE`. `None` if nothing comes within `wait` or the
+        /// server closed the connection.
+        async fn read(&mut self, wait: Duration) -> Option<Frame> {
+            loop {
+                if let Some(frame) = frame::decode(&mut self.buf, DEFAULT_MAX_FRAME_SIZE).unwrap() {
+                    if let Frame::WindowUpdate { stream, increment } = frame {
+                        let window = match stream {
+                            0 => &mut self.conn_window,
+                            stream => self.windows.entry(stream).or_default(),
+                        };
+                        *window += i64::from(increment);
+                    }
+                    return Some(frame);
+                }
+                match tokio::time::timeout(wait, self.io.read_buf(&mut self.buf)).await {
+                    Ok(Ok(len)) if len > 0 => {}
+                    _ => return None,
+                }
+            }
+        }
+
+        /// Sends up to `len` bytes of body on `stream` as the windows allow.
+        /// Returns how many were sent before the server stopped handing out
+        /// window.
+        async fn pump(&mut self, stream: u32, len: usize) -> usize {
+            let mut sent = 0;
+            while sent < len {
+                let window = self.windows[&stream].min(self.conn_window).max(0) as usize;
+                let n = window.min(len - sent).min(DEFAULT_MAX_FRAME_SIZE);
+                if n == 0 {
+                    match self.read(STALL).await {
+                        Some(Frame::WindowUpdate { .. }) => {}
+                        Some(frame) => self.frames.push_back(frame),
+                        None => break,
+                    }
+                    continue;
+                }
+                let mut out = BytesMut::new();
+                frame::data(&mut out, stream, &vec![b'x'; n], false);
+                self.send(&out).await;
+                sent += n;
+                self.conn_window -= n as i64;
+                *self.windows.get_mut(&stream).unwrap() -= n as i64;
+            }
+            sent
+        }
+
+        /// The first frame, already read or still to come, that `pred` holds
+        /// for.
+        async fn find(&mut self, pred: impl Fn(&Frame) -> bool) -> Frame {
+            if let Some(i) = self.frames.iter().position(&pred) {
+                return self.frames.remove(i).unwrap();
+            }
+            loop {
+                let frame = self.read(WAIT).await.expect("frame never came");
+                if pred(&frame) {
+                    return frame;
+                }
+                self.frames.push_back(frame);
+            }
+        }
+
+        /// The status and body of the response on `stream`.
+        async fn response(&mut self, stream: u32) -> (String, String) {
+            let mut status = String::new();
+            let mut body = Vec::new();
+            loop {
+                let frame = self
+                    .find(|frame| match frame {
+                        Frame::Headers { stream: id, .. }
+                        | Frame::Data { stream: id, .. }
+                        | Frame::RstStream { stream: id, .. } => *id == stream,
+                        _ => false,
+                    })
+                    .await;
+                match frame {
+                    Frame::Headers {
+                        block, end_stream, ..
+                    } => {
+                        let fields = self.decoder.decode(&block).unwrap();
+                        status = String::from_utf8(fields[0].1.to_vec()).unwrap();
+                        if end_stream {
+                            break;
+                        }
+                    }
+                    Frame::Data {
+                        data, end_stream, ..
+                    } => {
+                        body.extend_from_slice(&data);
+                        if end_stream {
+                            break;
+                        }
+                    }
+                    frame => panic!("stream {} got {:?}", stream, frame),
+                }
+            }
+            (status, String::from_utf8(body).unwrap())
+        }
+    }
+
+    /// Serves a connection to a client that has sent its preface.
+    async fn connect() -> (Client, JoinHandle<anyhow::Result<()>>, watch::Sender<bool>) {
+        let (client, server) = tokio::io::duplex(1 << 20);
+        let (shutdown, draining) = Draining::channel();
+        let config = Arc::new(Config::default());
+        let server = tokio::spawn(serve(
+            server,
+            BytesMut::new(),
+            None,
+            None,
+            config,
+            echo(),
+            draining,
+        ));
+        let mut client = Client::new(client);
+        client.handshake().await;
+        (client, server, shutdown)
+    }
+
+    fn ok(body: &str) -> (String, String) {
+        ("200".to_string(), body.to_string())
+    }
+
+    #[tokio::test]
+    async fn flow_control_bounds_buffered_bodies() {
+        let (mut client, server, _shutdown) = connect().await;
+        client.open(1, "/a", false).await;
+        client.open(3, "/b", false).await;
+
+        // Stream 3 is given window until the connection holds a buffer's
+        // worth of body, and then it stalls.
+        let window = DEFAULT_WINDOW as usize;
+        let mut sent = client.pump(3, 4 * RECEIVE_BUFFER).await;
+        assert!((RECEIVE_BUFFER..RECEIVE_BUFFER + window).contains(&sent));
+        assert_eq!(client.windows[&3], 0);
+
+        // Stream 1 is the oldest, so its body still gets through.
+        assert_eq!(client.pump(1, 200_000).await, 200_000);
+        client.end(1).await;
+        assert_eq!(client.response(1).await, ok("/a 200000"));
+
+        // Now stream 3 is. A newer stream gets no more than its first
+        // window, and sending past it resets the stream.
+        client.open(5, "/c", false).await;
+        assert_eq!(client.pump(5, 2 * window).await, window);
+        let mut out = BytesMut::new();
+        frame::data(&mut out, 5, b"x", false);
+        client.send(&out).await;
+        let reset = client
+            .find(|frame| matches!(frame, Frame::RstStream { .. }))
+            .await;
+        assert_eq!(
+            reset,
+            Frame::RstStream {
+                stream: 5,
+                reason: Reason::FLOW_CONTROL_ERROR
+            }
+        );
+
+        sent += client.pump(3, 100_000).await;
+        client.end(3).await;
+        assert_eq!(client.response(3).await, ok(&format!("/b {}", sent)));
+
+        drop(client);
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn upgrade_from_http1() {
+        let (io, server) = tokio::io::duplex(1 << 20);
+        let (_shutdown, draining) = Draining::channel();
+        let server = tokio::spawn(handle_client_request(
+            server,
+            "127.0.0.1:0".parse().unwrap(),
+            None,
+            Arc::new(Config::default()),
+            echo(),
+            draining,
+        ));
+        let mut client = Client::new(io);
+        client
+            .send(b"POST /up HTTP/1.1\r\nHost: localhost\r\nContent-Length: 2\r\nConnection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\nHTTP2-Settings: AAMAAABk\r\n\r\nhi")
+            .await;
+        let end = loop {
+            if let Some(i) = client.buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                break i + 4;
+            }
+            assert!(client.io.read_buf(&mut client.buf).await.unwrap() > 0);
+        };
+        let head = client.buf.split_to(end);
+        assert!(head.starts_with(b"HTTP/1.1 101 Switching Protocols\r\n"));
+
+        // The upgrading request is answered on stream 1, and the client can
+        // open more.
+        client.handshake().await;
+        assert_eq!(client.response(1).await, ok("/up 2"));
+        client.open(3, "/next", true).await;
+        assert_eq!(client.response(3).await, ok("/next 0"));
+
+        drop(client);
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn drain_with_goaway() {
+        let (mut client, server, shutdown) = connect().await;
+        client.open(1, "/a", false).await;
+        client.open(3, "/b", true).await;
+        assert_eq!(client.response(3).await, ok("/b 0"));
+
+        shutdown.send(true).unwrap();
+        let go_away = client
+            .find(|frame| matches!(frame, Frame::GoAway { .. }))
+            .await;
+        assert_eq!(
+            go_away,
+            Frame::GoAway {
+                last_stream: 3,
+                reason: Reason::NO_ERROR
+            }
+        );
+
+        // Streams opened after the GOAWAY are ignored; open ones finish, and
+        // then the connection closes.
+        client.open(5, "/c", true).await;
+        let mut out = BytesMut::new();
+        frame::data(&mut out, 1, b"hi", true);
+        client.send(&out).await;
+        assert_eq!(client.response(1).await, ok("/a 2"));
+        server.await.unwrap().unwrap();
+        while let Some(frame) = client.read(WAIT).await {
+            client.frames.push_back(frame);
+        }
+        assert!(!client
+            .frames
+            .iter()
+            .any(|frame| matches!(frame, Frame::Headers { stream: 5, .. })));
+    }
+
+    #[tokio::test]
+    async fn continuation_frames() {
+        let (mut client, server, _shutdown) = connect().await;
+        let pad = "p".repeat(100);
+        let block = request_block("/split", &[("x-pad", &pad)]);
+        let mut out = BytesMut::new();
+        frame::headers(&mut out, 1, &block, true, 16);
+        client.send(&out).await;
+        assert_eq!(client.response(1).await, ok("/split 0"));
+
+        // Any frame but the next CONTINUATION of the block breaks the
+        // connection.
+        let mut out = BytesMut::new();
+        frame::headers(
+            &mut out,
+            3,
+            &request_block("/split", &[("x-pad", &pad)]),
+            true,
+            16,
+        );
+        out.truncate(HEADER_LEN + 16);
+        frame::window_update(&mut out, 0, 1);
+        client.send(&out).await;
+        let go_away = client
+            .find(|frame| matches!(frame, Frame::GoAway { .. }))
+            .await;
+        assert_eq!(
+            go_away,
+            Frame::GoAway {
+                last_stream: 1,
+                reason: Reason::PROTOCOL_ERROR
+            }
+        );
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn refuse_streams_over_the_limit() {
+        let (mut client, server, _shutdown) = connect().await;
+        let settings = client
+            .find(|frame| matches!(frame, Frame::Settings { ack: false, .. }))
+            .await;
+        let Frame::Settings { params, .. } = settings else {
+            unreachable!()
+        };
+        let limit = (
+            frame::SETTINGS_MAX_CONCURRENT_STREAMS,
+            MAX_CONCURRENT_STREAMS as u32,
+        );
+        assert!(params.contains(&limit));
+
+        let streams = MAX_CONCURRENT_STREAMS as u32;
+        for i in 0..streams {
+            client.open(2 * i + 1, "/a", false).await;
+        }
+        let over = 2 * streams + 1;
+        client.open(over, "/b", true).await;
+        let reset = client
+            .find(|frame| matches!(frame, Frame::RstStream { .. }))
+            .await;
+        assert_eq!(
+            reset,
+            Frame::RstStream {
+                stream: over,
+                reason: Reason::REFUSED_STREAM
+            }
+        );
+
+        // A stream that completes makes room for another.
+        client.end(1).await;
+        assert_eq!(client.response(1).await, ok("/a 0"));
+        client.open(over + 2, "/c", true).await;
+        assert_eq!(client.response(over + 2).await, ok("/c 0"));
+
+        drop(client);
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn multiplex_streams() {
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        let handler: Arc<dyn Handler> = Arc::new(sync(|req: Request| {
+            let host = req.host().unwrap_or_default().to_string();
+            let body = format!("{} {} {}", host, req.path(), req.body().len());
+            Ok(Response::new(body.into()))
+        }));
+        let (_shutdown, draining) = Draining::channel();
+        let config = Arc::new(Config::default());
+        let server = tokio::spawn(serve(
+            server,
+            BytesMut::new(),
+            None,
+            None,
+            config,
+            handler,
+            draining,
+        ));
+
+        // Stream 1 sends its body after stream 3 is complete, and stream 5
+        // has a field HTTP/2 does not allow.
+        let mut out = BytesMut::from(PREFACE);
+        frame::settings(&mut out, &[]);
+        let max = DEFAULT_MAX_FRAME_SIZE;
+        frame::headers(&mut out, 1, &request_block("/a", &[]), false, max);
+        frame::headers(&mut out, 3, &request_block("/b", &[]), true, max);
+        let malformed = request_block("/c", &[("connection", "close")]);
+        frame::headers(&mut out, 5, &malformed, true, max);
+        frame::data(&mut out, 1, b"hello", true);
+        client.write_all(&out).await.unwrap();
+
+        let mut decoder = Decoder::new(TABLE_SIZE);
+        let mut buf = BytesMut::new();
+        let mut bodies = HashMap::new();
+        let mut settings_acked = false;
+        let mut reset = None;
+        let mut ended = 0;
+        while ended < 2 {
+            let Some(frame) = frame::decode(&mut buf, max).unwrap() else {
+                assert!(client.read_buf(&mut buf).await.unwrap() > 0);
+                continue;
+            };
+            match frame {
+                Frame::Settings { ack: true, .. } => settings_acked = true,
+                Frame::Headers {
+                    stream,
+                    block,
+                    end_stream,
+                    ..
+                } => {
+                    let fields = decoder.decode(&block).unwrap();
+                    assert_eq!(fields[0], (":status".into(), "200".into()), "{}", stream);
+                    assert!(!end_stream);
+                    bodies.insert(stream, Vec::new());
+                }
+                Frame::Data {
+                    stream,
+                    data,
+                    end_stream,
+                    ..
+                } => {
+                    bodies.get_mut(&stream).unwrap().extend_from_slice(&data);
+                    ended += usize::from(end_stream);
+                }
+                Frame::RstStream { stream, reason } => reset = Some((stream, reason)),
+                _ => {}
+            }
+        }
+        assert!(settings_acked);
+        assert_eq!(reset, Some((5, Reason::PROTOCOL_ERROR)));
+        assert_eq!(bodies[&1], b"localhost /a 5");
+        assert_eq!(bodies[&3], b"localhost /b 0");
+
+        drop(client);
+        server.await.unwrap().unwrap();
+    }
+}
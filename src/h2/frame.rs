@@ -0,0 +1,396 @@
+//! HTTP/2 frames (RFC 9113, sections 4 and 6).
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+/// What a client sends before its first frame.
+pub(crate) const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+pub(crate) const HEADER_LEN: usize = 9;
+pub(crate) const DEFAULT_MAX_FRAME_SIZE: usize = 16_384;
+pub(crate) const MAX_MAX_FRAME_SIZE: usize = (1 << 24) - 1;
+pub(crate) const DEFAULT_WINDOW: i64 = 65_535;
+pub(crate) const MAX_WINDOW: i64 = (1 << 31) - 1;
+
+pub(crate) const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+pub(crate) const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+pub(crate) const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+pub(crate) const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+pub(crate) const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+pub(crate) const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const PRIORITY: u8 = 0x2;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY_FLAG: u8 = 0x20;
+
+/// The error code of a `RST_STREAM` or `GOAWAY` frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Reason(pub(crate) u32);
+
+impl Reason {
+    pub(crate) const NO_ERROR: Reason = Reason(0x0);
+    pub(crate) const PROTOCOL_ERROR: Reason = Reason(0x1);
+    pub(crate) const INTERNAL_ERROR: Reason = Reason(0x2);
+    pub(crate) const FLOW_CONTROL_ERROR: Reason = Reason(0x3);
+    pub(crate) const STREAM_CLOSED: Reason = Reason(0x5);
+    pub(crate) const FRAME_SIZE_ERROR: Reason = Reason(0x6);
+    pub(crate) const REFUSED_STREAM: Reason = Reason(0x7);
+    pub(crate) const COMPRESSION_ERROR: Reason = Reason(0x9);
+    pub(crate) const ENHANCE_YOUR_CALM: Reason = Reason(0xb);
+}
+
+/// A broken rule, and how much of the connection it takes down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Error {
+    /// The connection is closed with `GOAWAY`.
+    Connection(Reason),
+    /// The stream is closed with `RST_STREAM`; the connection carries on.
+    Stream(u32, Reason),
+}
+
+/// A frame received from the client. Padding is already removed.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Frame {
+    Data {
+        stream: u32,
+        data: Bytes,
+        end_stream: bool,
+        /// The frame's whole payload, padding included, which is what flow
+        /// control counts.
+        flow_len: usize,
+    },
+    Headers {
+        stream: u32,
+        block: Bytes,
+        end_stream: bool,
+        end_headers: bool,
+        /// The stream this one depends on, if the frame has priority fields.
+        dependency: Option<u32>,
+    },
+    Priority {
+        stream: u32,
+        dependency: u32,
+    },
+    RstStream {
+        stream: u32,
+        reason: Reason,
+    },
+    Settings {
+        ack: bool,
+        params: Vec<(u16, u32)>,
+    },
+    PushPromise,
+    Ping {
+        ack: bool,
+        payload: [u8; 8],
+    },
+    GoAway {
+        last_stream: u32,
+        reason: Reason,
+    },
+    WindowUpdate {
+        stream: u32,
+        increment: u32,
+    },
+    Continuation {
+        stream: u32,
+        block: Bytes,
+        end_headers: bool,
+    },
+    /// A frame of an extension type, which is ignored.
+    Unknown,
+}
+
+/// Takes the next whole frame off `buf`. Returns `None` if `buf` does not
+/// hold one yet.
+pub(crate) fn decode(buf: &mut BytesMut, max_frame_size: usize) -> Result<Option<Frame>, Error> {
+    if buf.len() < HEADER_LEN {
+        return Ok(None);
+    }
+    let len = (usize::from(buf[0]) << 16) | (usize::from(buf[1]) << 8) | usize::from(buf[2]);
+    if len > max_frame_size {
+        return Err(Error::Connection(Reason::FRAME_SIZE_ERROR));
+    }
+    if buf.len() < HEADER_LEN + len {
+        return Ok(None);
+    }
+    let kind = buf[3];
+    let flags = buf[4];
+    let stream = u32::from_be_bytes([buf[5], buf[6], buf[7], buf[8]]) & 0x7fff_ffff;
+    buf.advance(HEADER_LEN);
+    let mut payload = buf.split_to(len).freeze();
+
+    let on_stream = matches!(
+        kind,
+        DATA | HEADERS | PRIORITY | RST_STREAM | PUSH_PROMISE | CONTINUATION
+    );
+    let on_connection = matches!(kind, SETTINGS | PING | GOAWAY);
+    if (on_stream && stream == 0) || (on_connection && stream != 0) {
+        return Err(Error::Connection(Reason::PROTOCOL_ERROR));
+    }
+
+    let frame = match kind {
+        DATA => {
+            let flow_len = payload.len();
+            let data = unpad(payload, flags)?;
+            Frame::Data {
+                stream,
+                data,
+                end_stream: flags & END_STREAM != 0,
+                flow_len,
+            }
+        }
+        HEADERS => {
+            let mut block = unpad(payload, flags)?;
+            let dependency = if flags & PRIORITY_FLAG != 0 {
+                if block.len() < 5 {
+                    return Err(Error::Connection(Reason::FRAME_SIZE_ERROR));
+                }
+                let dependency = block.get_u32() & 0x7fff_ffff;
+                block.advance(1);
+                Some(dependency)
+            } else {
+                None
+            };
+            Frame::Headers {
+                stream,
+                block,
+                end_stream: flags & END_STREAM != 0,
+                end_headers: flags & END_HEADERS != 0,
+                dependency,
+            }
+        }
+        PRIORITY => {
+            if payload.len() != 5 {
+                return Err(Error::Stream(stream, Reason::FRAME_SIZE_ERROR));
+            }
+            Frame::Priority {
+                stream,
+                dependency: payload.get_u32() & 0x7fff_ffff,
+            }
+        }
+        RST_STREAM => {
+            if payload.len() != 4 {
+                return Err(Error::Connection(Reason::FRAME_SIZE_ERROR));
+            }
+            Frame::RstStream {
+                stream,
+                reason: Reason(payload.get_u32()),
+            }
+        }
+        SETTINGS => {
+            let ack = flags & ACK != 0;
+            if (ack && !payload.is_empty()) || payload.len() % 6 != 0 {
+                return Err(Error::Connection(Reason::FRAME_SIZE_ERROR));
+            }
+            let mut params = Vec::with_capacity(payload.len() / 6);
+            while payload.has_remaining() {
+                params.push((payload.get_u16(), payload.get_u32()));
+            }
+            Frame::Settings { ack, params }
+        }
+        PUSH_PROMISE => Frame::PushPromise,
+        PING => {
+            let payload: [u8; 8] = payload[..]
+                .try_into()
+                .map_err(|_| Error::Connection(Reason::FRAME_SIZE_ERROR))?;
+            Frame::Ping {
+                ack: flags & ACK != 0,
+                payload,
+            }
+        }
+        GOAWAY => {
+            if payload.len() < 8 {
+                return Err(Error::Connection(Reason::FRAME_SIZE_ERROR));
+            }
+            Frame::GoAway {
+                last_stream: payload.get_u32() & 0x7fff_ffff,
+                reason: Reason(payload.get_u32()),
+            }
+        }
+        WINDOW_UPDATE => {
+            if payload.len() != 4 {
+                return Err(Error::Connection(Reason::FRAME_SIZE_ERROR));
+            }
+            Frame::WindowUpdate {
+                stream,
+                increment: payload.get_u32() & 0x7fff_ffff,
+            }
+        }
+        CONTINUATION => Frame::Continuation {
+            stream,
+            block: payload,
+            end_headers: flags & END_HEADERS != 0,
+        },
+        _ => Frame::Unknown,
+    };
+    Ok(Some(frame))
+}
+
+/// Strips the padding of a `DATA` or `HEADERS` payload.
+fn unpad(mut payload: Bytes, flags: u8) -> Result<Bytes, Error> {
+    if flags & PADDED == 0 {
+        return Ok(payload);
+    }
+    let Some(&pad) = payload.first() else {
+        return Err(Error::Connection(Reason::FRAME_SIZE_ERROR));
+    };
+    payload.advance(1);
+    if usize::from(pad) > payload.len() {
+        return Err(Error::Connection(Reason::PROTOCOL_ERROR));
+    }
+    payload.truncate(payload.len() - usize::from(pad));
+    Ok(payload)
+}
+
+fn head(dst: &mut BytesMut, len: usize, kind: u8, flags: u8, stream: u32) {
+    dst.reserve(HEADER_LEN + len);
+    dst.put_uint(len as u64, 3);
+    dst.put_u8(kind);
+    dst.put_u8(flags);
+    dst.put_u32(stream);
+}
+
+pub(crate) fn settings(dst: &mut BytesMut, params: &[(u16, u32)]) {
+    head(dst, params.len() * 6, SETTINGS, 0, 0);
+    for &(id, value) in params {
+        dst.put_u16(id);
+        dst.put_u32(value);
+    }
+}
+
+pub(crate) fn settings_ack(dst: &mut BytesMut) {
+    head(dst, 0, SETTINGS, ACK, 0);
+}
+
+pub(crate) fn ping_ack(dst: &mut BytesMut, payload: [u8; 8]) {
+    head(dst, 8, PING, ACK, 0);
+    dst.put_slice(&payload);
+}
+
+pub(crate) fn go_away(dst: &mut BytesMut, last_stream: u32, reason: Reason) {
+    head(dst, 8, GOAWAY, 0, 0);
+    dst.put_u32(last_stream);
+    dst.put_u32(reason.0);
+}
+
+pub(crate) fn rst_stream(dst: &mut BytesMut, stream: u32, reason: Reason) {
+    head(dst, 4, RST_STREAM, 0, stream);
+    dst.put_u32(reason.0);
+}
+
+pub(crate) fn window_update(dst: &mut BytesMut, stream: u32, increment: u32) {
+    head(dst, 4, WINDOW_UPDATE, 0, stream);
+    dst.put_u32(increment);
+}
+
+pub(crate) fn data(dst: &mut BytesMut, stream: u32, data: &[u8], end_stream: bool) {
+    let flags = if end_stream { END_STREAM } else { 0 };
+    head(dst, data.len(), DATA, flags, stream);
+    dst.put_slice(data);
+}
+
+/// Writes a header block as a `HEADERS` frame followed by as many
+/// `CONTINUATION` frames as `max_frame_size` requires.
+pub(crate) fn headers(
+    dst: &mut BytesMut,
+    stream: u32,
+    block: &[u8],
+    end_stream: bool,
+    max_frame_size: usize,
+) {
+    let mut chunks = block.chunks(max_frame_size).peekable();
+    let mut kind = HEADERS;
+    let mut flags = if end_stream { END_STREAM } else { 0 };
+    loop {
+        let chunk = chunks.next().unwrap_or_default();
+        if chunks.peek().is_none() {
+            flags |= END_HEADERS;
+        }
+        head(dst, chunk.len(), kind, flags, stream);
+        dst.put_slice(chunk);
+        if flags & END_HEADERS != 0 {
+            return;
+        }
+        kind = CONTINUATION;
+        flags = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, headers, Error, Frame, Reason, DEFAULT_MAX_FRAME_SIZE};
+    use bytes::BytesMut;
+
+    #[test]
+    fn decode_frames() {
+        let mut buf = BytesMut::new();
+        // A padded DATA frame with END_STREAM, in two reads.
+        buf.extend_from_slice(&[0, 0, 8, 0x0, 0x9, 0, 0, 0, 1, 2, b'h', b'e']);
+        assert_eq!(decode(&mut buf, DEFAULT_MAX_FRAME_SIZE), Ok(None));
+        buf.extend_from_slice(&[b'l', b'l', b'o', 0, 0]);
+        assert_eq!(
+            decode(&mut buf, DEFAULT_MAX_FRAME_SIZE),
+            Ok(Some(Frame::Data {
+                stream: 1,
+                data: "hello".into(),
+                end_stream: true,
+                flow_len: 8,
+            }))
+        );
+        assert!(buf.is_empty());
+
+        let mut buf = BytesMut::from(&[0, 0, 6, 0x4, 0, 0, 0, 0, 0, 0, 0x4, 0, 1, 0, 0][..]);
+        assert_eq!(
+            decode(&mut buf, DEFAULT_MAX_FRAME_SIZE),
+            Ok(Some(Frame::Settings {
+                ack: false,
+                params: vec![(0x4, 0x10000)],
+            }))
+        );
+
+        let error = |bytes: &[u8]| decode(&mut BytesMut::from(bytes), DEFAULT_MAX_FRAME_SIZE);
+        // Too large, DATA on stream 0, a short PING, and too much padding.
+        let protocol = Err(Error::Connection(Reason::PROTOCOL_ERROR));
+        let frame_size = Err(Error::Connection(Reason::FRAME_SIZE_ERROR));
+        assert_eq!(error(&[0, 0x40, 1, 0x0, 0, 0, 0, 0, 1]), frame_size);
+        assert_eq!(error(&[0, 0, 0, 0x0, 0, 0, 0, 0, 0]), protocol);
+        assert_eq!(error(&[0, 0, 1, 0x6, 0, 0, 0, 0, 0, 0]), frame_size);
+        assert_eq!(error(&[0, 0, 2, 0x0, 0x8, 0, 0, 0, 1, 2, 0]), protocol);
+        assert_eq!(
+            error(&[0, 0, 4, 0x2, 0, 0, 0, 0, 3, 0, 0, 0, 0]),
+            Err(Error::Stream(3, Reason::FRAME_SIZE_ERROR))
+        );
+    }
+
+    #[test]
+    fn split_header_blocks() {
+        let mut buf = BytesMut::new();
+        headers(&mut buf, 3, &[7; 10], true, 4);
+        let mut blocks = Vec::new();
+        while let Some(frame) = decode(&mut buf, DEFAULT_MAX_FRAME_SIZE).unwrap() {
+            blocks.push(frame);
+        }
+        assert_eq!(blocks.len(), 3);
+        assert!(matches!(
+            &blocks[0],
+            Frame::Headers { stream: 3, end_stream: true, end_headers: false, block, .. } if block.len() == 4
+        ));
+        assert!(matches!(
+            &blocks[2],
+            Frame::Continuation { stream: 3, end_headers: true, block } if block.len() == 2
+        ));
+    }
+}
@@ -0,0 +1,442 @@
+//! HPACK header compression (RFC 7541).
+//!
+//! The decoder keeps the dynamic table the client indexes into. The encoder
+//! never adds to the client's table: it refers to the static table where it
+//! can and sends everything else as literals.
+
+use std::collections::VecDeque;
+
+use bytes::{Buf, Bytes};
+use thiserror::Error;
+
+use super::huffman;
+
+/// The dynamic table size we advertise, the default.
+pub(crate) const TABLE_SIZE: usize = 4096;
+
+/// Every table entry costs this much on top of its name and value.
+const ENTRY_OVERHEAD: usize = 32;
+
+/// Integers beyond this are not a sensible length or index.
+const MAX_INTEGER: usize = 1 << 24;
+
+static STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// A header block that cannot be decoded. Always a connection error, since
+/// the decoder's table may no longer match the client's.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub(crate) enum HpackError {
+    #[error("header block ends early")]
+    Truncated,
+    #[error("integer too large")]
+    IntegerOverflow,
+    #[error("index {0} is not in the table")]
+    InvalidIndex(usize),
+    #[error("invalid Huffman code")]
+    InvalidHuffman,
+    #[error("table size {0} above the limit")]
+    InvalidTableSize(usize),
+    #[error("table size update after a header field")]
+    LateTableSizeUpdate,
+}
+
+/// Decodes header blocks, keeping the dynamic table between them.
+#[derive(Debug)]
+pub(crate) struct Decoder {
+    table: DynamicTable,
+    /// The most the client may set the table size to.
+    max_size: usize,
+}
+
+impl Decoder {
+    pub(crate) fn new(max_size: usize) -> Decoder {
+        Decoder {
+            table: DynamicTable::new(max_size),
+            max_size,
+        }
+    }
+
+    /// Decodes a whole header block into its fields, in order.
+    pub(crate) fn decode(&mut self, mut src: &[u8]) -> Result<Vec<(Bytes, Bytes)>, HpackError> {
+        let mut fields = Vec::new();
+        while let Some(&first) = src.first() {
+            if first & 0x80 != 0 {
+                // Indexed field.
+                let index = decode_integer(&mut src, 7)?;
+                fields.push(self.entry(index)?);
+            } else if first & 0x40 != 0 {
+                // Literal with incremental indexing.
+                let field = self.literal(&mut src, 6)?;
+                self.table.insert(field.clone());
+                fields.push(field);
+            } else if first & 0x20 != 0 {
+                // Dynamic table size update, only allowed before any field.
+                if !fields.is_empty() {
+                    return Err(HpackError::LateTableSizeUpdate);
+                }
+                let size = decode_integer(&mut src, 5)?;
+                if size > self.max_size {
+                    return Err(HpackError::InvalidTableSize(size));
+                }
+                self.table.resize(size);
+            } else {
+                // Literal without indexing, or never indexed.
+                fields.push(self.literal(&mut src, 4)?);
+            }
+        }
+        Ok(fields)
+    }
+
+    fn entry(&self, index: usize) -> Result<(Bytes, Bytes), HpackError> {
+        match index {
+            0 => Err(HpackError::InvalidIndex(0)),
+            1..=61 => {
+                let (name, value) = STATIC_TABLE[index - 1];
+                Ok((
+                    Bytes::from_static(name.as_bytes()),
+                    Bytes::from_static(value.as_bytes()),
+                ))
+            }
+            _ => self
+                .table
+                .get(index - 62)
+                .cloned()
+                .ok_or(HpackError::InvalidIndex(index)),
+        }
+    }
+
+    fn literal(&self, src: &mut &[u8], prefix: u8) -> Result<(Bytes, Bytes), HpackError> {
+        let index = decode_integer(src, prefix)?;
+        let name = if index == 0 {
+            decode_string(src)?
+        } else {
+            self.entry(index)?.0
+        };
+        Ok((name, decode_string(src)?))
+    }
+}
+
+/// The entries the client added, newest first.
+#[derive(Debug)]
+struct DynamicTable {
+    entries: VecDeque<(Bytes, Bytes)>,
+    size: usize,
+    max_size: usize,
+}
+
+impl DynamicTable {
+    fn new(max_size: usize) -> DynamicTable {
+        DynamicTable {
+            entries: VecDeque::new(),
+            size: 0,
+            max_size,
+        }
+    }
+
+    fn get(&self, index: usize) -> Option<&(Bytes, Bytes)> {
+        self.entries.get(index)
+    }
+
+    fn insert(&mut self, entry: (Bytes, Bytes)) {
+        let size = entry_size(&entry);
+        if size > self.max_size {
+            // An entry larger than the table empties it.
+            self.entries.clear();
+            self.size = 0;
+            return;
+        }
+        self.evict(self.max_size - size);
+        self.size += size;
+        self.entries.push_front(entry);
+    }
+
+    fn resize(&mut self, max_size: usize) {
+        self.max_size = max_size;
+        self.evict(max_size);
+    }
+
+    /// Drops the oldest entries until the table fits in `size`.
+    fn evict(&mut self, size: usize) {
+        while self.size > size {
+            let Some(entry) = self.entries.pop_back() else {
+                break;
+            };
+            self.size -= entry_size(&entry);
+        }
+    }
+}
+
+fn entry_size((name, value): &(Bytes, Bytes)) -> usize {
+    name.len() + value.len() + ENTRY_OVERHEAD
+}
+
+/// The size of a header list as `SETTINGS_MAX_HEADER_LIST_SIZE` counts it.
+pub(crate) fn list_size<'a>(fields: impl IntoIterator<Item = &'a (Bytes, Bytes)>) -> usize {
+    fields.into_iter().map(entry_size).sum()
+}
+
+fn decode_integer(src: &mut &[u8], prefix: u8) -> Result<usize, HpackError> {
+    let mask = (1u8 << prefix) - 1;
+    let Some(&first) = src.first() else {
+        return Err(HpackError::Truncated);
+    };
+    src.advance(1);
+    let mut value = usize::from(first & mask);
+    if value < usize::from(mask) {
+        return Ok(value);
+    }
+    let mut shift = 0;
+    loop {
+        let Some(&byte) = src.first() else {
+            return Err(HpackError::Truncated);
+        };
+        src.advance(1);
+        value += usize::from(byte & 0x7f) << shift;
+        if value > MAX_INTEGER {
+            return Err(HpackError::IntegerOverflow);
+        }
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
+
+fn decode_string(src: &mut &[u8]) -> Result<Bytes, HpackError> {
+    let huffman = src.first().is_some_and(|b| b & 0x80 != 0);
+    let len = decode_integer(src, 7)?;
+    if src.len() < len {
+        return Err(HpackError::Truncated);
+    }
+    let (raw, rest) = src.split_at(len);
+    *src = rest;
+    if huffman {
+        huffman::decode(raw)
+            .map(Bytes::from)
+            .ok_or(HpackError::InvalidHuffman)
+    } else {
+        Ok(Bytes::copy_from_slice(raw))
+    }
+}
+
+/// Encodes header blocks without a dynamic table.
+#[derive(Debug, Default)]
+pub(crate) struct Encoder;
+
+impl Encoder {
+    /// Appends one field to a header block. Sensitive values are marked never
+    /// to be indexed by intermediaries.
+    pub(crate) fn encode(&mut self, name: &[u8], value: &[u8], sensitive: bool, dst: &mut Vec<u8>) {
+        let mut name_index = 0;
+        for (i, &(n, v)) in STATIC_TABLE.iter().enumerate() {
+            if n.as_bytes() != name {
+                continue;
+            }
+            if v.as_bytes() == value && !sensitive {
+                encode_integer(i + 1, 7, 0x80, dst);
+                return;
+            }
+            if name_index == 0 {
+                name_index = i + 1;
+            }
+        }
+        let flags = if sensitive { 0x10 } else { 0x00 };
+        encode_integer(name_index, 4, flags, dst);
+        if name_index == 0 {
+            encode_string(name, dst);
+        }
+        encode_string(value, dst);
+    }
+}
+
+fn encode_integer(value: usize, prefix: u8, flags: u8, dst: &mut Vec<u8>) {
+    let mask = (1usize << prefix) - 1;
+    if value < mask {
+        dst.push(flags | value as u8);
+        return;
+    }
+    dst.push(flags | mask as u8);
+    let mut rest = value - mask;
+    while rest >= 0x80 {
+        dst.push(0x80 | (rest & 0x7f) as u8);
+        rest >>= 7;
+    }
+    dst.push(rest as u8);
+}
+
+/// Writes a string literal, Huffman coded if that makes it shorter.
+fn encode_string(src: &[u8], dst: &mut Vec<u8>) {
+    let huffman_len = huffman::encoded_len(src);
+    if huffman_len < src.len() {
+        encode_integer(huffman_len, 7, 0x80, dst);
+        huffman::encode(src, dst);
+    } else {
+        encode_integer(src.len(), 7, 0x00, dst);
+        dst.extend_from_slice(src);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Decoder, Encoder, HpackError, TABLE_SIZE};
+
+    fn fields(decoded: Vec<(bytes::Bytes, bytes::Bytes)>) -> Vec<(String, String)> {
+        decoded
+            .into_iter()
+            .map(|(n, v)| {
+                (
+                    String::from_utf8(n.to_vec()).unwrap(),
+                    String::from_utf8(v.to_vec()).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    fn pairs(list: &[(&str, &str)]) -> Vec<(String, String)> {
+        list.iter()
+            .map(|(n, v)| (n.to_string(), v.to_string()))
+            .collect()
+    }
+
+    // The request examples of RFC 7541, appendix C.3 and C.4.
+    #[test]
+    fn decode_requests() {
+        let first = pairs(&[
+            (":method", "GET"),
+            (":scheme", "http"),
+            (":path", "/"),
+            (":authority", "www.example.com"),
+        ]);
+        let mut second = first.clone();
+        second.push(("cache-control".into(), "no-cache".into()));
+        let third = pairs(&[
+            (":method", "GET"),
+            (":scheme", "https"),
+            (":path", "/index.html"),
+            (":authority", "www.example.com"),
+            ("custom-key", "custom-value"),
+        ]);
+
+        let mut decoder = Decoder::new(TABLE_SIZE);
+        let block = b"\x82\x86\x84\x41\x0fwww.example.com";
+        assert_eq!(fields(decoder.decode(block).unwrap()), first);
+        assert_eq!(decoder.table.size, 57);
+        let block = b"\x82\x86\x84\xbe\x58\x08no-cache";
+        assert_eq!(fields(decoder.decode(block).unwrap()), second);
+        let block = b"\x82\x87\x85\xbf\x40\x0acustom-key\x0ccustom-value";
+        assert_eq!(fields(decoder.decode(block).unwrap()), third);
+        assert_eq!(decoder.table.size, 164);
+
+        let mut decoder = Decoder::new(TABLE_SIZE);
+        let block = b"\x82\x86\x84\x41\x8c\xf1\xe3\xc2\xe5\xf2\x3a\x6b\xa0\xab\x90\xf4\xff";
+        assert_eq!(fields(decoder.decode(block).unwrap()), first);
+        let block = b"\x82\x86\x84\xbe\x58\x86\xa8\xeb\x10\x64\x9c\xbf";
+        assert_eq!(fields(decoder.decode(block).unwrap()), second);
+    }
+
+    #[test]
+    fn reject_bad_blocks() {
+        let mut decoder = Decoder::new(TABLE_SIZE);
+        assert_eq!(decoder.decode(b"\x80"), Err(HpackError::InvalidIndex(0)));
+        assert_eq!(decoder.decode(b"\xbe"), Err(HpackError::InvalidIndex(62)));
+        assert_eq!(decoder.decode(b"\x41\x05abc"), Err(HpackError::Truncated));
+        assert_eq!(
+            decoder.decode(b"\x82\x20"),
+            Err(HpackError::LateTableSizeUpdate)
+        );
+        assert_eq!(
+            decoder.decode(b"\x3f\xe2\x1f"),
+            Err(HpackError::InvalidTableSize(4097))
+        );
+
+        // Shrinking the table evicts what no longer fits.
+        decoder.decode(b"\x41\x0fwww.example.com").unwrap();
+        decoder.decode(b"\x20\xbe").unwrap_err();
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        let mut encoder = Encoder;
+        let mut block = Vec::new();
+        encoder.encode(b":status", b"200", false, &mut block);
+        assert_eq!(block, [0x88]);
+        encoder.encode(b":status", b"201", false, &mut block);
+        encoder.encode(b"content-type", b"text/plain", false, &mut block);
+        encoder.encode(b"set-cookie", b"id=1", true, &mut block);
+        encoder.encode(b"x-custom", b"some value", false, &mut block);
+
+        let decoded = Decoder::new(TABLE_SIZE).decode(&block).unwrap();
+        assert_eq!(
+            fields(decoded),
+            pairs(&[
+                (":status", "200"),
+                (":status", "201"),
+                ("content-type", "text/plain"),
+                ("set-cookie", "id=1"),
+                ("x-custom", "some value"),
+            ])
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use std::future::{ready, Future};
+use std::pin::Pin;
+
+use anyhow::Result;
+
+use crate::request::Request;
+use crate::response::Response;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// A handler stored behind a pointer, as kept by the [`Router`](crate::router::Router).
+pub type BoxHandler = Box<dyn Handler>;
+
+/// Turns a request into a response.
+///
+/// Any `async` function or closure taking a [`Request`] and returning
+/// `Result<Response>` is a handler. Plain functions that answer without
+/// waiting can be wrapped with [`sync`]. An error returned by a handler is
+/// logged and answered with `500 Internal Server Error`.
+///
+/// ```ignore
+/// async fn hello(_req: Request) -> Result<Response> {
+///     Ok(Response::new("hello".into()))
+/// }
+///
+/// let mut router = Router::new();
+/// router.get("/hello", hello).get("/ping", handler::sync(|_| Ok(Response::new("pong".into()))));
+/// ```
+pub trait Handler: Send + Sync + 'static {
+    fn call(&self, req: Request) -> BoxFuture<'static, Result<Response>>;
+}
+
+impl<F, Fut> Handler for F
+where
+    F: Fn(Request) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<Response>> + Send + 'static,
+{
+    fn call(&self, req: Request) -> BoxFuture<'static, Result<Response>> {
+        Box::pin(self(req))
+    }
+}
+
+impl<H: Handler> From<H> for BoxHandler {
+    fn from(handler: H) -> Self {
+        Box::new(handler)
+    }
+}
+
+/// Adapts a synchronous function into a [`Handler`].
+///
+/// The function runs on the connection's task, so it must not block.
+pub fn sync<F>(f: F) -> SyncHandler<F>
+where
+    F: Fn(Request) -> Result<Response> + Send + Sync + 'static,
+{
+    SyncHandler(f)
+}
+
+/// A handler made from a synchronous function. See [`sync`].
+#[derive(Debug, Clone, Copy)]
+pub struct SyncHandler<F>(F);
+
+impl<F> Handler for SyncHandler<F>
+where
+    F: Fn(Request) -> Result<Response> + Send + Sync + 'static,
+{
+    fn call(&self, req: Request) -> BoxFuture<'static, Result<Response>> {
+        Box::pin(ready((self.0)(req)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sync, BoxHandler};
+    use crate::request::Request;
+    use crate::response::Response;
+    use crate::status::StatusCode;
+    use anyhow::Result;
+
+    async fn created(_req: Request) -> Result<Response> {
+        Ok(Response::with_status(StatusCode::CREATED))
+    }
+
+    #[tokio::test]
+    async fn async_and_sync_handlers() {
+        let handlers: Vec<BoxHandler> = vec![
+            created.into(),
+            sync(|req: Request| {
+                let mut res = Response::new(req.into_body().into());
+                *res.status_mut() = StatusCode::ACCEPTED;
+                Ok(res)
+            })
+            .into(),
+        ];
+
+        let res = handlers[0].call(Request::default()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+
+        let res = handlers[1].call(Request::new("abc".into())).await.unwrap();
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        assert_eq!(res.into_body().collect().await.unwrap(), "abc");
+    }
+}
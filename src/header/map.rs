@@ -0,0 +1,708 @@
+use core::fmt;
+use std::{iter::FromIterator, mem};
+
+use super::name::{HdrName, HeaderName};
+use super::value::HeaderValue;
+
+/// A multi-value map of HTTP header fields.
+///
+/// Names are normalized to lower case when they are inserted, and lookups by
+/// `&str` ignore ASCII case. Every value is kept, so repeated fields such as
+/// `Set-Cookie` or `Via` survive a round trip, and iteration yields the values
+/// in the order they were added.
+///
+/// Header blocks are small, so entries live in a dense vector together with a
+/// precomputed hash of their name. A lookup compares hashes first and only
+/// falls back to comparing names on a hash match. Standard headers hash to
+/// their enum discriminant and never compare bytes at all.
+#[derive(Clone)]
+pub struct HeaderMap<T = HeaderValue> {
+    entries: Vec<Bucket<T>>,
+}
+
+#[derive(Clone)]
+struct Bucket<T> {
+    hash: u64,
+    key: HeaderName,
+    value: T,
+}
+
+/// A view into a single name in a `HeaderMap`, obtained from
+/// [`HeaderMap::entry`].
+pub enum Entry<'a, T> {
+    Occupied(OccupiedEntry<'a, T>),
+    Vacant(VacantEntry<'a, T>),
+}
+
+/// A name that is present in the map at least once.
+pub struct OccupiedEntry<'a, T> {
+    map: &'a mut HeaderMap<T>,
+    index: usize,
+}
+
+/// A name that is not present in the map.
+pub struct VacantEntry<'a, T> {
+    map: &'a mut HeaderMap<T>,
+    hash: u64,
+    key: HeaderName,
+}
+
+/// Iterator over every `(name, value)` pair, in insertion order.
+pub struct Iter<'a, T> {
+    inner: std::slice::Iter<'a, Bucket<T>>,
+}
+
+/// Mutable iterator over every `(name, value)` pair, in insertion order.
+pub struct IterMut<'a, T> {
+    inner: std::slice::IterMut<'a, Bucket<T>>,
+}
+
+/// Owning iterator over every `(name, value)` pair, in insertion order.
+pub struct IntoIter<T> {
+    inner: std::vec::IntoIter<Bucket<T>>,
+}
+
+/// Iterator over the distinct names, in order of first appearance.
+pub struct Keys<'a, T> {
+    entries: &'a [Bucket<T>],
+    pos: usize,
+}
+
+/// Iterator over every value, in insertion order.
+pub struct Values<'a, T> {
+    inner: std::slice::Iter<'a, Bucket<T>>,
+}
+
+/// Iterator over all values stored under one name.
+pub struct GetAll<'a, T> {
+    entries: &'a [Bucket<T>],
+    first: Option<usize>,
+    pos: usize,
+}
+
+impl<T> HeaderMap<T> {
+    #[inline]
+    pub fn new() -> Self {
+        HeaderMap::with_capacity(0)
+    }
+
+    #[inline]
+    pub fn with_capacity(capacity: usize) -> Self {
+        HeaderMap {
+            entries: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Number of values stored in the map, counting each repeated name once
+    /// per value.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Number of distinct names stored in the map.
+    pub fn keys_len(&self) -> usize {
+        self.keys().count()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    #[inline]
+    pub fn reserve(&mut self, additional: usize) {
+        self.entries.reserve(additional);
+    }
+
+    /// Returns the first value stored under `key`.
+    pub fn get<K: AsHeaderName>(&self, key: K) -> Option<&T> {
+        key.find(self).map(|index| &self.entries[index].value)
+    }
+
+    /// Returns the first value stored under `key` for mutation.
+    pub fn get_mut<K: AsHeaderName>(&mut self, key: K) -> Option<&mut T> {
+        key.find(self)
+            .map(move |index| &mut self.entries[index].value)
+    }
+
+    /// Returns every value stored under `key`, in insertion order.
+    pub fn get_all<K: AsHeaderName>(&self, key: K) -> GetAll<'_, T> {
+        let first = key.find(self);
+        GetAll {
+            entries: &self.entries,
+            pos: first.unwrap_or(0),
+            first,
+        }
+    }
+
+    pub fn contains_key<K: AsHeaderName>(&self, key: K) -> bool {
+        key.find(self).is_some()
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            inner: self.entries.iter(),
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        IterMut {
+            inner: self.entries.iter_mut(),
+        }
+    }
+
+    pub fn keys(&self) -> Keys<'_, T> {
+        Keys {
+            entries: &self.entries,
+            pos: 0,
+        }
+    }
+
+    pub fn values(&self) -> Values<'_, T> {
+        Values {
+            inner: self.entries.iter(),
+        }
+    }
+
+    /// Inserts `val` under `key`, replacing every value already stored under
+    /// that name.
+    ///
+    /// The new value takes the position of the first replaced value. The
+    /// previous first value is returned.
+    pub fn insert<K: IntoHeaderName>(&mut self, key: K, val: T) -> Option<T> {
+        let key = key.into_header_name();
+        let hash = key.map_hash();
+        match self.find(hash, |k| *k == key) {
+            Some(index) => {
+                self.remove_after(index);
+                Some(mem::replace(&mut self.entries[index].value, val))
+            }
+            None => {
+                self.entries.push(Bucket {
+                    hash,
+                    key,
+                    value: val,
+                });
+                None
+            }
+        }
+    }
+
+    /// Adds `val` under `key` without touching values that are already there.
+    ///
+    /// Returns `true` if the name was already present.
+    pub fn append<K: IntoHeaderName>(&mut self, key: K, val: T) -> bool {
+        let key = key.into_header_name();
+        let hash = key.map_hash();
+        let existed = self.find(hash, |k| *k == key).is_some();
+        self.entries.push(Bucket {
+            hash,
+            key,
+            value: val,
+        });
+        existed
+    }
+
+    /// Removes every value stored under `key` and returns the first one.
+    pub fn remove<K: AsHeaderName>(&mut self, key: K) -> Option<T> {
+        let index = key.find(self)?;
+        self.remove_after(index);
+        Some(self.entries.remove(index).value)
+    }
+
+    /// Gets the entry for `key` for in-place manipulation.
+    pub fn entry<K: IntoHeaderName>(&mut self, key: K) -> Entry<'_, T> {
+        let key = key.into_header_name();
+        let hash = key.map_hash();
+        match self.find(hash, |k| *k == key) {
+            Some(index) => Entry::Occupied(OccupiedEntry { map: self, index }),
+            None => Entry::Vacant(VacantEntry {
+                map: self,
+                hash,
+                key,
+            }),
+        }
+    }
+
+    fn find<F>(&self, hash: u64, eq: F) -> Option<usize>
+    where
+        F: Fn(&HeaderName) -> bool,
+    {
+        self.entries
+            .iter()
+            .position(|bucket| bucket.hash == hash && eq(&bucket.key))
+    }
+
+    /// Drops every entry after `index` that shares its name.
+    fn remove_after(&mut self, index: usize) {
+        let (head, tail) = self.entries.split_at(index + 1);
+        let first = &head[index];
+        if tail.iter().all(|b| !first.same_name(b)) {
+            return;
+        }
+        let hash = first.hash;
+        let key = first.key.clone();
+        let mut i = 0;
+        self.entries.retain(|b| {
+            let keep = i <= index || b.hash != hash || b.key != key;
+            i += 1;
+            keep
+        });
+    }
+}
+
+impl<T> Bucket<T> {
+    #[inline]
+    fn same_name(&self, other: &Bucket<T>) -> bool {
+        self.hash == other.hash && self.key == other.key
+    }
+}
+
+impl<T> Default for HeaderMap<T> {
+    fn default() -> Self {
+        HeaderMap::new()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for HeaderMap<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<T: PartialEq> PartialEq for HeaderMap<T> {
+    fn eq(&self, other: &HeaderMap<T>) -> bool {
+        self.len() == other.len()
+            && self
+                .keys()
+                .all(|key| self.get_all(key).eq(other.get_all(key)))
+    }
+}
+
+impl<T: Eq> Eq for HeaderMap<T> {}
+
+impl<'a, T> IntoIterator for &'a HeaderMap<T> {
+    type Item = (&'a HeaderName, &'a T);
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut HeaderMap<T> {
+    type Item = (&'a HeaderName, &'a mut T);
+    type IntoIter = IterMut<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<T> IntoIterator for HeaderMap<T> {
+    type Item = (HeaderName, T);
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            inner: self.entries.into_iter(),
+        }
+    }
+}
+
+impl<T> Extend<(HeaderName, T)> for HeaderMap<T> {
+    /// Appends every pair, keeping values that are already in the map.
+    fn extend<I: IntoIterator<Item = (HeaderName, T)>>(&mut self, iter: I) {
+        for (key, val) in iter {
+            self.append(key, val);
+        }
+    }
+}
+
+impl<T> FromIterator<(HeaderName, T)> for HeaderMap<T> {
+    fn from_iter<I: IntoIterator<Item = (HeaderName, T)>>(iter: I) -> Self {
+        let mut map = HeaderMap::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = (&'a HeaderName, &'a T);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|b| (&b.key, &b.value))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+    type Item = (&'a HeaderName, &'a mut T);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|b| (&b.key, &mut b.value))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = (HeaderName, T);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|b| (b.key, b.value))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, T> Iterator for Keys<'a, T> {
+    type Item = &'a HeaderName;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pos < self.entries.len() {
+            let (seen, rest) = self.entries.split_at(self.pos);
+            self.pos += 1;
+            let bucket = &rest[0];
+            if !seen.iter().any(|b| b.same_name(bucket)) {
+                return Some(&bucket.key);
+            }
+        }
+        None
+    }
+}
+
+impl<'a, T> Iterator for Values<'a, T> {
+    type Item = &'a T;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|b| &b.value)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, T> Iterator for GetAll<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let first = &self.entries[self.first?];
+        while self.pos < self.entries.len() {
+            let bucket = &self.entries[self.pos];
+            self.pos += 1;
+            if first.same_name(bucket) {
+                return Some(&bucket.value);
+            }
+        }
+        None
+    }
+}
+
+impl<'a, T> Entry<'a, T> {
+    /// Returns the first value, inserting `default` if the name is absent.
+    pub fn or_insert(self, default: T) -> &'a mut T {
+        match self {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(default),
+        }
+    }
+
+    /// Returns the first value, inserting the result of `default` if the name
+    /// is absent.
+    pub fn or_insert_with<F: FnOnce() -> T>(self, default: F) -> &'a mut T {
+        match self {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(default()),
+        }
+    }
+
+    pub fn key(&self) -> &HeaderName {
+        match self {
+            Entry::Occupied(e) => e.key(),
+            Entry::Vacant(e) => e.key(),
+        }
+    }
+}
+
+impl<'a, T> OccupiedEntry<'a, T> {
+    pub fn key(&self) -> &HeaderName {
+        &self.map.entries[self.index].key
+    }
+
+    /// Returns the first value stored under this name.
+    pub fn get(&self) -> &T {
+        &self.map.entries[self.index].value
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.map.entries[self.index].value
+    }
+
+    pub fn into_mut(self) -> &'a mut T {
+        &mut self.map.entries[self.index].value
+    }
+
+    /// Iterates every value stored under this name.
+    pub fn iter(&self) -> GetAll<'_, T> {
+        GetAll {
+            entries: &self.map.entries,
+            first: Some(self.index),
+            pos: self.index,
+        }
+    }
+
+    /// Replaces every value stored under this name with `val`, returning the
+    /// previous first value.
+    pub fn insert(&mut self, val: T) -> T {
+        self.map.remove_after(self.index);
+        mem::replace(&mut self.map.entries[self.index].value, val)
+    }
+
+    /// Adds another value under this name.
+    pub fn append(&mut self, val: T) {
+        let bucket = &self.map.entries[self.index];
+        let (hash, key) = (bucket.hash, bucket.key.clone());
+        self.map.entries.push(Bucket {
+            hash,
+            key,
+            value: val,
+        });
+    }
+
+    /// Removes every value stored under this name, returning the first one.
+    pub fn remove(self) -> T {
+        self.map.remove_after(self.index);
+        self.map.entries.remove(self.index).value
+    }
+}
+
+impl<'a, T> VacantEntry<'a, T> {
+    pub fn key(&self) -> &HeaderName {
+        &self.key
+    }
+
+    pub fn into_key(self) -> HeaderName {
+        self.key
+    }
+
+    pub fn insert(self, val: T) -> &'a mut T {
+        self.map.entries.push(Bucket {
+            hash: self.hash,
+            key: self.key,
+            value: val,
+        });
+        &mut self.map.entries.last_mut().unwrap().value
+    }
+}
+
+/// A type that can be used to look up a value in a `HeaderMap`.
+///
+/// Implemented for `HeaderName` and for strings. String lookups ignore ASCII
+/// case; a string that is not a valid header name never matches.
+pub trait AsHeaderName: sealed::AsHeaderName {}
+
+/// A type that can be used as the name of a new `HeaderMap` value.
+///
+/// `&'static str` is accepted for convenience and panics if it is not a valid
+/// header name.
+pub trait IntoHeaderName: sealed::IntoHeaderName {}
+
+mod sealed {
+    use super::{HdrName, HeaderMap, HeaderName};
+
+    pub trait AsHeaderName {
+        fn find<T>(&self, map: &HeaderMap<T>) -> Option<usize>;
+    }
+
+    pub trait IntoHeaderName {
+        fn into_header_name(self) -> HeaderName;
+    }
+
+    fn find_str<T>(map: &HeaderMap<T>, name: &str) -> Option<usize> {
+        HdrName::from_bytes(name.as_bytes(), |hdr| {
+            map.find(hdr.map_hash(), |key| *key == hdr)
+        })
+        .ok()
+        .flatten()
+    }
+
+    impl AsHeaderName for HeaderName {
+        fn find<T>(&self, map: &HeaderMap<T>) -> Option<usize> {
+            map.find(self.map_hash(), |key| key == self)
+        }
+    }
+
+    impl AsHeaderName for &HeaderName {
+        fn find<T>(&self, map: &HeaderMap<T>) -> Option<usize> {
+            (*self).find(map)
+        }
+    }
+
+    impl AsHeaderName for &str {
+        fn find<T>(&self, map: &HeaderMap<T>) -> Option<usize> {
+            find_str(map, self)
+        }
+    }
+
+    impl AsHeaderName for String {
+        fn find<T>(&self, map: &HeaderMap<T>) -> Option<usize> {
+            find_str(map, self)
+        }
+    }
+
+    impl AsHeaderName for &String {
+        fn find<T>(&self, map: &HeaderMap<T>) -> Option<usize> {
+            find_str(map, self)
+        }
+    }
+
+    impl IntoHeaderName for HeaderName {
+        #[inline]
+        fn into_header_name(self) -> HeaderName {
+            self
+        }
+    }
+
+    impl IntoHeaderName for &HeaderName {
+        #[inline]
+        fn into_header_name(self) -> HeaderName {
+            self.clone()
+        }
+    }
+
+    impl IntoHeaderName for &'static str {
+        #[inline]
+        fn into_header_name(self) -> HeaderName {
+            HdrName::from_static(self.as_bytes(), |hdr| hdr.into())
+        }
+    }
+}
+
+impl AsHeaderName for HeaderName {}
+impl AsHeaderName for &HeaderName {}
+impl AsHeaderName for &str {}
+impl AsHeaderName for String {}
+impl AsHeaderName for &String {}
+
+impl IntoHeaderName for HeaderName {}
+impl IntoHeaderName for &HeaderName {}
+impl IntoHeaderName for &'static str {}
+
+#[cfg(test)]
+mod tests {
+    use super::{Entry, HeaderMap};
+    use crate::header::{HeaderName, CONTENT_LENGTH, HOST, SET_COOKIE, VIA};
+
+    #[test]
+    fn lookup_ignores_case() {
+        let mut map = HeaderMap::new();
+        map.insert(HOST, "localhost");
+        map.insert(HeaderName::from_static("x-request-id"), "42");
+
+        assert_eq!(map.get("Host"), Some(&"localhost"));
+        assert_eq!(map.get("HOST"), Some(&"localhost"));
+        assert_eq!(map.get("X-Request-Id"), Some(&"42"));
+        assert_eq!(map.get("x-request-id"), Some(&"42"));
+        assert!(map.get("not a header").is_none());
+        assert!(!map.contains_key(CONTENT_LENGTH));
+    }
+
+    #[test]
+    fn append_keeps_every_value_in_order() {
+        let mut map = HeaderMap::new();
+        assert!(!map.append(SET_COOKIE, "a=1"));
+        map.append(VIA, "1.1 proxy");
+        assert!(map.append("Set-Cookie", "b=2"));
+
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.keys_len(), 2);
+        assert_eq!(map.get(SET_COOKIE), Some(&"a=1"));
+        assert_eq!(
+            map.get_all("set-cookie").copied().collect::<Vec<_>>(),
+            vec!["a=1", "b=2"]
+        );
+        assert_eq!(
+            map.iter()
+                .map(|(k, v)| (k.as_str(), *v))
+                .collect::<Vec<_>>(),
+            vec![
+                ("set-cookie", "a=1"),
+                ("via", "1.1 proxy"),
+                ("set-cookie", "b=2")
+            ]
+        );
+        assert_eq!(
+            map.keys().map(HeaderName::as_str).collect::<Vec<_>>(),
+            vec!["set-cookie", "via"]
+        );
+    }
+
+    #[test]
+    fn insert_replaces_all_values() {
+        let mut map = HeaderMap::new();
+        map.append(SET_COOKIE, "a=1");
+        map.append(VIA, "1.1 proxy");
+        map.append(SET_COOKIE, "b=2");
+
+        assert_eq!(map.insert(SET_COOKIE, "c=3"), Some("a=1"));
+        assert_eq!(map.get_all(SET_COOKIE).count(), 1);
+        assert_eq!(
+            map.iter()
+                .map(|(k, v)| (k.as_str(), *v))
+                .collect::<Vec<_>>(),
+            vec![("set-cookie", "c=3"), ("via", "1.1 proxy")]
+        );
+    }
+
+    #[test]
+    fn remove_drops_all_values() {
+        let mut map = HeaderMap::new();
+        map.append(VIA, "a");
+        map.append(HOST, "localhost");
+        map.append(VIA, "b");
+
+        assert_eq!(map.remove("Via"), Some("a"));
+        assert_eq!(map.len(), 1);
+        assert!(map.remove(VIA).is_none());
+    }
+
+    #[test]
+    fn entry_api() {
+        let mut map = HeaderMap::new();
+        *map.entry(VIA).or_insert("a") = "b";
+        assert_eq!(map.get(VIA), Some(&"b"));
+
+        match map.entry("via") {
+            Entry::Occupied(mut e) => {
+                e.append("c");
+                assert_eq!(e.iter().copied().collect::<Vec<_>>(), vec!["b", "c"]);
+                assert_eq!(e.remove(), "b");
+            }
+            Entry::Vacant(_) => unreachable!(),
+        }
+        assert!(map.is_empty());
+    }
+}
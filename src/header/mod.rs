@@ -2,4 +2,11 @@ mod map;
 mod name;
 mod value;
 
+pub use self::map::{
+    AsHeaderName, Entry, GetAll, HeaderMap, IntoHeaderName, IntoIter, Iter, IterMut, Keys,
+    OccupiedEntry, VacantEntry, Values,
+};
+pub use self::name::*;
+pub use self::value::{HeaderValue, InvalidHeaderValue, ToStrError};
+
 const MAX_HEADER_NAME_LEN: usize = (1 << 16) - 1;
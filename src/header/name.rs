@@ -995,7 +995,7 @@ const HEADER_CHARS_H2: [u8; 256] = [
 
 impl InvalidHeaderName {
     #[inline]
-    pub fn new() -> Self {
+    pub(crate) fn new() -> Self {
         InvalidHeaderName { _priv: () }
     }
 }
@@ -1078,6 +1078,27 @@ impl HeaderName {
         }
     }
 
+    /// Converts shared bytes to an HTTP header name.
+    ///
+    /// Custom names that are already lowercase reuse `src` instead of being
+    /// copied, which is what a parser holding the request head in one buffer
+    /// wants.
+    pub(crate) fn from_shared(src: Bytes) -> Result<HeaderName, InvalidHeaderName> {
+        let mut buf = uninit_u8_array();
+        let reuse = match parse_hdr(&src, &mut buf, &HEADER_CHARS)?.inner {
+            Repr::Standard(std) => return Ok(std.into()),
+            Repr::Custom(MaybeLower { buf, lower: true }) => buf == &src[..],
+            Repr::Custom(MaybeLower { lower: false, .. }) => false,
+        };
+        if !reuse {
+            return HeaderName::from_bytes(&src);
+        }
+        // Safety: `src` equals the buffer parse_hdr validated, which only
+        // holds single byte UTF-8.
+        let val = unsafe { ByteStr::from_utf8_unchecked(src) };
+        Ok(Custom(val).into())
+    }
+
     /// Converts a slice of bytes to an HTTP header name.
     ///
     /// This function expects the input to only contain lowercase characters.
@@ -1087,7 +1108,7 @@ impl HeaderName {
     /// # Examples
     ///
     /// ```
-    /// # use http::header::*;
+    /// # use http_server_starter_rust::header::*;
     ///
     /// // Parsing a lower case header
     /// let hdr = HeaderName::from_lowercase(b"content-length").unwrap();
@@ -1135,31 +1156,21 @@ impl HeaderName {
     ///
     /// This function panics when the static string is a invalid header.
     ///
-    /// Until [Allow panicking in constants](https://github.com/rust-lang/rfcs/pull/2345)
-    /// makes its way into stable, the panic message at compile-time is
-    /// going to look cryptic, but should at least point at your header value:
+    /// Used in a constant, an invalid name fails the build, and the error
+    /// points at the constant:
     ///
     /// ```text
-    /// error: any use of this value will cause an error
-    ///     --> http/src/header/name.rs:1241:13
-    ///      |
-    /// 1241 |             ([] as [u8; 0])[0]; // Invalid header name
-    ///      |             ^^^^^^^^^^^^^^^^^^
-    ///      |             |
-    ///      |             index out of bounds: the length is 0 but the index is 0
-    ///      |             inside `http::HeaderName::from_static` at http/src/header/name.rs:1241:13
-    ///      |             inside `INVALID_NAME` at src/main.rs:3:34
-    ///      |
-    ///     ::: src/main.rs:3:1
-    ///      |
-    /// 3    | const INVALID_NAME: HeaderName = HeaderName::from_static("Capitalized");
-    ///      | ------------------------------------------------------------------------
+    /// error[E0080]: evaluation panicked: invalid header name
+    ///  --> src/main.rs:3:34
+    ///   |
+    /// 3 | const INVALID_NAME: HeaderName = HeaderName::from_static("Capitalized");
+    ///   |                                  ^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^ evaluation of `INVALID_NAME` failed inside this call
     /// ```
     ///
     /// # Examples
     ///
     /// ```
-    /// # use http::header::*;
+    /// # use http_server_starter_rust::header::*;
     /// // Parsing a standard header
     /// let hdr = HeaderName::from_static("content-length");
     /// assert_eq!(CONTENT_LENGTH, hdr);
@@ -1173,7 +1184,7 @@ impl HeaderName {
     /// ```
     ///
     /// ```should_panic
-    /// # use http::header::*;
+    /// # use http_server_starter_rust::header::*;
     /// #
     /// // Parsing a header that contains invalid symbols(s):
     /// HeaderName::from_static("content{}{}length"); // This line panics!
@@ -1182,7 +1193,6 @@ impl HeaderName {
     /// let a = HeaderName::from_static("foobar");
     /// let b = HeaderName::from_static("FOOBAR"); // This line panics!
     /// ```
-    #[allow(unconditional_panic)] // required for the panic circumvention
     pub const fn from_static(src: &'static str) -> HeaderName {
         let name_bytes = src.as_bytes();
         if let Some(standard) = StandardHeader::from_bytes(name_bytes) {
@@ -1191,7 +1201,7 @@ impl HeaderName {
             };
         }
 
-        if name_bytes.len() == 0 || name_bytes.len() > super::MAX_HEADER_NAME_LEN || {
+        if name_bytes.is_empty() || name_bytes.len() > super::MAX_HEADER_NAME_LEN || {
             let mut i = 0;
             loop {
                 if i >= name_bytes.len() {
@@ -1202,7 +1212,7 @@ impl HeaderName {
                 i += 1;
             }
         } {
-            ([] as [u8; 0])[0]; // Invalid header name
+            panic!("invalid header name");
         }
 
         HeaderName {
@@ -1217,13 +1227,25 @@ impl HeaderName {
     pub fn as_str(&self) -> &str {
         match self.inner {
             Repr::Standard(v) => v.as_str(),
-            Repr::Custom(ref v) => &*v.0,
+            Repr::Custom(ref v) => &v.0,
         }
     }
 
     pub(super) fn into_bytes(self) -> Bytes {
         self.inner.into()
     }
+
+    /// Hash used by `HeaderMap` to skip entries without comparing names.
+    ///
+    /// Standard headers hash to their enum discriminant, so looking them up
+    /// never touches the name bytes.
+    #[inline]
+    pub(super) fn map_hash(&self) -> u64 {
+        match self.inner {
+            Repr::Standard(std) => std as u64,
+            Repr::Custom(Custom(ref name)) => fnv_hash(name.as_bytes().iter().copied()),
+        }
+    }
 }
 
 impl FromStr for HeaderName {
@@ -1357,7 +1379,7 @@ impl<'a> PartialEq<&'a HeaderName> for HeaderName {
     }
 }
 
-impl<'a> PartialEq<HeaderName> for &'a HeaderName {
+impl PartialEq<HeaderName> for &HeaderName {
     #[inline]
     fn eq(&self, other: &HeaderName) -> bool {
         *other == *self
@@ -1371,7 +1393,7 @@ impl PartialEq<str> for HeaderName {
     /// # Examples
     ///
     /// ```
-    /// use http::header::CONTENT_LENGTH;
+    /// use http_server_starter_rust::header::CONTENT_LENGTH;
     ///
     /// assert_eq!(CONTENT_LENGTH, "content-length");
     /// assert_eq!(CONTENT_LENGTH, "Content-Length");
@@ -1390,7 +1412,7 @@ impl PartialEq<HeaderName> for str {
     /// # Examples
     ///
     /// ```
-    /// use http::header::CONTENT_LENGTH;
+    /// use http_server_starter_rust::header::CONTENT_LENGTH;
     ///
     /// assert_eq!(CONTENT_LENGTH, "content-length");
     /// assert_eq!(CONTENT_LENGTH, "Content-Length");
@@ -1411,7 +1433,7 @@ impl<'a> PartialEq<&'a str> for HeaderName {
     }
 }
 
-impl<'a> PartialEq<HeaderName> for &'a str {
+impl PartialEq<HeaderName> for &str {
     fn eq(&self, other: &HeaderName) -> bool {
         *other == *self
     }
@@ -1455,6 +1477,18 @@ impl<'a> HdrName<'a> {
         let hdr = parse_hdr(hdr, &mut buf, &HEADER_CHARS).expect("static str is invalid");
         f(hdr)
     }
+
+    /// Same hash as `HeaderName::map_hash` for the normalized name.
+    #[inline]
+    pub(super) fn map_hash(&self) -> u64 {
+        match self.inner {
+            Repr::Standard(std) => std as u64,
+            Repr::Custom(MaybeLower { buf, lower: true }) => fnv_hash(buf.iter().copied()),
+            Repr::Custom(MaybeLower { buf, lower: false }) => {
+                fnv_hash(buf.iter().map(|b| HEADER_CHARS[*b as usize]))
+            }
+        }
+    }
 }
 
 #[doc(hidden)]
@@ -1466,7 +1500,7 @@ impl<'a> From<HdrName<'a>> for HeaderName {
             },
             Repr::Custom(maybe_lower) => {
                 if maybe_lower.lower {
-                    let buf = Bytes::copy_from_slice(&maybe_lower.buf[..]);
+                    let buf = Bytes::copy_from_slice(maybe_lower.buf);
                     let byte_str = unsafe { ByteStr::from_utf8_unchecked(buf) };
 
                     HeaderName {
@@ -1546,6 +1580,16 @@ fn eq_ignore_ascii_case(lower: &[u8], s: &[u8]) -> bool {
         .all(|(a, b)| *a == HEADER_CHARS[*b as usize])
 }
 
+/// FNV-1a over the lowercase name. The top bit is set so custom names never
+/// collide with the discriminant of a standard header.
+#[inline]
+fn fnv_hash(bytes: impl Iterator<Item = u8>) -> u64 {
+    let hash = bytes.fold(0xcbf2_9ce4_8422_2325_u64, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    hash | (1 << 63)
+}
+
 const SCRATCH_BUF_SIZE: usize = 64;
 const SCRATCH_BUF_OVERFLOW: usize = SCRATCH_BUF_SIZE + 1;
 
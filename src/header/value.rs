@@ -0,0 +1,530 @@
+use core::fmt;
+use std::{cmp, error::Error, hash::Hash, str::FromStr};
+
+use bytes::Bytes;
+
+use super::name::HeaderName;
+use crate::byte_str::ByteStr;
+
+/// Represents an HTTP header field value.
+///
+/// Field values are usually visible ASCII, but RFC 9110 also allows opaque
+/// bytes (`obs-text`), so a value is stored as bytes and only turned into a
+/// `&str` on request through [`HeaderValue::to_str`].
+///
+/// A value can be marked sensitive. Sensitive values print as `Sensitive` in
+/// `Debug` output so that credentials never end up in logs.
+#[derive(Clone)]
+pub struct HeaderValue {
+    inner: Repr,
+    is_sensitive: bool,
+}
+
+#[derive(Clone)]
+enum Repr {
+    Shared(Bytes),
+    /// Formatted integers, stored inline so that converting a number never
+    /// allocates.
+    Inline([u8; INLINE_LEN], u8),
+}
+
+/// Longest decimal integer: `-9223372036854775808` is 20 bytes.
+const INLINE_LEN: usize = 20;
+
+/// A possible error when converting a `HeaderValue` from a string or byte
+/// slice.
+pub struct InvalidHeaderValue {
+    _priv: (),
+}
+
+/// A possible error when converting a `HeaderValue` to a string representation.
+///
+/// Header field values may contain opaque bytes, in which case it is not
+/// possible to represent the value as a string.
+pub struct ToStrError {
+    _priv: (),
+}
+
+impl HeaderValue {
+    /// Converts a static string to a `HeaderValue`.
+    ///
+    /// This function will not perform any copying, however the string is
+    /// checked to ensure that no invalid characters are present. Only visible
+    /// ASCII characters, space and horizontal tab (9 and 32-126) are
+    /// permitted.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the argument contains invalid header value
+    /// characters.
+    pub const fn from_static(src: &'static str) -> HeaderValue {
+        let bytes = src.as_bytes();
+        let mut i = 0;
+        while i < bytes.len() {
+            if !is_visible_ascii(bytes[i]) {
+                panic!("invalid header value");
+            }
+            i += 1;
+        }
+
+        HeaderValue {
+            inner: Repr::Shared(Bytes::from_static(bytes)),
+            is_sensitive: false,
+        }
+    }
+
+    /// Attempts to convert a string to a `HeaderValue`.
+    ///
+    /// The same characters as [`HeaderValue::from_bytes`] are accepted, so
+    /// non-ASCII characters are kept as their UTF-8 bytes. Such a value is
+    /// opaque: [`HeaderValue::to_str`] refuses it.
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(src: &str) -> Result<HeaderValue, InvalidHeaderValue> {
+        HeaderValue::try_from_generic(src, |s| Bytes::copy_from_slice(s.as_bytes()))
+    }
+
+    /// Converts a `HeaderName` into a `HeaderValue`.
+    ///
+    /// Since every valid header name is a valid header value this cannot fail.
+    #[inline]
+    pub fn from_name(name: HeaderName) -> HeaderValue {
+        name.into()
+    }
+
+    /// Attempts to convert a slice of bytes to a `HeaderValue`.
+    ///
+    /// Control characters other than horizontal tab are rejected. Bytes in the
+    /// range 128-255 (`obs-text`) are accepted as opaque data.
+    #[inline]
+    pub fn from_bytes(src: &[u8]) -> Result<HeaderValue, InvalidHeaderValue> {
+        HeaderValue::try_from_generic(src, Bytes::copy_from_slice)
+    }
+
+    /// Attempts to convert shared bytes to a `HeaderValue` without copying.
+    ///
+    /// The same characters as [`HeaderValue::from_bytes`] are accepted.
+    #[inline]
+    pub fn from_shared(src: Bytes) -> Result<HeaderValue, InvalidHeaderValue> {
+        HeaderValue::try_from_generic(src, |s| s)
+    }
+
+    fn try_from_generic<T: AsRef<[u8]>, F: FnOnce(T) -> Bytes>(
+        src: T,
+        into: F,
+    ) -> Result<HeaderValue, InvalidHeaderValue> {
+        for &b in src.as_ref() {
+            if !is_valid(b) {
+                return Err(InvalidHeaderValue::new());
+            }
+        }
+        Ok(HeaderValue {
+            inner: Repr::Shared(into(src)),
+            is_sensitive: false,
+        })
+    }
+
+    /// Yields a `&str` slice if the value only contains visible ASCII chars.
+    ///
+    /// This function will perform a scan of the header value, checking all the
+    /// characters.
+    pub fn to_str(&self) -> Result<&str, ToStrError> {
+        let bytes = self.as_bytes();
+
+        for &b in bytes {
+            if !is_visible_ascii(b) {
+                return Err(ToStrError::new());
+            }
+        }
+
+        // Safety: every byte was just checked to be visible ASCII.
+        unsafe { Ok(std::str::from_utf8_unchecked(bytes)) }
+    }
+
+    /// Returns the length of `self` in bytes.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.as_bytes().len()
+    }
+
+    /// Returns true if the `HeaderValue` has a length of zero bytes.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Converts a `HeaderValue` to a byte slice.
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        match self.inner {
+            Repr::Shared(ref bytes) => bytes.as_ref(),
+            Repr::Inline(ref buf, len) => &buf[..len as usize],
+        }
+    }
+
+    /// Mark that the header value represents sensitive information.
+    ///
+    /// Sensitive values are hidden from `Debug` output.
+    #[inline]
+    pub fn set_sensitive(&mut self, val: bool) {
+        self.is_sensitive = val;
+    }
+
+    /// Returns `true` if the value represents sensitive data.
+    #[inline]
+    pub fn is_sensitive(&self) -> bool {
+        self.is_sensitive
+    }
+
+    fn from_inline(buf: [u8; INLINE_LEN], len: usize) -> HeaderValue {
+        HeaderValue {
+            inner: Repr::Inline(buf, len as u8),
+            is_sensitive: false,
+        }
+    }
+}
+
+impl AsRef<[u8]> for HeaderValue {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl fmt::Debug for HeaderValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_sensitive {
+            return f.write_str("Sensitive");
+        }
+
+        f.write_str("\"")?;
+        let bytes = self.as_bytes();
+        let mut from = 0;
+
+        for (i, &b) in bytes.iter().enumerate() {
+            if !is_visible_ascii(b) || b == b'"' {
+                if from != i {
+                    // Safety: the skipped bytes are all visible ASCII.
+                    f.write_str(unsafe { std::str::from_utf8_unchecked(&bytes[from..i]) })?;
+                }
+                if b == b'"' {
+                    f.write_str("\\\"")?;
+                } else {
+                    write!(f, "\\x{:x}", b)?;
+                }
+                from = i + 1;
+            }
+        }
+
+        // Safety: the remaining bytes are all visible ASCII.
+        f.write_str(unsafe { std::str::from_utf8_unchecked(&bytes[from..]) })?;
+        f.write_str("\"")
+    }
+}
+
+impl From<HeaderName> for HeaderValue {
+    #[inline]
+    fn from(h: HeaderName) -> HeaderValue {
+        HeaderValue {
+            inner: Repr::Shared(h.into_bytes()),
+            is_sensitive: false,
+        }
+    }
+}
+
+macro_rules! from_integers {
+    ($($t:ident),*) => {$(
+        impl From<$t> for HeaderValue {
+            fn from(num: $t) -> HeaderValue {
+                let mut buf = [0_u8; INLINE_LEN];
+                let len = write_decimal(&mut buf, num as i128);
+                HeaderValue::from_inline(buf, len)
+            }
+        }
+    )*};
+}
+
+from_integers!(u16, i16, u32, i32, u64, i64, usize, isize);
+
+/// Formats `num` right-aligned into `buf`, then moves it to the front.
+fn write_decimal(buf: &mut [u8; INLINE_LEN], num: i128) -> usize {
+    let mut n = num.unsigned_abs();
+    let mut pos = INLINE_LEN;
+    loop {
+        pos -= 1;
+        buf[pos] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            break;
+        }
+    }
+    if num < 0 {
+        pos -= 1;
+        buf[pos] = b'-';
+    }
+    let len = INLINE_LEN - pos;
+    buf.copy_within(pos.., 0);
+    len
+}
+
+impl FromStr for HeaderValue {
+    type Err = InvalidHeaderValue;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<HeaderValue, Self::Err> {
+        HeaderValue::from_str(s)
+    }
+}
+
+impl<'a> TryFrom<&'a str> for HeaderValue {
+    type Error = InvalidHeaderValue;
+
+    #[inline]
+    fn try_from(t: &'a str) -> Result<Self, Self::Error> {
+        t.parse()
+    }
+}
+
+impl<'a> TryFrom<&'a String> for HeaderValue {
+    type Error = InvalidHeaderValue;
+
+    #[inline]
+    fn try_from(s: &'a String) -> Result<Self, Self::Error> {
+        Self::from_bytes(s.as_bytes())
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for HeaderValue {
+    type Error = InvalidHeaderValue;
+
+    #[inline]
+    fn try_from(t: &'a [u8]) -> Result<Self, Self::Error> {
+        HeaderValue::from_bytes(t)
+    }
+}
+
+impl TryFrom<String> for HeaderValue {
+    type Error = InvalidHeaderValue;
+
+    #[inline]
+    fn try_from(t: String) -> Result<Self, Self::Error> {
+        HeaderValue::from_shared(ByteStr::from(t).into())
+    }
+}
+
+impl TryFrom<Vec<u8>> for HeaderValue {
+    type Error = InvalidHeaderValue;
+
+    #[inline]
+    fn try_from(vec: Vec<u8>) -> Result<Self, Self::Error> {
+        HeaderValue::from_shared(vec.into())
+    }
+}
+
+impl TryFrom<Bytes> for HeaderValue {
+    type Error = InvalidHeaderValue;
+
+    #[inline]
+    fn try_from(bytes: Bytes) -> Result<Self, Self::Error> {
+        HeaderValue::from_shared(bytes)
+    }
+}
+
+impl<'a> From<&'a HeaderValue> for HeaderValue {
+    #[inline]
+    fn from(t: &'a HeaderValue) -> Self {
+        t.clone()
+    }
+}
+
+impl From<HeaderValue> for Bytes {
+    #[inline]
+    fn from(value: HeaderValue) -> Bytes {
+        match value.inner {
+            Repr::Shared(bytes) => bytes,
+            Repr::Inline(buf, len) => Bytes::copy_from_slice(&buf[..len as usize]),
+        }
+    }
+}
+
+const fn is_visible_ascii(b: u8) -> bool {
+    b >= 32 && b < 127 || b == b'\t'
+}
+
+/// `field-vchar / SP / HTAB / obs-text`, per RFC 9110 section 5.5.
+#[inline]
+fn is_valid(b: u8) -> bool {
+    b >= 32 && b != 127 || b == b'\t'
+}
+
+impl InvalidHeaderValue {
+    pub(crate) fn new() -> Self {
+        Self { _priv: () }
+    }
+}
+
+impl fmt::Debug for InvalidHeaderValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InvalidHeaderValue").finish()
+    }
+}
+
+impl fmt::Display for InvalidHeaderValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("failed to parse header value")
+    }
+}
+
+impl Error for InvalidHeaderValue {}
+
+impl ToStrError {
+    pub(crate) fn new() -> Self {
+        Self { _priv: () }
+    }
+}
+
+impl fmt::Debug for ToStrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ToStrError").finish()
+    }
+}
+
+impl fmt::Display for ToStrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("failed to convert header to a str")
+    }
+}
+
+impl Error for ToStrError {}
+
+impl Hash for HeaderValue {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.as_bytes().hash(state);
+    }
+}
+
+impl PartialEq for HeaderValue {
+    #[inline]
+    fn eq(&self, other: &HeaderValue) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl Eq for HeaderValue {}
+
+impl PartialOrd for HeaderValue {
+    #[inline]
+    fn partial_cmp(&self, other: &HeaderValue) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for HeaderValue {
+    #[inline]
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        self.as_bytes().cmp(other.as_bytes())
+    }
+}
+
+impl PartialEq<str> for HeaderValue {
+    #[inline]
+    fn eq(&self, other: &str) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl PartialEq<[u8]> for HeaderValue {
+    #[inline]
+    fn eq(&self, other: &[u8]) -> bool {
+        self.as_bytes() == other
+    }
+}
+
+impl PartialEq<HeaderValue> for str {
+    #[inline]
+    fn eq(&self, other: &HeaderValue) -> bool {
+        *other == *self
+    }
+}
+
+impl PartialEq<HeaderValue> for [u8] {
+    #[inline]
+    fn eq(&self, other: &HeaderValue) -> bool {
+        *other == *self
+    }
+}
+
+impl PartialEq<String> for HeaderValue {
+    #[inline]
+    fn eq(&self, other: &String) -> bool {
+        *self == other[..]
+    }
+}
+
+impl<'a> PartialEq<&'a str> for HeaderValue {
+    #[inline]
+    fn eq(&self, other: &&'a str) -> bool {
+        *self == **other
+    }
+}
+
+impl PartialEq<HeaderValue> for &str {
+    #[inline]
+    fn eq(&self, other: &HeaderValue) -> bool {
+        *other == *self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HeaderValue;
+    use crate::header::CONTENT_TYPE;
+
+    #[test]
+    fn visible_ascii_round_trips() {
+        let value = HeaderValue::from_static("text/plain; charset=utf-8");
+        assert_eq!(value.to_str().unwrap(), "text/plain; charset=utf-8");
+        assert_eq!(value, "text/plain; charset=utf-8");
+        assert_eq!(HeaderValue::from_name(CONTENT_TYPE), "content-type");
+    }
+
+    #[test]
+    fn opaque_bytes_are_accepted_but_not_str() {
+        let value = HeaderValue::from_bytes(b"caf\xe9").unwrap();
+        assert_eq!(value.as_bytes(), b"caf\xe9");
+        assert!(value.to_str().is_err());
+        assert_eq!(format!("{:?}", value), "\"caf\\xe9\"");
+    }
+
+    #[test]
+    fn control_characters_are_rejected() {
+        assert!(HeaderValue::from_bytes(b"a\r\nb").is_err());
+        assert!(HeaderValue::from_bytes(b"a\0b").is_err());
+        assert!(HeaderValue::from_bytes(b"a\x7fb").is_err());
+        assert!(HeaderValue::from_str("a\tb").is_ok());
+        assert!(HeaderValue::from_str("a\nb").is_err());
+    }
+
+    #[test]
+    fn non_ascii_strings_are_opaque() {
+        let value = HeaderValue::from_str("café").unwrap();
+        assert_eq!(value.as_bytes(), "café".as_bytes());
+        assert!(value.to_str().is_err());
+    }
+
+    #[test]
+    fn sensitive_values_are_hidden() {
+        let mut value = HeaderValue::from_static("Bearer secret");
+        assert_eq!(format!("{:?}", value), "\"Bearer secret\"");
+        value.set_sensitive(true);
+        assert_eq!(format!("{:?}", value), "Sensitive");
+        assert_eq!(value, "Bearer secret");
+    }
+
+    #[test]
+    fn integers() {
+        assert_eq!(HeaderValue::from(0_u16), "0");
+        assert_eq!(HeaderValue::from(4221_usize), "4221");
+        assert_eq!(HeaderValue::from(u64::MAX), "18446744073709551615");
+        assert_eq!(HeaderValue::from(i64::MIN), "-9223372036854775808");
+    }
+}
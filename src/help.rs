@@ -1,152 +1,785 @@
-use anyhow::Result;
-use std::collections::HashMap;
-
-pub struct HttpRequest<'a> {
-    pub method: &'a str,
-    pub path: &'a str,
-    pub query: HashMap<&'a str, &'a str>,
-    pub headers: HashMap<&'a str, &'a str>,
-    pub body: Option<&'a [u8]>,
+use std::ops::Range;
+
+use bytes::{Bytes, BytesMut};
+use thiserror::Error;
+
+use crate::header::{
+    HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, CONNECTION, CONTENT_LENGTH, COOKIE, EXPECT,
+    PROXY_AUTHORIZATION, SET_COOKIE, TRANSFER_ENCODING,
+};
+use crate::method::Method;
+use crate::status::StatusCode;
+use crate::uri::{Form, Uri};
+use crate::version::Version;
+
+/// Limits on the size of a request head. Exceeding them is answered with
+/// `414 URI Too Long` or `431 Request Header Fields Too Large`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseLimits {
+    /// Longest request line, including the CRLF.
+    pub max_request_line: usize,
+    /// Largest header section, including the CRLF of each field line.
+    pub max_header_size: usize,
+    /// Most field lines in one request.
+    pub max_headers: usize,
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        ParseLimits {
+            max_request_line: 8 * 1024,
+            max_header_size: 64 * 1024,
+            max_headers: 100,
+        }
+    }
+}
+
+/// Why a request head was rejected (RFC 9112).
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    #[error("malformed request line")]
+    BadRequestLine,
+    #[error("invalid request target")]
+    InvalidUri,
+    #[error("invalid token")]
+    InvalidToken,
+    #[error("invalid header field value")]
+    InvalidHeaderValue,
+    #[error("obsolete line folding in header field")]
+    ObsFold,
+    #[error("line ends with a bare LF")]
+    BareLf,
+    #[error("invalid content-length")]
+    InvalidContentLength,
+    #[error("content-length sent more than once")]
+    DuplicateContentLength,
+    #[error("conflicting content-length values")]
+    ConflictingContentLength,
+    #[error("unsupported transfer-encoding")]
+    UnsupportedTransferEncoding,
+    #[error("request line is too long")]
+    UriTooLong,
+    #[error("header section is too large")]
+    HeadersTooLarge,
+    #[error("unsupported http version")]
+    UnsupportedVersion,
+}
+
+impl ParseError {
+    /// The status the server answers this error with.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ParseError::UriTooLong => StatusCode::URI_TOO_LONG,
+            ParseError::HeadersTooLarge => StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            ParseError::UnsupportedVersion => StatusCode::HTTP_VERSION_NOT_SUPPORTED,
+            ParseError::UnsupportedTransferEncoding => StatusCode::NOT_IMPLEMENTED,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+/// Incremental parser for request heads (RFC 9112).
+///
+/// A head split across several reads is not scanned again from the start:
+/// the parser remembers how far it got and where each line begins, and
+/// carries on from there when more bytes arrive. Nothing is copied until the
+/// head is complete. The method, target and header values of the resulting
+/// [`RequestHead`] then share the buffer the head was read into.
+#[derive(Debug)]
+pub struct HeadParser {
+    limits: ParseLimits,
+    /// Start of the line being scanned.
+    pos: usize,
+    /// How far the line being scanned is known to hold no LF.
+    scanned: usize,
+    request_line: Option<RequestLine>,
+    /// Field lines seen so far, without their CRLF.
+    fields: Vec<Range<usize>>,
+    header_size: usize,
+}
+
+#[derive(Debug, Clone)]
+struct RequestLine {
+    method: Range<usize>,
+    target: Range<usize>,
+    version: Version,
+}
+
+/// A parsed request head whose parts point into the buffer it was read into.
+#[derive(Debug)]
+pub struct RequestHead {
+    pub method: Method,
+    pub target: Uri,
+    pub version: Version,
+    pub headers: HeaderMap,
+    /// Length of a `Content-Length` body; 0 for chunked requests.
     pub body_len: usize,
+    pub chunked: bool,
+    /// Both `Transfer-Encoding` and `Content-Length` were sent. The body is
+    /// read as chunked, but an intermediary may have framed it differently,
+    /// so the connection is closed after the response (RFC 9112, 6.1).
+    pub conflicting_framing: bool,
 }
 
-impl<'request> HttpRequest<'request> {
-    pub fn parse_request(
-        request: &'request [u8],
-    ) -> Result<(&'request [u8], Option<HttpRequest<'request>>)> {
-        let mut method: Option<&str> = None;
-        let mut path: Option<&str> = None;
-        let mut headers: HashMap<&str, &str> = HashMap::new();
+impl HeadParser {
+    pub fn new(limits: ParseLimits) -> Self {
+        HeadParser {
+            limits,
+            pos: 0,
+            scanned: 0,
+            request_line: None,
+            fields: Vec::new(),
+            header_size: 0,
+        }
+    }
 
-        let mut remaining: &[u8] = request;
-        loop {
-            let mut i = 0;
-            while i < remaining.len() && remaining[i] != b'\r' {
-                i += 1;
+    /// Parses the request head at the start of `buf`.
+    ///
+    /// Returns `None` until the head is complete; call again once more bytes
+    /// have been appended. The complete head is split off the front of `buf`,
+    /// leaving the body and any pipelined requests, and the parser is ready
+    /// for the next head.
+    pub fn parse(&mut self, buf: &mut BytesMut) -> Result<Option<RequestHead>, ParseError> {
+        let Some(len) = self.scan(buf)? else {
+            return Ok(None);
+        };
+        let parser = std::mem::replace(self, HeadParser::new(self.limits));
+        let line = parser.request_line.expect("request line is parsed first");
+        let head = buf.split_to(len).freeze();
+
+        let method =
+            Method::from_bytes(&head[line.method]).map_err(|_| ParseError::InvalidToken)?;
+        let target = parse_target(method.as_str().as_bytes(), head.slice(line.target))?;
+
+        let mut headers = HeaderMap::with_capacity(parser.fields.len());
+        for field in parser.fields {
+            let (name, value) = split_field(&head[field.clone()])?;
+            let name = HeaderName::from_shared(
+                head.slice(field.start + name.start..field.start + name.end),
+            )
+            .map_err(|_| ParseError::InvalidToken)?;
+            let mut value = HeaderValue::from_shared(
+                head.slice(field.start + value.start..field.start + value.end),
+            )
+            .map_err(|_| ParseError::InvalidHeaderValue)?;
+            if is_sensitive(&name) {
+                value.set_sensitive(true);
             }
-            if i + 1 >= remaining.len() {
-                // Incomplete http request
-                return Ok((request, None));
+            headers.append(name, value);
+        }
+        let (body_len, chunked) = body_framing(&headers)?;
+        let conflicting_framing = chunked && headers.contains_key(CONTENT_LENGTH);
+
+        Ok(Some(RequestHead {
+            method,
+            target,
+            version: line.version,
+            headers,
+            body_len,
+            chunked,
+            conflicting_framing,
+        }))
+    }
+
+    /// Scans `buf` from where the previous call stopped and returns the length
+    /// of the head once its final empty line has been seen.
+    fn scan(&mut self, buf: &[u8]) -> Result<Option<usize>, ParseError> {
+        loop {
+            if self.request_line.is_none() {
+                // Empty lines before the request line are ignored (RFC 9112,
+                // 2.2). They stay in the buffer until the head is split off,
+                // so they count towards the request line limit.
+                if buf[self.pos..].starts_with(b"\r\n") {
+                    self.pos += 2;
+                    self.scanned = self.pos;
+                    continue;
+                }
+                let limit = self.limits.max_request_line.saturating_sub(self.pos);
+                let Some(line) = self.next_line(buf, limit, ParseError::UriTooLong)? else {
+                    return Ok(None);
+                };
+                self.request_line = Some(parse_request_line(buf, line)?);
+                continue;
             }
-            assert_eq!(remaining[i + 1], b'\n');
-            i += 1;
-            // 0../r/n
-            let line = std::str::from_utf8(&remaining[..i - 1])?;
+
+            let limit = self.limits.max_header_size.saturating_sub(self.header_size);
+            let Some(line) = self.next_line(buf, limit, ParseError::HeadersTooLarge)? else {
+                return Ok(None);
+            };
+            self.header_size += line.len() + 2;
             if line.is_empty() {
-                // /r/n/r/n
-                remaining = &remaining[i + 1..];
-                break;
-            } else {
-                if method.is_none() {
-                    let (m, r) = line.split_once(' ').unwrap();
-                    let (p, v) = r.split_once(' ').unwrap();
-                    method = Some(m);
-                    path = Some(p);
-                    assert_eq!(v, "HTTP/1.1");
-                } else {
-                    // http headers
-                    let (key, value) = line.split_once(": ").unwrap();
-                    headers.insert(key, value);
+                return Ok(Some(self.pos));
+            }
+            if buf[line.start] == b' ' || buf[line.start] == b'\t' {
+                return Err(ParseError::ObsFold);
+            }
+            if self.fields.len() == self.limits.max_headers {
+                return Err(ParseError::HeadersTooLarge);
+            }
+            self.fields.push(line);
+        }
+    }
+
+    /// Returns the range of the next CRLF terminated line, without its CRLF,
+    /// and moves past it. Returns `None` if the line is not complete yet. A
+    /// line longer than `limit`, counting the CRLF, fails with `too_long`.
+    fn next_line(
+        &mut self,
+        buf: &[u8],
+        limit: usize,
+        too_long: ParseError,
+    ) -> Result<Option<Range<usize>>, ParseError> {
+        let start = self.pos;
+        let from = self.scanned.max(start);
+        match buf[from..].iter().position(|&b| b == b'\n') {
+            Some(i) => {
+                let lf = from + i;
+                if lf - start + 1 > limit {
+                    return Err(too_long);
                 }
-                remaining = &remaining[i + 1..];
+                if lf == start || buf[lf - 1] != b'\r' {
+                    return Err(ParseError::BareLf);
+                }
+                self.pos = lf + 1;
+                self.scanned = self.pos;
+                Ok(Some(start..lf - 1))
+            }
+            None if buf.len() - start >= limit => Err(too_long),
+            None => {
+                self.scanned = buf.len();
+                Ok(None)
             }
         }
-        let content_length = headers
-            .get("Content-Length")
-            .map(|s| s.parse::<usize>().expect("invalid content-length header"))
-            .unwrap_or(0);
+    }
+}
+
+impl RequestHead {
+    /// Whether the client waits for `100 Continue` before sending the body.
+    pub fn expects_continue(&self) -> bool {
+        expects_continue(self.version, &self.headers)
+    }
 
-        Ok((
-            remaining,
-            Some(HttpRequest {
-                method: method.unwrap(),
-                path: path.unwrap(),
-                headers,
-                body: None,
-                query: Default::default(),
-                body_len: content_length,
-            }),
-        ))
+    /// Whether the connection stays open after this request: the client
+    /// wants it to, and the body was framed unambiguously.
+    pub fn keep_alive(&self) -> bool {
+        !self.conflicting_framing && keep_alive(self.version, &self.headers)
     }
+}
+
+fn expects_continue(version: Version, headers: &HeaderMap) -> bool {
+    version >= Version::HTTP_11
+        && headers
+            .get(EXPECT)
+            .is_some_and(|v| v.as_bytes().eq_ignore_ascii_case(b"100-continue"))
+}
+
+/// HTTP/1.1 connections persist unless the client sends `Connection: close`;
+/// HTTP/1.0 connections close unless it sends `Connection: keep-alive`.
+fn keep_alive(version: Version, headers: &HeaderMap) -> bool {
+    let has_option =
+        |option: &[u8]| tokens(headers, CONNECTION).any(|token| token.eq_ignore_ascii_case(option));
+    if has_option(b"close") {
+        false
+    } else {
+        version >= Version::HTTP_11 || has_option(b"keep-alive")
+    }
+}
+
+/// Works out how the body is delimited: its `Content-Length`, and whether
+/// it is chunked instead.
+fn body_framing(headers: &HeaderMap) -> Result<(usize, bool), ParseError> {
+    // Transfer-Encoding overrides Content-Length. No coding other than
+    // chunked is implemented, so anything but exactly `chunked` is refused
+    // rather than handing the handler a body that is still encoded.
+    let mut codings = tokens(headers, TRANSFER_ENCODING);
+    let chunked = match (codings.next(), codings.next()) {
+        (None, _) => false,
+        (Some(coding), None) if coding.eq_ignore_ascii_case(b"chunked") => true,
+        _ => return Err(ParseError::UnsupportedTransferEncoding),
+    };
+    let content_length = content_length(headers)?;
+    Ok((
+        if chunked {
+            0
+        } else {
+            content_length.unwrap_or(0)
+        },
+        chunked,
+    ))
+}
+
+/// Parses `method SP request-target SP HTTP-version` found at `line` in
+/// `buf`.
+fn parse_request_line(buf: &[u8], line: Range<usize>) -> Result<RequestLine, ParseError> {
+    let bytes = &buf[line.clone()];
+    let mut parts = bytes.split(|&b| b == b' ');
+    let (Some(method), Some(target), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(ParseError::BadRequestLine);
+    };
+
+    if method.is_empty() || !method.iter().all(|&b| is_tchar(b)) {
+        return Err(ParseError::InvalidToken);
+    }
+    if target.is_empty() || !target.iter().all(|b| (0x21..0x7f).contains(b)) {
+        return Err(ParseError::BadRequestLine);
+    }
+    let version = match version {
+        [b'H', b'T', b'T', b'P', b'/', major, b'.', minor]
+            if major.is_ascii_digit() && minor.is_ascii_digit() =>
+        {
+            match (major, minor) {
+                (b'1', b'0') => Version::HTTP_10,
+                // Later 1.x minor versions are compatible with 1.1.
+                (b'1', _) => Version::HTTP_11,
+                _ => return Err(ParseError::UnsupportedVersion),
+            }
+        }
+        _ => return Err(ParseError::BadRequestLine),
+    };
+
+    let method_end = line.start + method.len();
+    let target_start = method_end + 1;
+    Ok(RequestLine {
+        method: line.start..method_end,
+        target: target_start..target_start + target.len(),
+        version,
+    })
+}
+
+/// Parses the request target and checks that its form suits the method:
+/// authority-form is only for `CONNECT` and asterisk-form only for `OPTIONS`.
+fn parse_target(method: &[u8], target: Bytes) -> Result<Uri, ParseError> {
+    let uri = Uri::from_shared(target).map_err(|_| ParseError::InvalidUri)?;
+    let allowed = match uri.form() {
+        Form::Origin | Form::Absolute => method != b"CONNECT",
+        Form::Authority => method == b"CONNECT",
+        Form::Asterisk => method == b"OPTIONS",
+    };
+    if allowed {
+        Ok(uri)
+    } else {
+        Err(ParseError::InvalidUri)
+    }
+}
+
+/// Reads `Content-Length`. Repeated fields, or a list of values, are rejected
+/// even when they agree, since intermediaries may pick a different one.
+fn content_length(headers: &HeaderMap) -> Result<Option<usize>, ParseError> {
+    let mut values = tokens(headers, CONTENT_LENGTH);
+    let Some(first) = values.next() else {
+        return Ok(None);
+    };
+    if let Some(other) = values.next() {
+        return Err(if other == first {
+            ParseError::DuplicateContentLength
+        } else {
+            ParseError::ConflictingContentLength
+        });
+    }
+    if first.is_empty() || !first.iter().all(u8::is_ascii_digit) {
+        return Err(ParseError::InvalidContentLength);
+    }
+    std::str::from_utf8(first)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .map(Some)
+        .ok_or(ParseError::InvalidContentLength)
+}
+
+/// `tchar` from RFC 9110, 5.6.2.
+fn is_tchar(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
 
-    pub fn set_body(&mut self, body: &'request [u8]) {
-        assert_eq!(body.len(), self.body_len);
-        self.body = Some(body);
+/// Parses a `name: value` field line, without the trailing CRLF.
+pub fn parse_header_line(line: &[u8]) -> Result<(HeaderName, HeaderValue), ParseError> {
+    let (name, value) = split_field(line)?;
+    let name = HeaderName::from_bytes(&line[name]).map_err(|_| ParseError::InvalidToken)?;
+    let mut value =
+        HeaderValue::from_bytes(&line[value]).map_err(|_| ParseError::InvalidHeaderValue)?;
+    if is_sensitive(&name) {
+        value.set_sensitive(true);
     }
+    Ok((name, value))
+}
+
+/// Splits a field line into the range of its name and of its value, with
+/// the optional whitespace around the value left out.
+fn split_field(line: &[u8]) -> Result<(Range<usize>, Range<usize>), ParseError> {
+    let colon = line
+        .iter()
+        .position(|&b| b == b':')
+        .ok_or(ParseError::InvalidToken)?;
+    let is_ows = |b: &u8| *b == b' ' || *b == b'\t';
+    let value = &line[colon + 1..];
+    let start = value.iter().position(|b| !is_ows(b)).unwrap_or(value.len());
+    let end = value
+        .iter()
+        .rposition(|b| !is_ows(b))
+        .map_or(start, |i| i + 1);
+    Ok((0..colon, colon + 1 + start..colon + 1 + end))
+}
+
+/// Iterates the comma separated tokens of every `name` field.
+pub(crate) fn tokens<'a>(
+    headers: &'a HeaderMap,
+    name: HeaderName,
+) -> impl Iterator<Item = &'a [u8]> + 'a {
+    headers
+        .get_all(name)
+        .flat_map(|value| value.as_bytes().split(|&b| b == b','))
+        .map(trim_ows)
+        .filter(|token| !token.is_empty())
+}
+
+/// Strips optional whitespace (`OWS`) around a field value.
+fn trim_ows(value: &[u8]) -> &[u8] {
+    let is_ows = |b: &u8| *b == b' ' || *b == b'\t';
+    let start = value.iter().position(|b| !is_ows(b)).unwrap_or(value.len());
+    let end = value
+        .iter()
+        .rposition(|b| !is_ows(b))
+        .map_or(start, |i| i + 1);
+    &value[start..end]
+}
+
+/// Credentials that must never show up in `Debug` output.
+pub(crate) fn is_sensitive(name: &HeaderName) -> bool {
+    *name == AUTHORIZATION || *name == PROXY_AUTHORIZATION || *name == COOKIE || *name == SET_COOKIE
 }
 #[cfg(test)]
 mod tests {
-    use super::HttpRequest;
+    use super::{HeadParser, ParseError, ParseLimits, RequestHead};
+    use crate::status::StatusCode;
+    use crate::version::Version;
+    use bytes::BytesMut;
+
+    /// Parses the head at the start of `input`, returning it and the bytes
+    /// after it.
+    fn parse(input: &[u8]) -> (Option<RequestHead>, BytesMut) {
+        let mut buf = BytesMut::from(input);
+        let head = HeadParser::new(ParseLimits::default())
+            .parse(&mut buf)
+            .unwrap();
+        (head, buf)
+    }
+
+    fn parse_err_with(input: &[u8], limits: ParseLimits) -> ParseError {
+        match HeadParser::new(limits).parse(&mut BytesMut::from(input)) {
+            Err(e) => e,
+            Ok(_) => panic!("{:?} parsed", String::from_utf8_lossy(input)),
+        }
+    }
+
+    fn parse_err(input: &[u8]) -> ParseError {
+        parse_err_with(input, ParseLimits::default())
+    }
 
     #[test]
     fn parse_no_headers_no_body() {
-        let input = b"GET /index.html HTTP/1.1\r\n\r\n";
-        let (rest, request) = HttpRequest::parse_request(input).unwrap();
+        let (head, rest) = parse(b"GET /index.html HTTP/1.1\r\n\r\n");
 
         assert!(rest.is_empty());
 
-        let request = request.unwrap();
-        assert_eq!(request.method, "GET");
-        assert_eq!(request.path, "/index.html");
-        assert!(request.headers.is_empty());
-        assert_eq!(request.body_len, 0);
-        assert!(request.body.is_none());
+        let head = head.unwrap();
+        assert_eq!(head.method, "GET");
+        assert_eq!(head.target.path(), "/index.html");
+        assert_eq!(head.version, Version::HTTP_11);
+        assert!(head.headers.is_empty());
+        assert_eq!(head.body_len, 0);
+        assert!(!head.chunked);
     }
 
     #[test]
     fn parse_headers_no_body() {
-        let input =
-            b"GET /index.html HTTP/1.1\r\nHost: localhost:4221\r\nUser-Agent: curl/7.64.1\r\n\r\n";
-        let (rest, request) = HttpRequest::parse_request(input).unwrap();
+        let (head, rest) = parse(
+            b"GET /index.html HTTP/1.1\r\nHost: localhost:4221\r\nUser-Agent: curl/7.64.1\r\n\r\n",
+        );
 
         assert!(rest.is_empty());
 
-        let request = request.unwrap();
-        assert_eq!(request.method, "GET");
-        assert_eq!(request.path, "/index.html");
-        assert_eq!(request.headers.len(), 2);
-        assert_eq!(request.headers.get("Host"), Some(&"localhost:4221"));
-        assert_eq!(request.headers.get("User-Agent"), Some(&"curl/7.64.1"));
-        assert_eq!(request.body_len, 0);
-        assert!(request.body.is_none());
+        let head = head.unwrap();
+        assert_eq!(head.method, "GET");
+        assert_eq!(head.target.path(), "/index.html");
+        assert_eq!(head.headers.len(), 2);
+        assert_eq!(head.headers.get("Host").unwrap(), "localhost:4221");
+        assert_eq!(head.headers.get("User-Agent").unwrap(), "curl/7.64.1");
+        assert_eq!(head.body_len, 0);
     }
 
     #[test]
     fn parse_headers_body() {
-        let input =
-            b"GET /index.html HTTP/1.1\r\nHost: localhost:4221\r\nUser-Agent: curl/7.64.1\r\nContent-Length: 3\r\n\r\nfoo";
-        let (rest, request) = HttpRequest::parse_request(input).unwrap();
+        let (head, rest) = parse(
+            b"GET /index.html HTTP/1.1\r\nHost: localhost:4221\r\nUser-Agent: curl/7.64.1\r\nContent-Length: 3\r\n\r\nfoo",
+        );
 
-        assert_eq!(rest, b"foo");
+        assert_eq!(&rest[..], b"foo");
 
-        let mut request = request.unwrap();
-        assert_eq!(request.method, "GET");
-        assert_eq!(request.path, "/index.html");
-        assert_eq!(request.headers.len(), 3);
-        assert_eq!(request.headers.get("Host"), Some(&"localhost:4221"));
-        assert_eq!(request.headers.get("User-Agent"), Some(&"curl/7.64.1"));
-        assert_eq!(request.headers.get("Content-Length"), Some(&"3"));
-        assert_eq!(request.body_len, 3);
-        assert!(request.body.is_none());
+        let head = head.unwrap();
+        assert_eq!(head.method, "GET");
+        assert_eq!(head.target.path(), "/index.html");
+        assert_eq!(head.headers.len(), 3);
+        assert_eq!(head.headers.get("Host").unwrap(), "localhost:4221");
+        assert_eq!(head.headers.get("User-Agent").unwrap(), "curl/7.64.1");
+        assert_eq!(head.headers.get("Content-Length").unwrap(), "3");
+        assert_eq!(head.body_len, 3);
+    }
 
-        request.set_body(rest);
-        assert_eq!(request.body.unwrap(), b"foo");
+    #[test]
+    fn parse_opaque_and_sensitive_values() {
+        let (head, _) =
+            parse(b"GET / HTTP/1.1\r\nX-Name: caf\xe9\r\nAuthorization:Basic Zm9v \r\n\r\n");
+
+        let head = head.unwrap();
+        assert_eq!(head.headers.get("x-name").unwrap().as_bytes(), b"caf\xe9");
+        let auth = head.headers.get("authorization").unwrap();
+        assert_eq!(auth, "Basic Zm9v");
+        assert!(auth.is_sensitive());
+        assert!(!format!("{:?}", head.headers).contains("Zm9v"));
+    }
+
+    #[test]
+    fn keep_alive_defaults_per_version() {
+        let cases: [(&[u8], bool); 5] = [
+            (b"GET / HTTP/1.1\r\n\r\n", true),
+            (b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n", false),
+            (b"GET / HTTP/1.0\r\n\r\n", false),
+            (b"GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n", true),
+            (
+                b"GET / HTTP/1.1\r\nConnection: upgrade, close\r\n\r\n",
+                false,
+            ),
+        ];
+        for (input, keep_alive) in cases {
+            let (head, _) = parse(input);
+            assert_eq!(head.unwrap().keep_alive(), keep_alive);
+        }
+    }
+
+    #[test]
+    fn parse_pipelined_requests() {
+        let mut buf = BytesMut::from(&b"GET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\n\r\n"[..]);
+        let mut parser = HeadParser::new(ParseLimits::default());
+        let head = parser.parse(&mut buf).unwrap().unwrap();
+        assert_eq!(head.target.path(), "/a");
+        let head = parser.parse(&mut buf).unwrap().unwrap();
+        assert_eq!(head.target.path(), "/b");
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn parse_chunked_ignores_content_length() {
+        let input =
+            b"POST /files/a HTTP/1.1\r\nContent-Length: 3\r\nTransfer-Encoding: Chunked\r\n\r\n";
+        let (head, _) = parse(input);
+
+        let head = head.unwrap();
+        assert!(head.chunked);
+        assert_eq!(head.body_len, 0);
+        assert!(head.conflicting_framing);
+        assert!(!head.keep_alive());
+
+        for codings in ["chunked, gzip", "gzip, chunked", "chunked, chunked"] {
+            let input = format!("POST /files/a HTTP/1.1\r\nTransfer-Encoding: {codings}\r\n\r\n");
+            let err = parse_err(input.as_bytes());
+            assert_eq!(err, ParseError::UnsupportedTransferEncoding);
+            assert_eq!(err.status(), StatusCode::NOT_IMPLEMENTED);
+        }
     }
 
     #[test]
     fn parse_incomplete_header() {
         {
             let input = b"GET /index.html HTTP/1.1\r\nHost: localhos";
-            let (rest, request) = HttpRequest::parse_request(input).unwrap();
-            assert_eq!(rest, input);
-            assert!(request.is_none());
+            let (head, rest) = parse(input);
+            assert_eq!(&rest[..], input);
+            assert!(head.is_none());
         }
 
         {
             let input = b"GET /index.html HTTP/1.1\r\nHost: localhost:4221\r\n";
-            let (rest, request) = HttpRequest::parse_request(input).unwrap();
-            assert_eq!(rest, input);
-            assert!(request.is_none());
+            let (head, rest) = parse(input);
+            assert_eq!(&rest[..], input);
+            assert!(head.is_none());
         }
     }
+
+    #[test]
+    fn reject_malformed_request_lines() {
+        let cases: [(&[u8], ParseError); 7] = [
+            (b"GET /\r\n\r\n", ParseError::BadRequestLine),
+            (b"GET  / HTTP/1.1\r\n\r\n", ParseError::BadRequestLine),
+            (b"GET / HTTP/1.1 x\r\n\r\n", ParseError::BadRequestLine),
+            (b"GE(T / HTTP/1.1\r\n\r\n", ParseError::InvalidToken),
+            (b"GET /\x01 HTTP/1.1\r\n\r\n", ParseError::BadRequestLine),
+            (b"GET / HTTX/1.1\r\n\r\n", ParseError::BadRequestLine),
+            (b"GET / HTTP/2.0\r\n\r\n", ParseError::UnsupportedVersion),
+        ];
+        for (input, err) in cases {
+            assert_eq!(parse_err(input), err);
+        }
+        assert_eq!(
+            ParseError::UnsupportedVersion.status(),
+            StatusCode::HTTP_VERSION_NOT_SUPPORTED
+        );
+    }
+
+    #[test]
+    fn reject_malformed_fields() {
+        let cases: [(&[u8], ParseError); 8] = [
+            (b"GET / HTTP/1.1\nHost: a\r\n\r\n", ParseError::BareLf),
+            (b"GET / HTTP/1.1\r\nHost: a\n\r\n", ParseError::BareLf),
+            (
+                b"GET / HTTP/1.1\r\nX-A: a\r\n  b\r\n\r\n",
+                ParseError::ObsFold,
+            ),
+            (
+                b"GET / HTTP/1.1\r\nHost : a\r\n\r\n",
+                ParseError::InvalidToken,
+            ),
+            (
+                b"GET / HTTP/1.1\r\nno colon\r\n\r\n",
+                ParseError::InvalidToken,
+            ),
+            (
+                b"GET / HTTP/1.1\r\nX-A: a\x00b\r\n\r\n",
+                ParseError::InvalidHeaderValue,
+            ),
+            (
+                b"GET / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n",
+                ParseError::UnsupportedTransferEncoding,
+            ),
+            (
+                b"GET / HTTP/1.1\r\nContent-Length: -1\r\n\r\n",
+                ParseError::InvalidContentLength,
+            ),
+        ];
+        for (input, err) in cases {
+            assert_eq!(parse_err(input), err);
+        }
+    }
+
+    #[test]
+    fn reject_duplicate_and_conflicting_content_length() {
+        assert_eq!(
+            parse_err(b"POST / HTTP/1.1\r\nContent-Length: 3\r\nContent-Length: 3\r\n\r\n"),
+            ParseError::DuplicateContentLength
+        );
+        assert_eq!(
+            parse_err(b"POST / HTTP/1.1\r\nContent-Length: 3, 3\r\n\r\n"),
+            ParseError::DuplicateContentLength
+        );
+        assert_eq!(
+            parse_err(b"POST / HTTP/1.1\r\nContent-Length: 3\r\nContent-Length: 4\r\n\r\n"),
+            ParseError::ConflictingContentLength
+        );
+    }
+
+    #[test]
+    fn reject_oversized_heads_before_they_complete() {
+        let limits = ParseLimits {
+            max_request_line: 32,
+            max_header_size: 64,
+            max_headers: 2,
+        };
+        let long_target = format!("GET /{} HTTP/1.1", "a".repeat(40));
+        assert_eq!(
+            parse_err_with(long_target.as_bytes(), limits),
+            ParseError::UriTooLong
+        );
+
+        let big_header = format!("GET / HTTP/1.1\r\nX-A: {}", "a".repeat(80));
+        let err = parse_err_with(big_header.as_bytes(), limits);
+        assert_eq!(err, ParseError::HeadersTooLarge);
+        assert_eq!(err.status(), StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+
+        let many = b"GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n";
+        assert_eq!(parse_err_with(many, limits), ParseError::HeadersTooLarge);
+
+        let empty_lines = "\r\n".repeat(16);
+        assert_eq!(
+            parse_err_with(empty_lines.as_bytes(), limits),
+            ParseError::UriTooLong
+        );
+        let after_empty_lines = format!("{}GET / HTTP/1.1\r\n\r\n", "\r\n".repeat(10));
+        assert_eq!(
+            parse_err_with(after_empty_lines.as_bytes(), limits),
+            ParseError::UriTooLong
+        );
+    }
+
+    #[test]
+    fn skip_leading_empty_lines_and_accept_later_minor_versions() {
+        let (head, _) = parse(b"\r\nGET / HTTP/1.2\r\n\r\n");
+        assert_eq!(head.unwrap().version, Version::HTTP_11);
+    }
+
+    #[test]
+    fn head_parser_resumes_across_reads() {
+        let input = format!(
+            "GET /echo/abc HTTP/1.1\r\nHost: localhost\r\nX-Big: {}\r\n\r\nbody",
+            "v".repeat(3000)
+        );
+        let mut parser = HeadParser::new(ParseLimits::default());
+        let mut buf = BytesMut::new();
+        let mut head = None;
+        for chunk in input.as_bytes().chunks(7) {
+            buf.extend_from_slice(chunk);
+            if let Some(parsed) = parser.parse(&mut buf).unwrap() {
+                head = Some(parsed);
+                break;
+            }
+        }
+        let head = head.unwrap();
+        assert_eq!(head.method, "GET");
+        assert_eq!(head.target.path(), "/echo/abc");
+        assert_eq!(head.headers.get("x-big").unwrap().len(), 3000);
+        assert!(b"body".starts_with(&buf[..]));
+    }
+
+    #[test]
+    fn head_parser_shares_the_buffer() {
+        let mut buf = BytesMut::from(
+            &b"POST /files/a HTTP/1.1\r\nx-custom: yes\r\nContent-Length: 3\r\n\r\nabcGET / HTTP/1.1\r\n\r\n"[..],
+        );
+        let start = buf.as_ptr() as usize;
+        let range = start..start + buf.len();
+        let mut parser = HeadParser::new(ParseLimits::default());
+
+        let head = parser.parse(&mut buf).unwrap().unwrap();
+        assert!(range.contains(&(head.target.path().as_ptr() as usize)));
+        let (name, value) = head.headers.iter().next().unwrap();
+        assert!(range.contains(&(name.as_str().as_ptr() as usize)));
+        assert!(range.contains(&(value.as_bytes().as_ptr() as usize)));
+        assert_eq!(head.body_len, 3);
+
+        let _ = buf.split_to(head.body_len);
+        let next = parser.parse(&mut buf).unwrap().unwrap();
+        assert_eq!(next.target.path(), "/");
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn parse_query_and_target_forms() {
+        let (head, _) = parse(b"GET /search?q=a+b&tag=x&tag=y%21 HTTP/1.1\r\n\r\n");
+        let head = head.unwrap();
+        assert_eq!(head.target.path(), "/search");
+        let query = head.target.query_pairs();
+        assert_eq!(query.get("q"), Some("a b"));
+        assert_eq!(query.get_all("tag").collect::<Vec<_>>(), ["x", "y!"]);
+
+        assert!(parse(b"OPTIONS * HTTP/1.1\r\n\r\n").0.is_some());
+        assert!(parse(b"CONNECT a.com:443 HTTP/1.1\r\n\r\n").0.is_some());
+        assert_eq!(parse_err(b"GET * HTTP/1.1\r\n\r\n"), ParseError::InvalidUri);
+        assert_eq!(
+            parse_err(b"GET a.com:443 HTTP/1.1\r\n\r\n"),
+            ParseError::InvalidUri
+        );
+        assert_eq!(
+            parse_err(b"GET /%zz HTTP/1.1\r\n\r\n"),
+            ParseError::InvalidUri
+        );
+    }
 }
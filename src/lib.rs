@@ -0,0 +1,42 @@
+//! A small HTTP/1.1 and HTTP/2 server.
+//!
+//! Services implement [`Handler`], usually by registering functions on a
+//! [`Router`], and hand it to [`server::serve`].
+
+pub mod body;
+mod byte_str;
+mod chunked;
+pub mod compression;
+pub mod conditional;
+pub mod config;
+mod conn;
+pub mod decompression;
+pub mod files;
+mod h2;
+pub mod handler;
+pub mod header;
+mod help;
+pub mod location;
+pub mod method;
+pub mod range;
+pub mod reload;
+pub mod request;
+pub mod response;
+pub mod router;
+pub mod server;
+pub mod status;
+pub mod tls;
+pub mod uri;
+pub mod version;
+pub mod vhost;
+
+pub use body::Body;
+pub use files::StaticFiles;
+pub use handler::Handler;
+pub use method::Method;
+pub use request::Request;
+pub use response::Response;
+pub use router::Router;
+pub use status::StatusCode;
+pub use uri::Uri;
+pub use version::Version;
@@ -0,0 +1,167 @@
+//! Settings that apply to the requests under a path prefix.
+
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use crate::handler::{BoxFuture, BoxHandler, Handler};
+use crate::header::{HeaderMap, HeaderName, HeaderValue};
+use crate::request::Request;
+use crate::response::Response;
+use crate::router::decode_segments;
+use crate::status::StatusCode;
+
+/// Applies per-location settings around another handler.
+///
+/// A request belongs to the location with the longest prefix that matches
+/// whole segments of its path, so `/static` covers `/static` and
+/// `/static/app.js` but not `/statics`. Paths are decoded segment by segment
+/// before they are compared, as the [`Router`](crate::Router) does, so
+/// `/%75pload` is under `/upload`. Requests under no location pass through
+/// untouched.
+///
+/// ```ignore
+/// let handler = Locations::new(router)
+///     .location(Location::new("/upload").max_body_size(1 << 20))
+///     .location(Location::new("/static").header(CACHE_CONTROL, HeaderValue::from_static("max-age=3600")));
+/// ```
+pub struct Locations<H = BoxHandler> {
+    inner: Arc<H>,
+    /// Longest prefix first.
+    locations: Arc<Vec<Location>>,
+}
+
+/// The settings for one path prefix.
+#[derive(Debug, Clone)]
+pub struct Location {
+    prefix: String,
+    max_body_size: Option<usize>,
+    headers: HeaderMap,
+}
+
+impl Location {
+    /// A location for `prefix`. A trailing `/` is ignored.
+    pub fn new(prefix: impl Into<String>) -> Location {
+        let mut prefix = prefix.into();
+        while prefix.ends_with('/') {
+            prefix.pop();
+        }
+        Location {
+            prefix,
+            max_body_size: None,
+            headers: HeaderMap::new(),
+        }
+    }
+
+    /// Answers requests with bodies larger than `max`, in bytes, with `413
+    /// Content Too Large`. The server-wide limit still applies.
+    pub fn max_body_size(mut self, max: usize) -> Location {
+        self.max_body_size = Some(max);
+        self
+    }
+
+    /// Sets `name` on every response, replacing the value set by the handler.
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Location {
+        self.headers.insert(name, value);
+        self
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Whether the decoded `segments` of a path start with the prefix.
+    fn matches(&self, segments: &[Cow<'_, str>]) -> bool {
+        let prefix = self.prefix.strip_prefix('/').unwrap_or(&self.prefix);
+        let mut segments = segments.iter();
+        prefix.is_empty()
+            || prefix
+                .split('/')
+                .all(|p| segments.next().is_some_and(|s| s == p))
+    }
+}
+
+impl<H: Handler> Locations<H> {
+    pub fn new(inner: H) -> Locations<H> {
+        Locations {
+            inner: Arc::new(inner),
+            locations: Arc::new(Vec::new()),
+        }
+    }
+
+    /// Adds `location`. A location with the same prefix as an earlier one
+    /// replaces it.
+    pub fn location(mut self, location: Location) -> Locations<H> {
+        let locations = Arc::make_mut(&mut self.locations);
+        locations.retain(|l| l.prefix != location.prefix);
+        locations.push(location);
+        locations.sort_by_key(|l| usize::MAX - l.prefix.len());
+        self
+    }
+}
+
+impl<H: Handler> Handler for Locations<H> {
+    fn call(&self, req: Request) -> BoxFuture<'static, Result<Response>> {
+        let inner = Arc::clone(&self.inner);
+        let locations = Arc::clone(&self.locations);
+        Box::pin(async move {
+            let found = decode_segments(req.path())
+                .and_then(|segments| locations.iter().position(|l| l.matches(&segments)));
+            let Some(location) = found.map(|i| &locations[i]) else {
+                return inner.call(req).await;
+            };
+            if matches!(location.max_body_size, Some(max) if req.body().len() > max) {
+                return Ok(Response::with_status(StatusCode::PAYLOAD_TOO_LARGE));
+            }
+            let mut res = inner.call(req).await?;
+            for (name, value) in &location.headers {
+                res.headers_mut().insert(name.clone(), value.clone());
+            }
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Location, Locations};
+    use crate::handler::sync;
+    use crate::header::{HeaderValue, CACHE_CONTROL};
+    use crate::request::Request;
+    use crate::response::Response;
+    use crate::Handler;
+
+    #[tokio::test]
+    async fn apply_the_longest_matching_location() {
+        let handler = Locations::new(sync(|_| Ok(Response::new("ok".into()))))
+            .location(
+                Location::new("/static/")
+                    .header(CACHE_CONTROL, HeaderValue::from_static("max-age=60")),
+            )
+            .location(
+                Location::new("/static/private")
+                    .header(CACHE_CONTROL, HeaderValue::from_static("no-store")),
+            )
+            .location(Location::new("/upload").max_body_size(4));
+        let call = |path: &'static str, body: &'static str| {
+            let mut req = Request::new(body.into());
+            *req.uri_mut() = path.parse().unwrap();
+            handler.call(req)
+        };
+
+        let res = call("/static/app.js", "").await.unwrap();
+        assert_eq!(res.headers().get(CACHE_CONTROL).unwrap(), "max-age=60");
+        let res = call("/static/private/key", "").await.unwrap();
+        assert_eq!(res.headers().get(CACHE_CONTROL).unwrap(), "no-store");
+        let res = call("/statics", "").await.unwrap();
+        assert!(res.headers().get(CACHE_CONTROL).is_none());
+
+        assert_eq!(call("/upload/a", "1234").await.unwrap().status(), 200);
+        assert_eq!(call("/upload/a", "12345").await.unwrap().status(), 413);
+        assert_eq!(call("/%75pload/b", "12345").await.unwrap().status(), 413);
+        assert_eq!(call("/upload", "12345").await.unwrap().status(), 413);
+        assert_eq!(call("/upload%2Fb", "12345").await.unwrap().status(), 200);
+        assert_eq!(call("/other", "12345").await.unwrap().status(), 200);
+    }
+}
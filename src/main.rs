@@ -1,193 +1,146 @@
-use std::fs::create_dir;
-// Uncomment this block to pass the first stage
-use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream};
-use std::path::Path;
+use anyhow::Result;
+use http_server_starter_rust::compression::Compression;
+use http_server_starter_rust::config::{self, FileConfig};
+use http_server_starter_rust::decompression::Decompression;
+use http_server_starter_rust::handler::BoxHandler;
+use http_server_starter_rust::reload::Reloadable;
+use http_server_starter_rust::server;
+use http_server_starter_rust::tls::Tls;
 
-use anyhow::{Error, Result};
-use help::HttpRequest;
-
-mod byte_str;
-mod header;
-mod help;
-mod method;
-mod status;
-mod version;
+mod cli;
+mod routes;
 
 fn main() -> Result<()> {
+    let args = cli::Args::parse();
+    let file = args.config.as_deref().map(config::load).transpose()?;
+    let routes = Reloadable::new(site(&args, file.as_ref())?);
+    let tls = args.tls_settings(file.as_ref());
+    let tls = tls.map(|settings| Tls::new(&settings)).transpose()?;
+
     // You can use print statements as follows for debugging, they'll be visible when running tests.
     println!("Logs from your program will appear here again!");
 
-    // Uncomment this block to pass the first stage
-    //
-    let listener = TcpListener::bind("127.0.0.1:4221").unwrap();
-
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
-                std::thread::spawn(move || {
-                    handle_client_request(stream);
-                });
-            }
-            Err(e) => {
-                println!("error: {}", e);
-            }
+    let mut config = args.server_config(file.as_ref());
+    config.tls = tls.clone();
+    let handler = Decompression::new(routes.clone()).max_size(config.max_body_size);
+    let handler = Compression::new(handler);
+    config.runtime()?.block_on(async move {
+        if args.config.is_some() || tls.is_some() {
+            tokio::spawn(reload_on_hangup(args, file, routes, tls));
         }
-    }
-    Ok(())
+        server::serve_with_shutdown(config, handler, shutdown_signal()).await
+    })
 }
 
-fn handle_client_request(mut stream: TcpStream) {
-    let mut buf = [0_u8; 1024];
-    loop {
-        match stream.read(&mut buf) {
-            Ok(0) => {
-                break;
-            }
-            Ok(len) => {
-                if let Ok((rest, Some(mut req))) = HttpRequest::parse_request(&buf[..len]) {
-                    let mut body = rest.to_vec();
-                    if req.body_len > 0 {
-                        body.resize(req.body_len, 0);
-                        stream.read_exact(&mut body[rest.len()..]).unwrap();
-                        req.set_body(&body);
-                    }
-
-                    // todo handle business and response
-                    let method = HttpMethod::try_from(req.method).unwrap();
-                    let path = req.path;
-                    match method {
-                        HttpMethod::Get => match path {
-                            "/" => {
-                                stream.write_all(b"HTTP/1.1 200 OK\r\n\r\n").unwrap();
-                            }
-                            "/user-agent" => {
-                                let user_agent = req.headers.get("User-Agent").unwrap();
-                                ok(&mut stream, user_agent.as_bytes(), "text/plain");
-                            }
-                            path => {
-                                if path.starts_with("/echo/") && method == HttpMethod::Get {
-                                    let response_content =
-                                        path.strip_prefix("/echo/").unwrap_or("");
-                                    ok(&mut stream, response_content.as_bytes(), "text/plain");
-                                } else if path.starts_with("/files/") {
-                                    let mut args = std::env::args();
-                                    let dir = args
-                                        .nth(2)
-                                        .and_then(|s| Some(s.trim().to_string()))
-                                        .unwrap_or_default();
-                                    let filename = path.strip_prefix("/files/").and_then(|file| {
-                                        let mut path = Path::new(dir.as_str()).to_path_buf();
-                                        path.push(file);
-                                        println!("path: {:?}", path);
-                                        std::fs::read(path).ok()
-                                    });
-                                    if let Some(file) = filename {
-                                        ok(
-                                            &mut stream,
-                                            file.as_slice(),
-                                            "application/octet-stream",
-                                        );
-                                    } else {
-                                        not_found(&mut stream);
-                                    }
-                                } else {
-                                    not_found(&mut stream);
-                                }
-                            }
-                        },
-                        HttpMethod::Post => match path {
-                            path if path.starts_with("/files/") => {
-                                let mut args = std::env::args();
-                                let dir = args
-                                    .nth(2)
-                                    .and_then(|s| Some(s.trim().to_string()))
-                                    .unwrap_or_default();
-                                let path = path.strip_prefix("/files/").and_then(|file| {
-                                    let mut path = Path::new(dir.as_str()).to_path_buf();
-                                    if !path.exists() {
-                                        let _ = create_dir(&path);
-                                    }
-                                    path.push(file);
-                                    Some(path)
-                                });
-
-                                // read file bytes from stream.
-
-                                if let Some(file) = path {
-                                    // ok201(&mut stream, file.as_slice(), "application/octet-stream");
-                                    if let Some(body) = req.body {
-                                        std::fs::write(file, body).unwrap();
-                                    }
-                                    ok201(&mut stream, "write ok".as_bytes(), "text/plain");
-                                } else {
-                                }
-                                ok201(&mut stream, "file not found".as_bytes(), "text/plain");
-                            }
+/// Completes on the first SIGINT or SIGTERM.
+#[cfg(unix)]
+async fn shutdown_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
 
-                            _ => not_found(&mut stream),
-                        },
-                        _ => {}
-                    }
-                    stream.flush().unwrap();
-                    break;
-                } else {
-                    // incomplete request, continue read more bytes from client.
-                    continue;
-                }
-            }
-            Err(e) => {
-                panic!("failed to read from stream for: {:?}", e);
-            }
+    let (mut interrupt, mut terminate) = match (
+        signal(SignalKind::interrupt()),
+        signal(SignalKind::terminate()),
+    ) {
+        (Ok(interrupt), Ok(terminate)) => (interrupt, terminate),
+        (Err(e), _) | (_, Err(e)) => {
+            println!("cannot shut down gracefully: {}", e);
+            return std::future::pending().await;
         }
+    };
+    tokio::select! {
+        _ = interrupt.recv() => println!("received SIGINT"),
+        _ = terminate.recv() => println!("received SIGTERM"),
     }
 }
 
-fn not_found(stream: &mut TcpStream) {
-    stream.write_all(b"HTTP/1.1 404 Not Found\r\n\r\n").unwrap();
+/// Completes on the first Ctrl-C.
+#[cfg(not(unix))]
+async fn shutdown_signal() {
+    if tokio::signal::ctrl_c().await.is_err() {
+        std::future::pending().await
+    }
 }
 
-fn ok(stream: &mut TcpStream, body: &[u8], content_type: &str) {
-    stream.write(b"HTTP/1.1 200 OK\r\n").unwrap();
-    stream
-        .write(format!("Content-Type: {}\r\n", content_type).as_bytes())
-        .unwrap();
-    stream
-        .write(format!("Content-Length: {}\r\n\r\n", body.len()).as_bytes())
-        .unwrap();
-    stream.write_all(body).unwrap();
+/// The routes: the vhosts of the config file if it has any, or else the
+/// endpoints with `/files` serving `--directory`.
+fn site(args: &cli::Args, file: Option<&FileConfig>) -> Result<BoxHandler> {
+    match file {
+        Some(file) if !file.hosts.is_empty() => {
+            anyhow::ensure!(
+                args.directory.is_none(),
+                "--directory cannot be combined with vhosts from the config file"
+            );
+            Ok(routes::virtual_hosts(&file.hosts).into())
+        }
+        _ => Ok(routes::router(args.directory.clone(), args.cache_control.clone()).into()),
+    }
 }
 
-fn ok201(stream: &mut TcpStream, body: &[u8], content_type: &str) {
-    stream.write(b"HTTP/1.1 201 OK\r\n").unwrap();
-    stream
-        .write(format!("Content-Type: {}\r\n", content_type).as_bytes())
-        .unwrap();
-    stream
-        .write(format!("Content-Length: {}\r\n\r\n", body.len()).as_bytes())
-        .unwrap();
-    stream.write_all(body).unwrap();
-}
+/// Reloads the config file and the certificates on every SIGHUP, and swaps
+/// in the new routes and certificates. If anything fails to load, all of the
+/// running config is kept. Listeners and `[server]` settings only change on a
+/// restart.
+#[cfg(unix)]
+async fn reload_on_hangup(
+    args: cli::Args,
+    mut running: Option<FileConfig>,
+    routes: Reloadable,
+    tls: Option<Tls>,
+) {
+    use tokio::signal::unix::{signal, SignalKind};
 
-#[derive(PartialEq, Eq)]
-enum HttpMethod {
-    Get,
-    Post,
-    Put,
-    Delete,
-    Trace,
-}
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            println!("cannot reload on SIGHUP: {}", e);
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        match reload(&args, &routes, tls.as_ref()) {
+            Ok(file) => {
+                println!("reloaded");
+                let restart = match (&file, &running) {
+                    (Some(file), Some(running)) => {
+                        file.server != running.server
+                            || file.listeners != running.listeners
+                            || file.tls_listeners != running.tls_listeners
+                    }
+                    _ => false,
+                };
+                if restart {
+                    println!("listener and [server] changes apply after a restart");
+                }
+                // The new vhosts and certificates are live now, while the
+                // listeners and [server] settings stay those started with.
+                running = file.map(|file| match running.take() {
+                    Some(started) => FileConfig {
+                        server: started.server,
+                        listeners: started.listeners,
+                        tls_listeners: started.tls_listeners,
+                        ..file
+                    },
+                    None => file,
+                });
+            }
+            Err(e) => println!("keeping the running config: {:#}", e),
+        }
+    }
 
-impl TryFrom<&str> for HttpMethod {
-    type Error = anyhow::Error;
-    fn try_from(value: &str) -> std::result::Result<Self, Self::Error> {
-        Ok(match value.as_bytes() {
-            b"GET" => HttpMethod::Get,
-            b"POST" => HttpMethod::Post,
-            b"PUT" => HttpMethod::Put,
-            b"TRACE" => HttpMethod::Trace,
-            b"DELETE" => HttpMethod::Delete,
-            _ => return Err(Error::msg("Method not supported!")),
-        })
+    fn reload(
+        args: &cli::Args,
+        routes: &Reloadable,
+        tls: Option<&Tls>,
+    ) -> Result<Option<FileConfig>> {
+        let file = args.config.as_deref().map(config::load).transpose()?;
+        let site = site(args, file.as_ref())?;
+        if let (Some(tls), Some(settings)) = (tls, args.tls_settings(file.as_ref())) {
+            tls.reload(&settings)?;
+        }
+        routes.replace(site);
+        Ok(file)
     }
 }
+
+#[cfg(not(unix))]
+async fn reload_on_hangup(_: cli::Args, _: Option<FileConfig>, _: Reloadable, _: Option<Tls>) {}
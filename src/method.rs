@@ -97,10 +97,10 @@ impl Method {
     }
 
     pub fn is_safe(&self) -> bool {
-        match self.0 {
-            Inner::Get | Inner::Head | Inner::Options | Inner::Trace => true,
-            _ => false,
-        }
+        matches!(
+            self.0,
+            Inner::Get | Inner::Head | Inner::Options | Inner::Trace
+        )
     }
 
     pub fn is_idempotent(&self) -> bool {
@@ -141,7 +141,7 @@ impl<'a> PartialEq<&'a Method> for Method {
     }
 }
 
-impl<'a> PartialEq<Method> for &'a Method {
+impl PartialEq<Method> for &Method {
     #[inline]
     fn eq(&self, other: &Method) -> bool {
         *self == other
@@ -169,7 +169,7 @@ impl<'a> PartialEq<&'a str> for Method {
     }
 }
 
-impl<'a> PartialEq<Method> for &'a str {
+impl PartialEq<Method> for &str {
     #[inline]
     fn eq(&self, other: &Method) -> bool {
         *self == other.as_ref()
@@ -226,7 +226,7 @@ impl FromStr for Method {
 }
 
 impl InvalidMethod {
-    pub fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Self { _priv: () }
     }
 }
@@ -0,0 +1,259 @@
+//! Byte range requests, as described in RFC 9110 section 14.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{Cursor, SeekFrom};
+use std::ops::Range;
+use std::path::Path;
+
+use tokio::fs::File;
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncSeekExt};
+
+use crate::body::Body;
+
+/// Most ranges served from one request, after overlapping ranges are merged.
+/// Longer range sets are ignored and the whole file is sent instead.
+pub const MAX_RANGES: usize = 16;
+
+/// Longest range set that is parsed at all.
+const MAX_RANGE_SPECS: usize = 256;
+
+/// How to answer a request with a `Range` header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ranges {
+    /// Ignore the header and send the whole representation: the header is
+    /// malformed, uses another unit, or asks for too many ranges.
+    Full,
+    /// Send these ranges, each of them non-empty and within the
+    /// representation.
+    Partial(Vec<Range<u64>>),
+    /// None of the ranges overlaps the representation: answer `416`.
+    Unsatisfiable,
+}
+
+/// Resolves a `Range` header against a representation of `len` bytes.
+///
+/// Ranges that overlap or touch are merged, and the result is sorted by
+/// offset.
+pub fn parse(value: &[u8], len: u64) -> Ranges {
+    let Some(set) = strip_unit(value) else {
+        return Ranges::Full;
+    };
+
+    let mut ranges = Vec::new();
+    let mut specs = 0;
+    for spec in set.split(|&b| b == b',') {
+        let spec = trim(spec);
+        if spec.is_empty() {
+            continue;
+        }
+        specs += 1;
+        if specs > MAX_RANGE_SPECS {
+            return Ranges::Full;
+        }
+        match parse_spec(spec, len) {
+            Ok(Some(range)) => ranges.push(range),
+            Ok(None) => {}
+            Err(()) => return Ranges::Full,
+        }
+    }
+    if specs == 0 {
+        return Ranges::Full;
+    }
+    if ranges.is_empty() {
+        return Ranges::Unsatisfiable;
+    }
+
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<Range<u64>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    if merged.len() > MAX_RANGES {
+        return Ranges::Full;
+    }
+    Ranges::Partial(merged)
+}
+
+/// The `Content-Range` value for `range` of a representation of `len` bytes.
+pub fn content_range(range: &Range<u64>, len: u64) -> String {
+    format!("bytes {}-{}/{}", range.start, range.end - 1, len)
+}
+
+/// The `Content-Range` value sent with `416`.
+pub fn unsatisfied_range(len: u64) -> String {
+    format!("bytes */{}", len)
+}
+
+/// Streams one range of `file`.
+pub async fn single_part(mut file: File, range: Range<u64>) -> io::Result<Body> {
+    file.seek(SeekFrom::Start(range.start)).await?;
+    let len = range.end - range.start;
+    Ok(Body::from_reader(file.take(len), Some(len)))
+}
+
+/// A `multipart/byteranges` body holding each of `ranges` of the file at
+/// `path`, which is `len` bytes long.
+#[derive(Debug)]
+pub struct Multipart {
+    pub boundary: String,
+    pub body: Body,
+}
+
+impl Multipart {
+    pub async fn new(
+        path: &Path,
+        ranges: &[Range<u64>],
+        content_type: &str,
+        len: u64,
+    ) -> io::Result<Multipart> {
+        let boundary = boundary();
+        let mut reader: Box<dyn AsyncRead + Send + Unpin> = Box::new(io::empty());
+        let mut total = 0;
+        for range in ranges {
+            let head = format!(
+                "\r\n--{}\r\ncontent-type: {}\r\ncontent-range: {}\r\n\r\n",
+                boundary,
+                content_type,
+                content_range(range, len)
+            );
+            // Each part reads through its own handle, so the seeks do not
+            // interfere with each other.
+            let mut file = File::open(path).await?;
+            file.seek(SeekFrom::Start(range.start)).await?;
+            let part_len = range.end - range.start;
+            total += head.len() as u64 + part_len;
+            reader = Box::new(
+                reader
+                    .chain(Cursor::new(head.into_bytes()))
+                    .chain(file.take(part_len)),
+            );
+        }
+        let tail = format!("\r\n--{}--\r\n", boundary);
+        total += tail.len() as u64;
+        let reader = reader.chain(Cursor::new(tail.into_bytes()));
+
+        Ok(Multipart {
+            boundary,
+            body: Body::from_reader(reader, Some(total)),
+        })
+    }
+}
+
+/// A boundary that is unlikely to show up in the file.
+fn boundary() -> String {
+    let random = RandomState::new().build_hasher().finish();
+    format!("{:016x}", random)
+}
+
+fn strip_unit(value: &[u8]) -> Option<&[u8]> {
+    let eq = value.iter().position(|&b| b == b'=')?;
+    let unit = trim(&value[..eq]);
+    unit.eq_ignore_ascii_case(b"bytes")
+        .then_some(&value[eq + 1..])
+}
+
+/// Parses `first-last`, `first-` or `-suffix`. A range outside the
+/// representation is `Ok(None)`; bad syntax is an error.
+fn parse_spec(spec: &[u8], len: u64) -> Result<Option<Range<u64>>, ()> {
+    let dash = spec.iter().position(|&b| b == b'-').ok_or(())?;
+    let (first, last) = (&spec[..dash], &spec[dash + 1..]);
+
+    if first.is_empty() {
+        let suffix = number(last)?;
+        if suffix == 0 || len == 0 {
+            return Ok(None);
+        }
+        return Ok(Some(len - suffix.min(len)..len));
+    }
+
+    let first = number(first)?;
+    let last = if last.is_empty() {
+        None
+    } else {
+        Some(number(last)?)
+    };
+    if matches!(last, Some(last) if last < first) {
+        return Err(());
+    }
+    if first >= len {
+        return Ok(None);
+    }
+    let end = last.map_or(len, |last| last.saturating_add(1).min(len));
+    Ok(Some(first..end))
+}
+
+fn number(digits: &[u8]) -> Result<u64, ()> {
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return Err(());
+    }
+    std::str::from_utf8(digits)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or(())
+}
+
+fn trim(value: &[u8]) -> &[u8] {
+    let is_ows = |b: &u8| *b == b' ' || *b == b'\t';
+    let start = value.iter().position(|b| !is_ows(b)).unwrap_or(value.len());
+    let end = value
+        .iter()
+        .rposition(|b| !is_ows(b))
+        .map_or(start, |i| i + 1);
+    &value[start..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Ranges};
+    use std::ops::Range;
+
+    fn single(range: Range<u64>) -> Ranges {
+        Ranges::Partial(Vec::from([range]))
+    }
+
+    #[test]
+    fn parse_range_sets() {
+        assert_eq!(parse(b"bytes=0-499", 1000), single(0..500));
+        assert_eq!(parse(b"bytes=500-", 1000), single(500..1000));
+        assert_eq!(parse(b"bytes=-200", 1000), single(800..1000));
+        assert_eq!(parse(b"bytes=-2000", 1000), single(0..1000));
+        assert_eq!(parse(b"bytes=900-2000", 1000), single(900..1000));
+        assert_eq!(
+            parse(b"bytes=0-0, -1", 1000),
+            Ranges::Partial(vec![0..1, 999..1000])
+        );
+        assert_eq!(parse(b"Bytes = 1-2", 10), single(1..3));
+    }
+
+    #[test]
+    fn merge_overlapping_ranges() {
+        assert_eq!(
+            parse(b"bytes=50-99,0-49,10-20,200-", 300),
+            Ranges::Partial(vec![0..100, 200..300])
+        );
+    }
+
+    #[test]
+    fn unsatisfiable_and_ignored_ranges() {
+        assert_eq!(parse(b"bytes=1000-", 1000), Ranges::Unsatisfiable);
+        assert_eq!(parse(b"bytes=-0", 1000), Ranges::Unsatisfiable);
+        assert_eq!(parse(b"bytes=-5", 0), Ranges::Unsatisfiable);
+        assert_eq!(parse(b"bytes=5-1", 1000), Ranges::Full);
+        assert_eq!(parse(b"bytes=a-b", 1000), Ranges::Full);
+        assert_eq!(parse(b"bytes=", 1000), Ranges::Full);
+        assert_eq!(parse(b"items=0-1", 1000), Ranges::Full);
+
+        let many = (0..20)
+            .map(|i| format!("{}-{}", i * 10, i * 10))
+            .collect::<Vec<_>>()
+            .join(",");
+        assert_eq!(
+            parse(format!("bytes={}", many).as_bytes(), 1000),
+            Ranges::Full
+        );
+    }
+}
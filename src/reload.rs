@@ -0,0 +1,98 @@
+//! A handler that can be replaced while the server runs.
+
+use std::sync::{Arc, RwLock};
+
+use anyhow::Result;
+
+use crate::handler::{BoxFuture, BoxHandler, Handler};
+use crate::request::Request;
+use crate::response::Response;
+
+/// Passes requests to a handler that can be swapped out at any time.
+///
+/// Clones share the handler, so one clone can be served while another is
+/// kept to [`replace`](Reloadable::replace) it, for instance when the config
+/// is reloaded. Each request is routed by the handler that was current when
+/// it arrived: a replacement only affects new requests, and requests in
+/// flight finish under the handler they started with.
+///
+/// ```ignore
+/// let routes = Reloadable::new(router);
+/// let reload = routes.clone();
+/// tokio::spawn(async move {
+///     // ...
+///     reload.replace(new_router);
+/// });
+/// server::serve(config, routes).await
+/// ```
+#[derive(Clone)]
+pub struct Reloadable {
+    current: Arc<RwLock<Arc<dyn Handler>>>,
+}
+
+impl Reloadable {
+    pub fn new(handler: impl Into<BoxHandler>) -> Reloadable {
+        Reloadable {
+            current: Arc::new(RwLock::new(Arc::from(handler.into()))),
+        }
+    }
+
+    /// Serves new requests with `handler`.
+    pub fn replace(&self, handler: impl Into<BoxHandler>) {
+        let handler = Arc::from(handler.into());
+        // The old handler is dropped outside the lock, once the last request
+        // using it is done.
+        let _old = std::mem::replace(&mut *self.write(), handler);
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, Arc<dyn Handler>> {
+        // The lock is only held to clone or swap the `Arc`, which cannot
+        // panic, so a poisoned lock still holds a valid handler.
+        self.current.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn current(&self) -> Arc<dyn Handler> {
+        let current = self.current.read().unwrap_or_else(|e| e.into_inner());
+        Arc::clone(&current)
+    }
+}
+
+impl Handler for Reloadable {
+    fn call(&self, req: Request) -> BoxFuture<'static, Result<Response>> {
+        self.current().call(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Reloadable;
+    use crate::handler::sync;
+    use crate::request::Request;
+    use crate::response::Response;
+    use crate::status::StatusCode;
+    use crate::Handler;
+    use std::sync::Arc;
+    use tokio::sync::Notify;
+
+    #[tokio::test]
+    async fn requests_in_flight_finish_under_the_old_handler() {
+        let release = Arc::new(Notify::new());
+        let wait = release.clone();
+        let routes = Reloadable::new(move |_req: Request| {
+            let wait = wait.clone();
+            async move {
+                wait.notified().await;
+                Ok(Response::with_status(StatusCode::OK))
+            }
+        });
+
+        let in_flight = tokio::spawn(routes.call(Request::default()));
+        routes.replace(sync(|_| Ok(Response::with_status(StatusCode::ACCEPTED))));
+        let res = routes.call(Request::default()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+
+        release.notify_one();
+        let res = in_flight.await.unwrap().unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+}
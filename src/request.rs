@@ -0,0 +1,218 @@
+use core::fmt;
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use crate::header::{HeaderMap, HOST};
+use crate::method::Method;
+use crate::router::Params;
+use crate::tls::TlsInfo;
+use crate::uri::{authority_host, Query, Uri};
+use crate::version::Version;
+
+/// An HTTP request as handed to a [`Handler`](crate::handler::Handler).
+///
+/// The request owns its head and its body, so it can be moved into a task and
+/// outlive the connection buffer it was parsed from. Request bodies are read
+/// completely before the handler runs, so the body defaults to `Bytes`.
+pub struct Request<B = Bytes> {
+    method: Method,
+    uri: Uri,
+    version: Version,
+    headers: HeaderMap,
+    trailers: HeaderMap,
+    params: Params,
+    tls: Option<Arc<TlsInfo>>,
+    body: B,
+}
+
+impl<B> Request<B> {
+    /// Creates a `GET /` HTTP/1.1 request with the given body.
+    pub fn new(body: B) -> Request<B> {
+        Request {
+            method: Method::GET,
+            uri: Uri::default(),
+            version: Version::default(),
+            headers: HeaderMap::new(),
+            trailers: HeaderMap::new(),
+            params: Params::default(),
+            tls: None,
+            body,
+        }
+    }
+
+    #[inline]
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
+
+    #[inline]
+    pub fn method_mut(&mut self) -> &mut Method {
+        &mut self.method
+    }
+
+    /// The request target as sent by the client.
+    #[inline]
+    pub fn uri(&self) -> &Uri {
+        &self.uri
+    }
+
+    #[inline]
+    pub fn uri_mut(&mut self) -> &mut Uri {
+        &mut self.uri
+    }
+
+    /// The path of the request target, still percent-encoded.
+    #[inline]
+    pub fn path(&self) -> &str {
+        self.uri.path()
+    }
+
+    /// The decoded query string parameters.
+    pub fn query(&self) -> Query {
+        self.uri.query_pairs()
+    }
+
+    /// The host the request is for, without the port: the authority of an
+    /// absolute-form target, or else the `Host` header.
+    pub fn host(&self) -> Option<&str> {
+        if let Some(host) = self.uri.host() {
+            return Some(host);
+        }
+        let value = self.headers.get(HOST)?.to_str().ok()?;
+        authority_host(value.trim()).filter(|host| !host.is_empty())
+    }
+
+    #[inline]
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    #[inline]
+    pub fn version_mut(&mut self) -> &mut Version {
+        &mut self.version
+    }
+
+    #[inline]
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    #[inline]
+    pub fn headers_mut(&mut self) -> &mut HeaderMap {
+        &mut self.headers
+    }
+
+    /// Trailer fields sent after a chunked body.
+    #[inline]
+    pub fn trailers(&self) -> &HeaderMap {
+        &self.trailers
+    }
+
+    #[inline]
+    pub fn trailers_mut(&mut self) -> &mut HeaderMap {
+        &mut self.trailers
+    }
+
+    /// Parameters captured from the path by the router.
+    #[inline]
+    pub fn params(&self) -> &Params {
+        &self.params
+    }
+
+    #[inline]
+    pub fn params_mut(&mut self) -> &mut Params {
+        &mut self.params
+    }
+
+    /// The TLS session the request came over, or `None` for plain HTTP.
+    #[inline]
+    pub fn tls(&self) -> Option<&TlsInfo> {
+        self.tls.as_deref()
+    }
+
+    #[inline]
+    pub fn tls_mut(&mut self) -> &mut Option<Arc<TlsInfo>> {
+        &mut self.tls
+    }
+
+    #[inline]
+    pub fn body(&self) -> &B {
+        &self.body
+    }
+
+    #[inline]
+    pub fn body_mut(&mut self) -> &mut B {
+        &mut self.body
+    }
+
+    #[inline]
+    pub fn into_body(self) -> B {
+        self.body
+    }
+
+    /// Replaces the body with the result of `f`, keeping the head.
+    pub fn map<F, U>(self, f: F) -> Request<U>
+    where
+        F: FnOnce(B) -> U,
+    {
+        Request {
+            method: self.method,
+            uri: self.uri,
+            version: self.version,
+            headers: self.headers,
+            trailers: self.trailers,
+            params: self.params,
+            tls: self.tls,
+            body: f(self.body),
+        }
+    }
+}
+
+impl<B: Default> Default for Request<B> {
+    fn default() -> Self {
+        Request::new(B::default())
+    }
+}
+
+impl<B: fmt::Debug> fmt::Debug for Request<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Request")
+            .field("method", &self.method)
+            .field("uri", &self.uri)
+            .field("version", &self.version)
+            .field("headers", &self.headers)
+            .field("trailers", &self.trailers)
+            .field("params", &self.params)
+            .field("body", &self.body)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::Request;
+    use crate::uri::Uri;
+
+    fn request(target: &'static str) -> Request {
+        let mut req = Request::new(Bytes::new());
+        *req.uri_mut() = Uri::from_shared(Bytes::from_static(target.as_bytes())).unwrap();
+        req
+    }
+
+    #[test]
+    fn query_is_decoded() {
+        let req = request("/search?q=a+b%21&tag=x&tag=y&flag");
+        assert_eq!(req.path(), "/search");
+        let query = req.query();
+        assert_eq!(query.get("q"), Some("a b!"));
+        assert_eq!(query.get_all("tag").collect::<Vec<_>>(), ["x", "y"]);
+        assert_eq!(query.get("flag"), Some(""));
+        assert_eq!(query.get("missing"), None);
+
+        assert_eq!(request("/search").query().get("q"), None);
+        assert_eq!(request("http://a.org/?b=c").query().get("b"), Some("c"));
+    }
+}
@@ -0,0 +1,370 @@
+use core::fmt;
+use std::convert::Infallible;
+use std::time::SystemTime;
+
+use thiserror::Error;
+
+use crate::body::Body;
+use crate::header::{
+    HeaderMap, HeaderValue, IntoHeaderName, InvalidHeaderValue, CONTENT_LENGTH, DATE, SERVER,
+    TRANSFER_ENCODING,
+};
+use crate::status::StatusCode;
+use crate::version::Version;
+
+/// Value of the `Server` header added to responses that do not set one.
+const SERVER_NAME: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// An HTTP response produced by a [`Handler`](crate::handler::Handler).
+///
+/// The body defaults to [`Body`], which may be buffered or streamed.
+pub struct Response<B = Body> {
+    status: StatusCode,
+    version: Version,
+    headers: HeaderMap,
+    body: B,
+}
+
+/// Builds a [`Response`] from its status, version and headers.
+///
+/// ```ignore
+/// let res = Response::builder()
+///     .status(StatusCode::CREATED)
+///     .header(LOCATION, "/files/a.txt")
+///     .body(Body::empty())?;
+/// ```
+#[derive(Debug)]
+pub struct Builder {
+    inner: Result<Head, BuildError>,
+}
+
+#[derive(Debug)]
+struct Head {
+    status: StatusCode,
+    version: Version,
+    headers: HeaderMap,
+}
+
+/// A header passed to [`Builder::header`] could not be converted.
+#[derive(Debug, Error)]
+pub enum BuildError {
+    #[error("invalid header value")]
+    InvalidHeaderValue(#[from] InvalidHeaderValue),
+}
+
+impl From<Infallible> for BuildError {
+    fn from(never: Infallible) -> Self {
+        match never {}
+    }
+}
+
+/// How the body of a serialized response is delimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Framing {
+    /// `Content-Length` with the exact body size.
+    Length(u64),
+    /// `Transfer-Encoding: chunked`.
+    Chunked,
+    /// The body has no header describing it: either there is no body at all,
+    /// or it runs until the connection closes.
+    None,
+}
+
+impl Response<()> {
+    #[inline]
+    pub fn builder() -> Builder {
+        Builder::new()
+    }
+}
+
+impl<B> Response<B> {
+    /// Creates a `200 OK` response with the given body.
+    pub fn new(body: B) -> Response<B> {
+        Response {
+            status: StatusCode::default(),
+            version: Version::default(),
+            headers: HeaderMap::new(),
+            body,
+        }
+    }
+
+    #[inline]
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    #[inline]
+    pub fn status_mut(&mut self) -> &mut StatusCode {
+        &mut self.status
+    }
+
+    #[inline]
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    #[inline]
+    pub fn version_mut(&mut self) -> &mut Version {
+        &mut self.version
+    }
+
+    #[inline]
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    #[inline]
+    pub fn headers_mut(&mut self) -> &mut HeaderMap {
+        &mut self.headers
+    }
+
+    #[inline]
+    pub fn body(&self) -> &B {
+        &self.body
+    }
+
+    #[inline]
+    pub fn body_mut(&mut self) -> &mut B {
+        &mut self.body
+    }
+
+    #[inline]
+    pub fn into_body(self) -> B {
+        self.body
+    }
+
+    /// Replaces the body with the result of `f`, keeping the head.
+    pub fn map<F, U>(self, f: F) -> Response<U>
+    where
+        F: FnOnce(B) -> U,
+    {
+        Response {
+            status: self.status,
+            version: self.version,
+            headers: self.headers,
+            body: f(self.body),
+        }
+    }
+}
+
+impl<B> Response<B> {
+    /// Whether the status allows a body. Informational responses,
+    /// `204 No Content` and `304 Not Modified` never have one, nor a
+    /// `Content-Length`.
+    pub(crate) fn may_have_body(&self) -> bool {
+        !self.status.is_informational()
+            && self.status != StatusCode::NO_CONTENT
+            && self.status != StatusCode::NOT_MODIFIED
+    }
+
+    /// Serializes the status line and header section, including the blank
+    /// line that ends it, into `dst`.
+    ///
+    /// `Content-Length` and `Transfer-Encoding` are written from `framing`,
+    /// replacing any set by the handler. `Date` and `Server` are added unless
+    /// the handler already set them.
+    pub(crate) fn encode_head(&self, framing: Framing, dst: &mut Vec<u8>) {
+        let reason = self.status.canonical_reason().unwrap_or("");
+        dst.extend_from_slice(
+            format!("{:?} {} {}\r\n", self.version, self.status.as_u16(), reason).as_bytes(),
+        );
+        self.for_each_field(framing, |name, value, _| encode_header(dst, name, value));
+        dst.extend_from_slice(b"\r\n");
+    }
+
+    /// Calls `f` with the name, value and sensitivity of every header field
+    /// [`encode_head`](Response::encode_head) writes, in order.
+    pub(crate) fn for_each_field(&self, framing: Framing, mut f: impl FnMut(&str, &[u8], bool)) {
+        for (name, value) in &self.headers {
+            if *name == CONTENT_LENGTH || *name == TRANSFER_ENCODING {
+                continue;
+            }
+            f(name.as_str(), value.as_bytes(), value.is_sensitive());
+        }
+        if !self.headers.contains_key(DATE) {
+            let now = httpdate::fmt_http_date(SystemTime::now());
+            f("date", now.as_bytes(), false);
+        }
+        if !self.headers.contains_key(SERVER) {
+            f("server", SERVER_NAME.as_bytes(), false);
+        }
+        match framing {
+            Framing::Length(len) => f("content-length", len.to_string().as_bytes(), false),
+            Framing::Chunked => f("transfer-encoding", b"chunked", false),
+            Framing::None => {}
+        }
+    }
+}
+
+fn encode_header(dst: &mut Vec<u8>, name: &str, value: &[u8]) {
+    dst.extend_from_slice(name.as_bytes());
+    dst.extend_from_slice(b": ");
+    dst.extend_from_slice(value);
+    dst.extend_from_slice(b"\r\n");
+}
+
+impl Response<Body> {
+    /// A response with the given status and an empty body.
+    pub fn with_status(status: StatusCode) -> Response {
+        let mut res = Response::new(Body::empty());
+        res.status = status;
+        res
+    }
+}
+
+impl Builder {
+    /// A builder for a `200 OK` HTTP/1.1 response without headers.
+    pub fn new() -> Builder {
+        Builder {
+            inner: Ok(Head {
+                status: StatusCode::default(),
+                version: Version::default(),
+                headers: HeaderMap::new(),
+            }),
+        }
+    }
+
+    pub fn status(self, status: StatusCode) -> Builder {
+        self.and_then(|mut head| {
+            head.status = status;
+            Ok(head)
+        })
+    }
+
+    pub fn version(self, version: Version) -> Builder {
+        self.and_then(|mut head| {
+            head.version = version;
+            Ok(head)
+        })
+    }
+
+    /// Appends a header. A value that is not a valid header value makes
+    /// [`Builder::body`] fail.
+    pub fn header<K, V>(self, key: K, value: V) -> Builder
+    where
+        K: IntoHeaderName,
+        HeaderValue: TryFrom<V>,
+        <HeaderValue as TryFrom<V>>::Error: Into<BuildError>,
+    {
+        self.and_then(|mut head| {
+            let value = HeaderValue::try_from(value).map_err(Into::into)?;
+            head.headers.append(key, value);
+            Ok(head)
+        })
+    }
+
+    /// The headers set so far, or `None` if an earlier call failed.
+    pub fn headers_mut(&mut self) -> Option<&mut HeaderMap> {
+        self.inner.as_mut().ok().map(|head| &mut head.headers)
+    }
+
+    /// Finishes the response with `body`.
+    pub fn body<B>(self, body: B) -> Result<Response<B>, BuildError> {
+        let head = self.inner?;
+        Ok(Response {
+            status: head.status,
+            version: head.version,
+            headers: head.headers,
+            body,
+        })
+    }
+
+    fn and_then<F>(self, f: F) -> Builder
+    where
+        F: FnOnce(Head) -> Result<Head, BuildError>,
+    {
+        Builder {
+            inner: self.inner.and_then(f),
+        }
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Builder::new()
+    }
+}
+
+impl<B: Default> Default for Response<B> {
+    fn default() -> Self {
+        Response::new(B::default())
+    }
+}
+
+impl<B: fmt::Debug> fmt::Debug for Response<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Response")
+            .field("status", &self.status)
+            .field("version", &self.version)
+            .field("headers", &self.headers)
+            .field("body", &self.body)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Framing, Response};
+    use crate::body::Body;
+    use crate::header::{CONTENT_TYPE, LOCATION};
+    use crate::status::StatusCode;
+    use crate::version::Version;
+
+    fn head(res: &Response, framing: Framing) -> String {
+        let mut dst = Vec::new();
+        res.encode_head(framing, &mut dst);
+        String::from_utf8(dst).unwrap()
+    }
+
+    #[test]
+    fn builder_sets_status_version_and_headers() {
+        let res = Response::builder()
+            .status(StatusCode::CREATED)
+            .version(Version::HTTP_10)
+            .header(LOCATION, "/files/a")
+            .header("x-id", 7)
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(res.version(), Version::HTTP_10);
+        assert_eq!(res.headers().get(LOCATION).unwrap(), "/files/a");
+        assert_eq!(res.headers().get("x-id").unwrap(), "7");
+
+        let err = Response::builder()
+            .header(CONTENT_TYPE, "text/plain\r\n")
+            .body(());
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn encode_adds_date_server_and_length() {
+        let res = Response::builder()
+            .status(StatusCode::CREATED)
+            .header(CONTENT_TYPE, "text/plain")
+            .header("content-length", "999")
+            .body(Body::from("ok"))
+            .unwrap();
+        let head = head(&res, Framing::Length(2));
+        let mut lines = head.split("\r\n");
+        assert_eq!(lines.next(), Some("HTTP/1.1 201 Created"));
+        assert_eq!(lines.next(), Some("content-type: text/plain"));
+        assert!(lines.next().unwrap().starts_with("date: "));
+        assert!(lines.next().unwrap().starts_with("server: "));
+        assert_eq!(lines.next(), Some("content-length: 2"));
+        assert!(head.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn encode_keeps_handler_date_and_server() {
+        let res = Response::builder()
+            .header("date", "Sun, 06 Nov 1994 08:49:37 GMT")
+            .header("server", "custom")
+            .body(Body::empty())
+            .unwrap();
+        let head = head(&res, Framing::Chunked);
+        assert_eq!(
+            head,
+            "HTTP/1.1 200 OK\r\ndate: Sun, 06 Nov 1994 08:49:37 GMT\r\nserver: custom\r\ntransfer-encoding: chunked\r\n\r\n"
+        );
+    }
+}
@@ -0,0 +1,588 @@
+use core::fmt;
+use std::borrow::Cow;
+use std::future::ready;
+use std::str::FromStr;
+
+use itertools::Itertools;
+use thiserror::Error;
+
+use crate::handler::{BoxFuture, BoxHandler, Handler};
+use crate::header::{HeaderValue, ALLOW};
+use crate::method::Method;
+use crate::request::Request;
+use crate::response::Response;
+use crate::status::StatusCode;
+use crate::uri::percent_decode;
+
+/// Maps a method and a request path to a handler.
+///
+/// Patterns are made of `/` separated segments. A segment is either literal
+/// text, a named parameter such as `{msg}` that matches one non-empty
+/// segment, or a trailing wildcard such as `{*path}` that matches the rest of
+/// the path. When several patterns match, literal segments win over
+/// parameters and parameters win over wildcards, regardless of the order the
+/// routes were added in.
+///
+/// A `Router` of [`Handler`]s is itself a handler: it stores the captured
+/// parameters in the request and answers `404 Not Found` or
+/// `405 Method Not Allowed` when no route matches.
+///
+/// `HEAD` requests go to the `GET` route unless a `HEAD` route is registered,
+/// and `OPTIONS` requests without a route of their own are answered with
+/// `204 No Content` and the methods the path allows.
+///
+/// ```ignore
+/// let mut router = Router::new();
+/// router
+///     .get("/echo/{msg}", echo)
+///     .get("/files/{*path}", get_file);
+/// ```
+pub struct Router<H = BoxHandler> {
+    routes: Vec<Route<H>>,
+}
+
+struct Route<H> {
+    method: Method,
+    pattern: Pattern,
+    handler: H,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Pattern {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Static(String),
+    Param(String),
+    Wildcard(String),
+}
+
+/// A successful lookup: the handler and the parameters taken from the path.
+#[derive(Debug)]
+pub struct Match<'a, H> {
+    pub handler: &'a H,
+    pub params: Params,
+}
+
+/// Why a lookup found no handler.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum RouteError {
+    /// No pattern matches the path.
+    #[error("no route matches the path")]
+    NotFound,
+    /// Some pattern matches the path, but not for this method. Holds the
+    /// methods that would have matched, for the `Allow` header.
+    #[error("method not allowed")]
+    MethodNotAllowed(Vec<Method>),
+}
+
+/// Parameters captured from the request path, percent-decoded, in pattern
+/// order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Params {
+    entries: Vec<(String, String)>,
+}
+
+/// A path parameter that is missing or does not parse as the requested type.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ParamError {
+    #[error("missing path parameter `{0}`")]
+    Missing(String),
+    #[error("invalid value {value:?} for path parameter `{name}`")]
+    Invalid { name: String, value: String },
+}
+
+impl<H> Router<H> {
+    pub fn new() -> Self {
+        Router { routes: Vec::new() }
+    }
+
+    /// Registers `handler` for `method` on `pattern`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pattern is malformed or the same method is already
+    /// registered for an equivalent pattern.
+    pub fn route(&mut self, method: Method, pattern: &str, handler: impl Into<H>) -> &mut Self {
+        let pattern = Pattern::parse(pattern)
+            .unwrap_or_else(|e| panic!("invalid route pattern {:?}: {}", pattern, e));
+        if self
+            .routes
+            .iter()
+            .any(|r| r.method == method && r.pattern.same_shape(&pattern))
+        {
+            panic!("duplicate route: {} {}", method, pattern);
+        }
+        self.routes.push(Route {
+            method,
+            pattern,
+            handler: handler.into(),
+        });
+        self
+    }
+
+    pub fn get(&mut self, pattern: &str, handler: impl Into<H>) -> &mut Self {
+        self.route(Method::GET, pattern, handler)
+    }
+
+    pub fn post(&mut self, pattern: &str, handler: impl Into<H>) -> &mut Self {
+        self.route(Method::POST, pattern, handler)
+    }
+
+    pub fn put(&mut self, pattern: &str, handler: impl Into<H>) -> &mut Self {
+        self.route(Method::PUT, pattern, handler)
+    }
+
+    pub fn delete(&mut self, pattern: &str, handler: impl Into<H>) -> &mut Self {
+        self.route(Method::DELETE, pattern, handler)
+    }
+
+    /// Finds the handler for `method` and `path`, falling back to the `GET`
+    /// handler for `HEAD`.
+    ///
+    /// `path` is the raw, percent-encoded path. Each segment is decoded before
+    /// it is matched, so an encoded `/` stays inside its segment.
+    pub fn at(&self, method: &Method, path: &str) -> Result<Match<'_, H>, RouteError> {
+        let decoded = decode_segments(path).ok_or(RouteError::NotFound)?;
+        let segments: Vec<&str> = decoded.iter().map(|s| s.as_ref()).collect();
+
+        let mut best: Option<(&Route<H>, Vec<u8>)> = None;
+        let mut fallback: Option<(&Route<H>, Vec<u8>)> = None;
+        let mut allowed = Vec::new();
+        for route in &self.routes {
+            let Some(rank) = route.pattern.rank(&segments) else {
+                continue;
+            };
+            if !allowed.contains(&route.method) {
+                allowed.push(route.method.clone());
+            }
+            let slot = if route.method == *method {
+                &mut best
+            } else if *method == Method::HEAD && route.method == Method::GET {
+                &mut fallback
+            } else {
+                continue;
+            };
+            if !matches!(slot, Some((_, ref best)) if rank <= *best) {
+                *slot = Some((route, rank));
+            }
+        }
+
+        match best.or(fallback) {
+            Some((route, _)) => Ok(Match {
+                handler: &route.handler,
+                params: route.pattern.captures(&segments),
+            }),
+            None if allowed.is_empty() => Err(RouteError::NotFound),
+            None => Err(RouteError::MethodNotAllowed(with_implied(allowed))),
+        }
+    }
+
+    /// Every method some route answers, for `OPTIONS *`.
+    pub fn methods(&self) -> Vec<Method> {
+        let mut methods = Vec::new();
+        for route in &self.routes {
+            if !methods.contains(&route.method) {
+                methods.push(route.method.clone());
+            }
+        }
+        with_implied(methods)
+    }
+}
+
+/// Splits a raw, percent-encoded path into its segments and decodes each
+/// one, so an encoded `/` stays inside its segment. `None` if the path does
+/// not start with `/`.
+pub(crate) fn decode_segments(path: &str) -> Option<Vec<Cow<'_, str>>> {
+    let rest = path.strip_prefix('/')?;
+    Some(
+        rest.split('/')
+            .map(|s| percent_decode(s).unwrap_or(Cow::Borrowed(s)))
+            .collect(),
+    )
+}
+
+/// Adds the methods the router answers on its own: `HEAD` wherever there is
+/// a `GET`, and `OPTIONS` everywhere.
+fn with_implied(mut methods: Vec<Method>) -> Vec<Method> {
+    if !methods.contains(&Method::HEAD) {
+        if let Some(i) = methods.iter().position(|m| *m == Method::GET) {
+            methods.insert(i + 1, Method::HEAD);
+        }
+    }
+    if !methods.contains(&Method::OPTIONS) {
+        methods.push(Method::OPTIONS);
+    }
+    methods
+}
+
+impl Handler for Router {
+    fn call(&self, mut req: Request) -> BoxFuture<'static, anyhow::Result<Response>> {
+        let res = match self.at(req.method(), req.path()) {
+            Ok(found) => {
+                *req.params_mut() = found.params;
+                return found.handler.call(req);
+            }
+            Err(_) if *req.method() == Method::OPTIONS && req.path() == "*" => {
+                options_response(&self.methods())
+            }
+            Err(RouteError::MethodNotAllowed(allowed)) if *req.method() == Method::OPTIONS => {
+                options_response(&allowed)
+            }
+            Err(err) => err.into_response(),
+        };
+        Box::pin(ready(Ok(res)))
+    }
+}
+
+/// `204 No Content` listing the `allowed` methods.
+fn options_response(allowed: &[Method]) -> Response {
+    let mut res = Response::with_status(StatusCode::NO_CONTENT);
+    res.headers_mut().insert(ALLOW, allow_value(allowed));
+    res
+}
+
+fn allow_value(methods: &[Method]) -> HeaderValue {
+    HeaderValue::from_str(&methods.iter().join(", ")).expect("method names are valid values")
+}
+
+impl<H> Default for Router<H> {
+    fn default() -> Self {
+        Router::new()
+    }
+}
+
+impl<H> fmt::Debug for Router<H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(
+                self.routes
+                    .iter()
+                    .map(|r| format!("{} {}", r.method, r.pattern)),
+            )
+            .finish()
+    }
+}
+
+impl RouteError {
+    /// Value for the `Allow` header of a 405 response.
+    pub fn allow(&self) -> Option<String> {
+        match self {
+            RouteError::NotFound => None,
+            RouteError::MethodNotAllowed(methods) => Some(methods.iter().join(", ")),
+        }
+    }
+
+    /// The `404` or `405` response for this error.
+    pub fn into_response(self) -> Response {
+        match self {
+            RouteError::NotFound => Response::with_status(StatusCode::NOT_FOUND),
+            RouteError::MethodNotAllowed(methods) => {
+                let mut res = Response::with_status(StatusCode::METHOD_NOT_ALLOWED);
+                res.headers_mut().insert(ALLOW, allow_value(&methods));
+                res
+            }
+        }
+    }
+}
+
+impl Pattern {
+    fn parse(src: &str) -> Result<Pattern, &'static str> {
+        let rest = src.strip_prefix('/').ok_or("pattern must start with `/`")?;
+        let parts: Vec<&str> = rest.split('/').collect();
+        let mut segments = Vec::with_capacity(parts.len());
+        let mut names: Vec<&str> = Vec::new();
+
+        for (i, part) in parts.iter().enumerate() {
+            let segment = match part.strip_prefix('{').and_then(|p| p.strip_suffix('}')) {
+                Some(name) => {
+                    let (wildcard, name) = match name.strip_prefix('*') {
+                        Some(name) => (true, name),
+                        None => (false, name),
+                    };
+                    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                        return Err("parameter names must be alphanumeric");
+                    }
+                    if names.contains(&name) {
+                        return Err("parameter names must be unique");
+                    }
+                    names.push(name);
+                    if wildcard {
+                        if i + 1 != parts.len() {
+                            return Err("a wildcard must be the last segment");
+                        }
+                        Segment::Wildcard(name.to_string())
+                    } else {
+                        Segment::Param(name.to_string())
+                    }
+                }
+                None if part.contains(['{', '}']) => {
+                    return Err("a parameter must span a whole segment");
+                }
+                None => Segment::Static(part.to_string()),
+            };
+            segments.push(segment);
+        }
+        Ok(Pattern { segments })
+    }
+
+    /// Returns how specific the match is if the pattern matches `path`.
+    /// Higher ranks compare greater.
+    fn rank(&self, path: &[&str]) -> Option<Vec<u8>> {
+        let mut rank = Vec::with_capacity(self.segments.len());
+        for (i, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::Static(s) => {
+                    if path.get(i) != Some(&s.as_str()) {
+                        return None;
+                    }
+                    rank.push(2);
+                }
+                Segment::Param(_) => {
+                    match path.get(i) {
+                        Some(p) if !p.is_empty() => {}
+                        _ => return None,
+                    }
+                    rank.push(1);
+                }
+                Segment::Wildcard(_) => {
+                    let rest = &path[i.min(path.len())..];
+                    if rest.iter().all(|p| p.is_empty()) {
+                        return None;
+                    }
+                    rank.push(0);
+                    return Some(rank);
+                }
+            }
+        }
+        (path.len() == self.segments.len()).then_some(rank)
+    }
+
+    fn captures(&self, path: &[&str]) -> Params {
+        let mut params = Params::default();
+        for (i, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::Static(_) => {}
+                Segment::Param(name) => params.push(name, path[i]),
+                Segment::Wildcard(name) => params.push(name, &path[i..].join("/")),
+            }
+        }
+        params
+    }
+
+    /// Two patterns have the same shape if they match exactly the same paths.
+    fn same_shape(&self, other: &Pattern) -> bool {
+        self.segments.len() == other.segments.len()
+            && self
+                .segments
+                .iter()
+                .zip(&other.segments)
+                .all(|pair| match pair {
+                    (Segment::Static(a), Segment::Static(b)) => a == b,
+                    (Segment::Param(_), Segment::Param(_)) => true,
+                    (Segment::Wildcard(_), Segment::Wildcard(_)) => true,
+                    _ => false,
+                })
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for segment in &self.segments {
+            match segment {
+                Segment::Static(s) => write!(f, "/{}", s)?,
+                Segment::Param(name) => write!(f, "/{{{}}}", name)?,
+                Segment::Wildcard(name) => write!(f, "/{{*{}}}", name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Params {
+    fn push(&mut self, name: &str, value: &str) {
+        self.entries.push((name.to_string(), value.to_string()));
+    }
+
+    /// Returns the raw value of the parameter called `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Parses the parameter called `name` into `T`.
+    pub fn parse<T: FromStr>(&self, name: &str) -> Result<T, ParamError> {
+        let value = self
+            .get(name)
+            .ok_or_else(|| ParamError::Missing(name.to_string()))?;
+        value.parse().map_err(|_| ParamError::Invalid {
+            name: name.to_string(),
+            value: value.to_string(),
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ParamError, RouteError, Router};
+    use crate::handler::{sync, Handler};
+    use crate::method::Method;
+    use crate::request::Request;
+    use crate::response::Response;
+    use crate::status::StatusCode;
+
+    fn router() -> Router<&'static str> {
+        let mut router = Router::new();
+        router
+            .get("/", "root")
+            .get("/echo/{msg}", "echo")
+            .get("/files/{*path}", "get_file")
+            .post("/files/{*path}", "post_file")
+            .get("/files/index", "index")
+            .get("/users/{id}/posts/{post}", "post");
+        router
+    }
+
+    #[test]
+    fn match_params_and_wildcards() {
+        let router = router();
+
+        let m = router.at(&Method::GET, "/echo/hello").unwrap();
+        assert_eq!(*m.handler, "echo");
+        assert_eq!(m.params.get("msg"), Some("hello"));
+
+        let m = router.at(&Method::GET, "/files/a/b/c.txt").unwrap();
+        assert_eq!(*m.handler, "get_file");
+        assert_eq!(m.params.get("path"), Some("a/b/c.txt"));
+
+        let m = router.at(&Method::POST, "/files/up.bin").unwrap();
+        assert_eq!(*m.handler, "post_file");
+
+        let m = router.at(&Method::GET, "/users/7/posts/12").unwrap();
+        assert_eq!(m.params.parse::<u32>("id"), Ok(7));
+        assert_eq!(m.params.parse::<u64>("post"), Ok(12));
+        assert_eq!(
+            m.params.parse::<u8>("nope"),
+            Err(ParamError::Missing("nope".into()))
+        );
+    }
+
+    #[test]
+    fn static_segments_win() {
+        let router = router();
+        assert_eq!(
+            *router.at(&Method::GET, "/files/index").unwrap().handler,
+            "index"
+        );
+        assert_eq!(*router.at(&Method::GET, "/").unwrap().handler, "root");
+    }
+
+    #[test]
+    fn not_found_and_method_not_allowed() {
+        let router = router();
+        assert_eq!(
+            router.at(&Method::GET, "/echo/").unwrap_err(),
+            RouteError::NotFound
+        );
+        assert_eq!(
+            router.at(&Method::GET, "/files/").unwrap_err(),
+            RouteError::NotFound
+        );
+        assert_eq!(
+            router.at(&Method::GET, "/nope").unwrap_err(),
+            RouteError::NotFound
+        );
+
+        let err = router.at(&Method::DELETE, "/files/a").unwrap_err();
+        assert_eq!(err.allow().as_deref(), Some("GET, HEAD, POST, OPTIONS"));
+        let err = router.at(&Method::POST, "/echo/x").unwrap_err();
+        assert_eq!(
+            err,
+            RouteError::MethodNotAllowed(vec![Method::GET, Method::HEAD, Method::OPTIONS])
+        );
+    }
+
+    #[test]
+    fn head_falls_back_to_get() {
+        let mut router = router();
+        assert_eq!(
+            *router.at(&Method::HEAD, "/echo/x").unwrap().handler,
+            "echo"
+        );
+        router.route(Method::HEAD, "/echo/{msg}", "head_echo");
+        assert_eq!(
+            *router.at(&Method::HEAD, "/echo/x").unwrap().handler,
+            "head_echo"
+        );
+        assert!(router.at(&Method::HEAD, "/nope").is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate route")]
+    fn duplicate_routes_panic() {
+        let mut router: Router<u8> = Router::new();
+        router.get("/echo/{a}", 1).get("/echo/{b}", 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "wildcard must be the last segment")]
+    fn wildcard_must_be_last() {
+        Router::<()>::new().get("/{*path}/x", ());
+    }
+
+    #[tokio::test]
+    async fn router_is_a_handler() {
+        let mut router = Router::new();
+        router.get(
+            "/echo/{msg}",
+            sync(|req: Request| {
+                Ok(Response::new(
+                    req.params().get("msg").unwrap().to_string().into(),
+                ))
+            }),
+        );
+
+        let mut req = Request::default();
+        *req.uri_mut() = "/echo/hi%21?x=1".parse().unwrap();
+        let res = router.call(req).await.unwrap();
+        assert_eq!(res.into_body().collect().await.unwrap(), "hi!");
+
+        let mut req = Request::default();
+        *req.method_mut() = Method::POST;
+        *req.uri_mut() = "/echo/hi".parse().unwrap();
+        let res = router.call(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(res.headers().get("allow").unwrap(), "GET, HEAD, OPTIONS");
+
+        let mut req = Request::default();
+        *req.method_mut() = Method::OPTIONS;
+        *req.uri_mut() = "/echo/hi".parse().unwrap();
+        let res = router.call(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(res.headers().get("allow").unwrap(), "GET, HEAD, OPTIONS");
+
+        let mut req = Request::default();
+        *req.method_mut() = Method::OPTIONS;
+        *req.uri_mut() = "*".parse().unwrap();
+        let res = router.call(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+
+        let mut req = Request::default();
+        *req.method_mut() = Method::OPTIONS;
+        *req.uri_mut() = "/nope".parse().unwrap();
+        let res = router.call(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,123 @@
+//! The endpoints the server answers. Register new ones in [`endpoints`].
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+use http_server_starter_rust::config::HostConfig;
+use http_server_starter_rust::header::{HeaderValue, CONTENT_TYPE, USER_AGENT};
+use http_server_starter_rust::location::{Location, Locations};
+use http_server_starter_rust::vhost::VirtualHosts;
+use http_server_starter_rust::{Body, Request, Response, Router, StaticFiles};
+
+/// The endpoints, with `/files` serving `directory` if there is one, sending
+/// `cache_control` with its files.
+pub fn router(directory: Option<PathBuf>, cache_control: Option<HeaderValue>) -> Router {
+    let mut router = endpoints();
+    if let Some(directory) = directory {
+        let mut files = StaticFiles::new(directory);
+        if let Some(value) = cache_control {
+            files = files.cache_control(value);
+        }
+        mount(&mut router, "/files", files, true);
+    }
+    router
+}
+
+/// A site per vhost of the config file: the endpoints, plus the roots of
+/// its locations, with each location's settings applied.
+pub fn virtual_hosts(hosts: &[HostConfig]) -> VirtualHosts {
+    let mut vhosts = VirtualHosts::new();
+    for host in hosts {
+        let mut router = endpoints();
+        for location in &host.locations {
+            if let Some(root) = &location.root {
+                let files = StaticFiles::new(root);
+                mount(&mut router, &location.path, files, location.writable);
+            }
+        }
+
+        let mut site = Locations::new(router);
+        for location in &host.locations {
+            let mut settings = Location::new(location.path.as_str());
+            if let Some(max) = location.max_body_size {
+                settings = settings.max_body_size(max);
+            }
+            for (name, value) in &location.headers {
+                settings = settings.header(name.clone(), value.clone());
+            }
+            site = site.location(settings);
+        }
+
+        if host.names.is_empty() {
+            vhosts.fallback(site);
+        } else {
+            vhosts.add(&host.names, site);
+        }
+    }
+    vhosts
+}
+
+fn endpoints() -> Router {
+    let mut router = Router::new();
+    router
+        .get("/", root)
+        .get("/user-agent", user_agent)
+        .get("/echo/{msg}", echo);
+    router
+}
+
+/// Serves `files` under `prefix`, and lets clients upload, replace and
+/// delete them if `writable`.
+fn mount(router: &mut Router, prefix: &str, files: StaticFiles, writable: bool) {
+    let pattern = format!("{}/{{*path}}", prefix.trim_end_matches('/'));
+    router.get(&pattern, files.clone());
+    if writable {
+        let (upload, store, remove) = (files.clone(), files.clone(), files);
+        router
+            .post(&pattern, move |req| post_file(upload.clone(), req))
+            .put(&pattern, move |req| put_file(store.clone(), req))
+            .delete(&pattern, move |req| delete_file(remove.clone(), req));
+    }
+}
+
+async fn root(_req: Request) -> Result<Response> {
+    Ok(Response::new(Body::empty()))
+}
+
+async fn user_agent(req: Request) -> Result<Response> {
+    let user_agent = match req.headers().get(USER_AGENT) {
+        Some(value) => value.as_bytes().to_vec(),
+        None => Vec::new(),
+    };
+    ok(user_agent, "text/plain")
+}
+
+async fn echo(req: Request) -> Result<Response> {
+    let msg = req.params().get("msg").unwrap_or_default();
+    ok(msg.to_string(), "text/plain")
+}
+
+async fn post_file(files: StaticFiles, req: Request) -> Result<Response> {
+    files
+        .upload(&req, req.params().get("path").unwrap_or_default())
+        .await
+}
+
+async fn put_file(files: StaticFiles, req: Request) -> Result<Response> {
+    files
+        .put(&req, req.params().get("path").unwrap_or_default())
+        .await
+}
+
+async fn delete_file(files: StaticFiles, req: Request) -> Result<Response> {
+    files
+        .delete(&req, req.params().get("path").unwrap_or_default())
+        .await
+}
+
+fn ok(body: impl Into<Body>, content_type: &'static str) -> Result<Response> {
+    let res = Response::builder()
+        .header(CONTENT_TYPE, HeaderValue::from_static(content_type))
+        .body(body.into())?;
+    Ok(res)
+}
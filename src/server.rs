@@ -0,0 +1,293 @@
+use std::{
+    future::Future,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use socket2::{Domain, Socket, Type};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::{watch, Semaphore},
+    task::JoinSet,
+};
+
+use crate::{conn, handler::Handler, tls::Tls};
+
+pub const DEFAULT_ADDR: &str = "127.0.0.1:4221";
+pub const DEFAULT_MAX_CONNECTIONS: usize = 65_536;
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+pub const DEFAULT_HEADER_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_MAX_HEADER_SIZE: usize = 64 * 1024;
+pub const DEFAULT_MAX_BODY_SIZE: usize = 64 * 1024 * 1024;
+
+/// Pending connections queued by the kernel on each listener.
+const LISTEN_BACKLOG: i32 = 1024;
+
+/// How long to pause accepting after an accept error. Errors such as
+/// `EMFILE` persist until a connection closes, so retrying at once would spin.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(50);
+
+/// Runtime settings for the server.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Addresses to listen on. Connections from all of them are served
+    /// alike.
+    pub listen: Vec<SocketAddr>,
+    /// Addresses to listen on for HTTPS, with the certificates of `tls`.
+    pub tls_listen: Vec<SocketAddr>,
+    pub tls: Option<Tls>,
+    /// Number of tokio worker threads.
+    pub workers: usize,
+    /// Upper bound on connections served at the same time. The accept loop
+    /// waits for a slot once the limit is reached.
+    pub max_connections: usize,
+    /// How long a connection may sit without sending anything before it is
+    /// closed.
+    pub idle_timeout: Duration,
+    /// How long a client may take to send a whole request head once it has
+    /// started one.
+    pub header_timeout: Duration,
+    /// How long a shutdown waits for the requests in flight before the
+    /// connections still open are dropped.
+    pub shutdown_timeout: Duration,
+    /// Largest request header section, in bytes.
+    pub max_header_size: usize,
+    /// Largest request body accepted, in bytes, whether it is sent with
+    /// `Content-Length` or chunked.
+    pub max_body_size: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen: vec![DEFAULT_ADDR.parse().expect("valid default address")],
+            tls_listen: Vec::new(),
+            tls: None,
+            workers: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            header_timeout: DEFAULT_HEADER_TIMEOUT,
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            max_header_size: DEFAULT_MAX_HEADER_SIZE,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+        }
+    }
+}
+
+impl Config {
+    /// Builds the multi-threaded runtime the server runs on.
+    pub fn runtime(&self) -> Result<tokio::runtime::Runtime> {
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(self.workers)
+            .enable_all()
+            .build()
+            .context("failed to build tokio runtime")
+    }
+}
+
+/// Serves HTTP/1.x with `handler` on the configured addresses, forever.
+///
+/// ```ignore
+/// let config = Config::default();
+/// config.runtime()?.block_on(server::serve(config, router))
+/// ```
+pub async fn serve<H: Handler>(config: Config, handler: H) -> Result<()> {
+    serve_with_shutdown(config, handler, std::future::pending::<()>()).await
+}
+
+/// Serves HTTP/1.x with `handler` until `shutdown` completes, then drains the
+/// connections as [`run`] does.
+///
+/// ```ignore
+/// server::serve_with_shutdown(config, router, tokio::signal::ctrl_c()).await
+/// ```
+pub async fn serve_with_shutdown<H, S>(config: Config, handler: H, shutdown: S) -> Result<()>
+where
+    H: Handler,
+    S: Future,
+{
+    let handler: Arc<dyn Handler> = Arc::new(handler);
+    let conn_config = Arc::new(config.clone());
+    run(config, shutdown, move |stream, peer, tls, draining| {
+        conn::serve_connection(
+            stream,
+            peer,
+            tls,
+            conn_config.clone(),
+            handler.clone(),
+            draining,
+        )
+    })
+    .await
+}
+
+/// Tells connections that the server is shutting down. A connection that
+/// sees it finishes the request it is serving, answers it with
+/// `Connection: close`, and does not wait for another.
+#[derive(Debug, Clone)]
+pub struct Draining(watch::Receiver<bool>);
+
+impl Draining {
+    pub fn is_draining(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Waits until the server starts shutting down.
+    pub async fn wait(&mut self) {
+        // A server that is gone has nothing left to drain either.
+        let _ = self.0.wait_for(|draining| *draining).await;
+    }
+}
+
+#[cfg(test)]
+impl Draining {
+    /// A server that starts draining when the sender says so.
+    pub(crate) fn channel() -> (watch::Sender<bool>, Draining) {
+        let (sender, receiver) = watch::channel(false);
+        (sender, Draining(receiver))
+    }
+}
+
+/// Accepts connections on every configured address until `shutdown`
+/// completes, serving each one on its own task. `handler` is given the
+/// [`Tls`] of the config for connections to the `tls_listen` addresses.
+///
+/// All addresses are bound before any connection is accepted, so a bad
+/// address fails the whole server at startup. On shutdown the listeners are
+/// closed, and open connections get up to
+/// [`shutdown_timeout`](Config::shutdown_timeout) to finish before they are
+/// dropped.
+pub async fn run<F, Fut, S>(config: Config, shutdown: S, handler: F) -> Result<()>
+where
+    F: Fn(TcpStream, SocketAddr, Option<Tls>, Draining) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+    S: Future,
+{
+    anyhow::ensure!(
+        !config.listen.is_empty() || !config.tls_listen.is_empty(),
+        "no address to listen on"
+    );
+    anyhow::ensure!(
+        config.tls_listen.is_empty() || config.tls.is_some(),
+        "HTTPS listeners need certificates"
+    );
+    let plain = config.listen.iter().map(|&addr| (addr, None));
+    let secure = config
+        .tls_listen
+        .iter()
+        .map(|&addr| (addr, config.tls.clone()));
+    let listeners = plain
+        .chain(secure)
+        .map(|(addr, tls)| {
+            let listener = bind(addr).with_context(|| format!("failed to bind {}", addr))?;
+            Ok((listener, tls))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let limit = Arc::new(Semaphore::new(config.max_connections));
+    let (drain, draining) = watch::channel(false);
+    let server = Arc::new(Accept {
+        handler,
+        limit: limit.clone(),
+        draining: Draining(draining),
+        accepted: AtomicUsize::new(0),
+    });
+
+    let mut accepting = JoinSet::new();
+    for (listener, tls) in listeners {
+        accepting.spawn(accept(listener, tls, server.clone()));
+    }
+    tokio::select! {
+        Some(result) = accepting.join_next() => return result?,
+        _ = shutdown => {}
+    }
+
+    // Closing the listeners also gives back the slots their loops held.
+    accepting.shutdown().await;
+    drain.send_replace(true);
+    let started = Instant::now();
+    let open = config.max_connections - limit.available_permits();
+    println!(
+        "shutting down: waiting up to {:?} for {} open connections",
+        config.shutdown_timeout, open
+    );
+    // Every connection holds a slot until it is done.
+    let all = u32::try_from(config.max_connections).unwrap_or(u32::MAX);
+    let drained = tokio::time::timeout(config.shutdown_timeout, limit.acquire_many(all)).await;
+    let accepted = server.accepted.load(Ordering::Relaxed);
+    match drained {
+        Ok(_) => println!(
+            "shut down: served {} connections, drained the last {} in {:.2?}",
+            accepted,
+            open,
+            started.elapsed()
+        ),
+        Err(_) => println!(
+            "shut down: served {} connections, dropped {} still open after {:?}",
+            accepted,
+            config.max_connections - limit.available_permits(),
+            config.shutdown_timeout
+        ),
+    }
+    Ok(())
+}
+
+/// What the accept loops share.
+struct Accept<F> {
+    handler: F,
+    limit: Arc<Semaphore>,
+    draining: Draining,
+    /// Connections accepted so far, for the shutdown summary.
+    accepted: AtomicUsize,
+}
+
+async fn accept<F, Fut>(
+    listener: TcpListener,
+    tls: Option<Tls>,
+    server: Arc<Accept<F>>,
+) -> Result<()>
+where
+    F: Fn(TcpStream, SocketAddr, Option<Tls>, Draining) -> Fut,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    loop {
+        let permit = server.limit.clone().acquire_owned().await?;
+        let (stream, peer) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                println!("error: {}", e);
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+                continue;
+            }
+        };
+
+        server.accepted.fetch_add(1, Ordering::Relaxed);
+        let conn = (server.handler)(stream, peer, tls.clone(), server.draining.clone());
+        tokio::spawn(async move {
+            if let Err(e) = conn.await {
+                println!("connection {} failed: {:#}", peer, e);
+            }
+            drop(permit);
+        });
+    }
+}
+
+/// Binds a listener on `addr`. IPv6 listeners only take IPv6 connections, so
+/// `[::]` and `0.0.0.0` can be listened on side by side.
+fn bind(addr: SocketAddr) -> Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+    Ok(TcpListener::from_std(socket.into())?)
+}
@@ -10,14 +10,14 @@ pub struct InvalidStatusCode {
     _priv: (),
 }
 impl InvalidStatusCode {
-    pub fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Self { _priv: () }
     }
 }
 
 impl StatusCode {
     pub fn from_u16(code: u16) -> Result<StatusCode, InvalidStatusCode> {
-        if code < 100 || code >= 1000 {
+        if !(100..1000).contains(&code) {
             return Err(InvalidStatusCode::new());
         }
         NonZeroU16::new(code)
@@ -147,7 +147,7 @@ impl From<StatusCode> for u16 {
 
 impl<'a> From<&'a StatusCode> for StatusCode {
     fn from(value: &'a StatusCode) -> Self {
-        value.clone()
+        *value
     }
 }
 
@@ -170,7 +170,10 @@ macro_rules! status_code {
         impl StatusCode {
             $(
                 $(#[$docs])*
-                pub const $ident: StatusCode = StatusCode(unsafe {NonZeroU16::new_unchecked($code)});
+                pub const $ident: StatusCode = StatusCode(match NonZeroU16::new($code) {
+                    Some(code) => code,
+                    None => panic!("status code must be non-zero"),
+                });
             )+
         }
 
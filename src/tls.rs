@@ -0,0 +1,480 @@
+//! HTTPS: TLS termination with rustls.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use rustls::server::{
+    AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, ClientHello, NoClientAuth,
+    ResolvesServerCert, ServerConnection,
+};
+use rustls::sign::{self, CertifiedKey};
+use rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig};
+use rustls_pemfile::Item;
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+use crate::vhost::HostTable;
+
+/// The ALPN protocol id of HTTP/2.
+pub const ALPN_H2: &[u8] = b"h2";
+/// The ALPN protocol id of HTTP/1.1.
+pub const ALPN_HTTP_11: &[u8] = b"http/1.1";
+
+/// Certificates and client authentication for HTTPS listeners.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsSettings {
+    /// The certificates to choose from by the server name the client sends
+    /// (SNI).
+    pub certificates: Vec<CertificateFiles>,
+    pub client_auth: ClientAuth,
+    /// Protocols offered with ALPN, most preferred first.
+    pub alpn: Vec<Vec<u8>>,
+}
+
+/// A certificate chain and its private key, as PEM files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertificateFiles {
+    /// The server names this certificate is for, with `*.` wildcards as in
+    /// [`VirtualHosts`](crate::vhost::VirtualHosts). Empty for the
+    /// certificate used when no other one matches.
+    pub names: Vec<String>,
+    /// The chain, the server's own certificate first.
+    pub cert: PathBuf,
+    /// A PKCS#8, PKCS#1 or SEC1 private key.
+    pub key: PathBuf,
+}
+
+/// Whether clients are asked for a certificate (mutual TLS).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ClientAuth {
+    #[default]
+    Off,
+    /// Clients may present a certificate issued by one of the CAs in the PEM
+    /// file, or none at all.
+    Optional(PathBuf),
+    /// Clients must present a certificate issued by one of the CAs in the
+    /// PEM file.
+    Required(PathBuf),
+}
+
+impl Default for TlsSettings {
+    fn default() -> Self {
+        TlsSettings {
+            certificates: Vec::new(),
+            client_auth: ClientAuth::Off,
+            alpn: vec![ALPN_H2.to_vec(), ALPN_HTTP_11.to_vec()],
+        }
+    }
+}
+
+/// Why certificates could not be loaded.
+#[derive(Debug, Error)]
+pub enum TlsError {
+    #[error("failed to read {}", path.display())]
+    Read { path: PathBuf, source: io::Error },
+    #[error("no certificates in {}", .0.display())]
+    NoCertificates(PathBuf),
+    #[error("no private key in {}", .0.display())]
+    NoKey(PathBuf),
+    #[error("unsupported private key in {}", .0.display())]
+    UnsupportedKey(PathBuf),
+    #[error("invalid CA certificate in {}", .0.display())]
+    InvalidCa(PathBuf),
+    #[error("no certificates configured")]
+    NoCertificate,
+}
+
+/// Accepts TLS connections with certificates that can be reloaded while the
+/// server runs.
+///
+/// Clones share the certificates, so reloading one reloads them all.
+///
+/// ```ignore
+/// let tls = Tls::new(&settings)?;
+/// let (stream, info) = tls.accept(tcp).await?;
+/// ```
+#[derive(Clone)]
+pub struct Tls {
+    current: Arc<RwLock<Arc<ServerConfig>>>,
+}
+
+impl Tls {
+    pub fn new(settings: &TlsSettings) -> Result<Tls, TlsError> {
+        Ok(Tls {
+            current: Arc::new(RwLock::new(server_config(settings)?)),
+        })
+    }
+
+    /// Loads `settings` for the handshakes that start from now on. If any
+    /// file fails to load, the certificates in use are kept.
+    pub fn reload(&self, settings: &TlsSettings) -> Result<(), TlsError> {
+        let config = server_config(settings)?;
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) = config;
+        Ok(())
+    }
+
+    /// Runs the server side of the handshake on `stream`.
+    pub async fn accept<S>(&self, stream: S) -> io::Result<(TlsStream<S>, TlsInfo)>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let config = Arc::clone(&self.current.read().unwrap_or_else(|e| e.into_inner()));
+        let stream = TlsAcceptor::from(config).accept(stream).await?;
+        let info = TlsInfo::new(stream.get_ref().1);
+        Ok((stream, info))
+    }
+}
+
+impl fmt::Debug for Tls {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tls").finish_non_exhaustive()
+    }
+}
+
+/// What the handshake of a TLS connection settled, as seen by handlers
+/// through [`Request::tls`](crate::request::Request::tls).
+#[derive(Debug, Clone, Default)]
+pub struct TlsInfo {
+    server_name: Option<String>,
+    alpn_protocol: Option<Vec<u8>>,
+    peer_certificates: Vec<Vec<u8>>,
+    peer_subject: Option<String>,
+}
+
+impl TlsInfo {
+    fn new(conn: &ServerConnection) -> TlsInfo {
+        let peer_certificates: Vec<Vec<u8>> = conn
+            .peer_certificates()
+            .unwrap_or_default()
+            .iter()
+            .map(|cert| cert.0.clone())
+            .collect();
+        let peer_subject = peer_certificates.first().and_then(|der| {
+            let (_, cert) = x509_parser::parse_x509_certificate(der).ok()?;
+            Some(cert.subject().to_string())
+        });
+        TlsInfo {
+            server_name: conn.server_name().map(str::to_string),
+            alpn_protocol: conn.alpn_protocol().map(<[u8]>::to_vec),
+            peer_certificates,
+            peer_subject,
+        }
+    }
+
+    /// The host name the client asked for with SNI.
+    pub fn server_name(&self) -> Option<&str> {
+        self.server_name.as_deref()
+    }
+
+    /// The protocol agreed on with ALPN.
+    pub fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.alpn_protocol.as_deref()
+    }
+
+    /// The certificate chain the client presented, as DER, its own
+    /// certificate first. It has been verified against the client CAs. Empty
+    /// if the client sent none.
+    pub fn peer_certificates(&self) -> &[Vec<u8>] {
+        &self.peer_certificates
+    }
+
+    /// The subject of the client's certificate, such as
+    /// `CN=alice, O=Example`.
+    pub fn peer_subject(&self) -> Option<&str> {
+        self.peer_subject.as_deref()
+    }
+}
+
+/// Picks the certificate for the server name of the client hello. Without a
+/// match, and without a certificate for any name, the handshake fails.
+struct ByServerName(HostTable<Arc<CertifiedKey>>);
+
+impl ResolvesServerCert for ByServerName {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        self.0.select(client_hello.server_name()).cloned()
+    }
+}
+
+fn server_config(settings: &TlsSettings) -> Result<Arc<ServerConfig>, TlsError> {
+    if settings.certificates.is_empty() {
+        return Err(TlsError::NoCertificate);
+    }
+    let mut certificates = HostTable::default();
+    for files in &settings.certificates {
+        let key = Arc::new(load_certified_key(files)?);
+        if files.names.is_empty() {
+            certificates.fallback(key);
+        } else {
+            certificates.add(&files.names, key);
+        }
+    }
+
+    let verifier = match &settings.client_auth {
+        ClientAuth::Off => NoClientAuth::boxed(),
+        ClientAuth::Optional(ca) => {
+            AllowAnyAnonymousOrAuthenticatedClient::new(load_roots(ca)?).boxed()
+        }
+        ClientAuth::Required(ca) => AllowAnyAuthenticatedClient::new(load_roots(ca)?).boxed(),
+    };
+    let mut config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_client_cert_verifier(verifier)
+        .with_cert_resolver(Arc::new(ByServerName(certificates)));
+    config.alpn_protocols = settings.alpn.clone();
+    Ok(Arc::new(config))
+}
+
+fn load_certified_key(files: &CertificateFiles) -> Result<CertifiedKey, TlsError> {
+    let chain: Vec<Certificate> = read_pem(&files.cert)?
+        .into_iter()
+        .filter_map(|item| match item {
+            Item::X509Certificate(der) => Some(Certificate(der)),
+            _ => None,
+        })
+        .collect();
+    if chain.is_empty() {
+        return Err(TlsError::NoCertificates(files.cert.clone()));
+    }
+    let key = read_pem(&files.key)?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::PKCS8Key(der) | Item::RSAKey(der) | Item::ECKey(der) => Some(PrivateKey(der)),
+            _ => None,
+        })
+        .ok_or_else(|| TlsError::NoKey(files.key.clone()))?;
+    let key =
+        sign::any_supported_type(&key).map_err(|_| TlsError::UnsupportedKey(files.key.clone()))?;
+    Ok(CertifiedKey::new(chain, key))
+}
+
+fn load_roots(path: &Path) -> Result<RootCertStore, TlsError> {
+    let mut roots = RootCertStore::empty();
+    for item in read_pem(path)? {
+        if let Item::X509Certificate(der) = item {
+            roots
+                .add(&Certificate(der))
+                .map_err(|_| TlsError::InvalidCa(path.to_path_buf()))?;
+        }
+    }
+    if roots.is_empty() {
+        return Err(TlsError::NoCertificates(path.to_path_buf()));
+    }
+    Ok(roots)
+}
+
+fn read_pem(path: &Path) -> Result<Vec<Item>, TlsError> {
+    let read = |source| TlsError::Read {
+        path: path.to_path_buf(),
+        source,
+    };
+    let file = File::open(path).map_err(read)?;
+    rustls_pemfile::read_all(&mut BufReader::new(file)).map_err(read)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CertificateFiles, ClientAuth, Tls, TlsInfo, TlsSettings};
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa};
+    use rustls::{Certificate, ClientConfig, PrivateKey, RootCertStore};
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use tokio_rustls::TlsConnector;
+
+    struct Pki {
+        dir: PathBuf,
+        ca: rcgen::Certificate,
+    }
+
+    impl Pki {
+        fn new(name: &str) -> Pki {
+            let dir = std::env::temp_dir().join(format!("tls-{}-{}", name, std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let mut params = CertificateParams::new(Vec::new());
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            params
+                .distinguished_name
+                .push(DnType::CommonName, "Test CA");
+            let ca = rcgen::Certificate::from_params(params).unwrap();
+            std::fs::write(dir.join("ca.pem"), ca.serialize_pem().unwrap()).unwrap();
+            Pki { dir, ca }
+        }
+
+        /// Issues a certificate for `names`, written to `{stem}.pem` and
+        /// `{stem}.key`.
+        fn issue(&self, stem: &str, names: &[&str]) -> CertificateFiles {
+            let mut params =
+                CertificateParams::new(names.iter().map(|n| n.to_string()).collect::<Vec<_>>());
+            params.distinguished_name.push(DnType::CommonName, stem);
+            let cert = rcgen::Certificate::from_params(params).unwrap();
+            let files = CertificateFiles {
+                names: names.iter().map(|n| n.to_string()).collect(),
+                cert: self.dir.join(format!("{}.pem", stem)),
+                key: self.dir.join(format!("{}.key", stem)),
+            };
+            let pem = cert.serialize_pem_with_signer(&self.ca).unwrap();
+            std::fs::write(&files.cert, pem).unwrap();
+            std::fs::write(&files.key, cert.serialize_private_key_pem()).unwrap();
+            files
+        }
+
+        fn ca_file(&self) -> PathBuf {
+            self.dir.join("ca.pem")
+        }
+
+        fn client(&self, identity: Option<&CertificateFiles>) -> ClientConfig {
+            let mut roots = RootCertStore::empty();
+            roots.add(&Certificate(der(&self.ca_file()))).unwrap();
+            let builder = ClientConfig::builder()
+                .with_safe_defaults()
+                .with_root_certificates(roots);
+            let mut config = match identity {
+                Some(files) => {
+                    let key = rustls_pemfile::pkcs8_private_keys(
+                        &mut &*std::fs::read(&files.key).unwrap(),
+                    )
+                    .unwrap();
+                    builder
+                        .with_client_auth_cert(
+                            vec![Certificate(der(&files.cert))],
+                            PrivateKey(key[0].clone()),
+                        )
+                        .unwrap()
+                }
+                None => builder.with_no_client_auth(),
+            };
+            config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+            config
+        }
+    }
+
+    impl Drop for Pki {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    /// The first certificate in a PEM file, as DER.
+    fn der(path: &Path) -> Vec<u8> {
+        let pem = std::fs::read(path).unwrap();
+        rustls_pemfile::certs(&mut &*pem).unwrap().remove(0)
+    }
+
+    /// Connects to `tls` as `server_name`, returning the certificate the
+    /// server presented and what the server saw.
+    async fn handshake(
+        tls: &Tls,
+        client: ClientConfig,
+        server_name: &str,
+    ) -> io::Result<(Vec<u8>, TlsInfo)> {
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+        let server = tokio::spawn({
+            let tls = tls.clone();
+            async move { tls.accept(server_io).await.map(|(_, info)| info) }
+        });
+        let name = server_name.try_into().unwrap();
+        let connected = TlsConnector::from(Arc::new(client))
+            .connect(name, client_io)
+            .await;
+        let info = server.await.unwrap()?;
+        let stream = connected?;
+        let presented = stream.get_ref().1.peer_certificates().unwrap()[0].0.clone();
+        Ok((presented, info))
+    }
+
+    #[tokio::test]
+    async fn select_certificates_by_server_name() {
+        let pki = Pki::new("sni");
+        let settings = TlsSettings {
+            certificates: vec![
+                pki.issue("a", &["a.test"]),
+                pki.issue("b", &["*.b.test"]),
+                CertificateFiles {
+                    names: Vec::new(),
+                    ..pki.issue("default", &["localhost"])
+                },
+            ],
+            ..TlsSettings::default()
+        };
+        let tls = Tls::new(&settings).unwrap();
+
+        for (name, stem) in [
+            ("a.test", "a"),
+            ("www.b.test", "b"),
+            ("localhost", "default"),
+        ] {
+            let (presented, info) = handshake(&tls, pki.client(None), name)
+                .await
+                .unwrap_or_else(|e| panic!("{}: {}", name, e));
+            assert_eq!(
+                presented,
+                der(&pki.dir.join(format!("{}.pem", stem))),
+                "{}",
+                name
+            );
+            assert_eq!(info.server_name(), Some(name));
+            assert_eq!(info.alpn_protocol(), Some(&b"h2"[..]));
+            assert!(info.peer_certificates().is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn verify_client_certificates() {
+        let pki = Pki::new("mtls");
+        let alice = pki.issue("alice", &[]);
+        let mut settings = TlsSettings {
+            certificates: vec![pki.issue("server", &["server.test"])],
+            client_auth: ClientAuth::Required(pki.ca_file()),
+            ..TlsSettings::default()
+        };
+        let tls = Tls::new(&settings).unwrap();
+        assert!(handshake(&tls, pki.client(None), "server.test")
+            .await
+            .is_err());
+        let (_, info) = handshake(&tls, pki.client(Some(&alice)), "server.test")
+            .await
+            .unwrap();
+        assert_eq!(info.peer_subject(), Some("CN=alice"));
+        assert_eq!(info.peer_certificates()[0], der(&alice.cert));
+
+        settings.client_auth = ClientAuth::Optional(pki.ca_file());
+        tls.reload(&settings).unwrap();
+        let (_, info) = handshake(&tls, pki.client(None), "server.test")
+            .await
+            .unwrap();
+        assert_eq!(info.peer_subject(), None);
+    }
+
+    #[tokio::test]
+    async fn reload_certificates() {
+        let pki = Pki::new("reload");
+        let settings = TlsSettings {
+            certificates: vec![pki.issue("site", &["site.test"])],
+            ..TlsSettings::default()
+        };
+        let tls = Tls::new(&settings).unwrap();
+        let (before, _) = handshake(&tls, pki.client(None), "site.test")
+            .await
+            .unwrap();
+
+        pki.issue("site", &["site.test"]);
+        tls.reload(&settings).unwrap();
+        let (after, _) = handshake(&tls, pki.client(None), "site.test")
+            .await
+            .unwrap();
+        assert_ne!(before, after);
+        assert_eq!(after, der(&settings.certificates[0].cert));
+
+        std::fs::remove_file(&settings.certificates[0].key).unwrap();
+        assert!(tls.reload(&settings).is_err());
+        let (kept, _) = handshake(&tls, pki.client(None), "site.test")
+            .await
+            .unwrap();
+        assert_eq!(kept, after);
+    }
+}
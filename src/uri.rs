@@ -0,0 +1,482 @@
+use core::fmt;
+use std::borrow::Cow;
+use std::error::Error;
+use std::str::FromStr;
+
+use bytes::Bytes;
+
+use crate::byte_str::ByteStr;
+
+/// A request target (RFC 9112, section 3.2).
+///
+/// The target is kept as sent, percent-encoding included; [`Uri::path`] and
+/// [`Uri::query`] return the raw parts. Use [`Uri::decoded_path`] and
+/// [`Uri::query_pairs`] for the decoded values.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Uri {
+    form: Form,
+    scheme: Option<ByteStr>,
+    authority: Option<ByteStr>,
+    path: ByteStr,
+    query: Option<ByteStr>,
+}
+
+/// The host of an authority such as `example.com:80` or `[::1]:80`: without
+/// the port, and without the brackets around an IPv6 address.
+pub(crate) fn authority_host(authority: &str) -> Option<&str> {
+    if let Some(rest) = authority.strip_prefix('[') {
+        return rest.split_once(']').map(|(host, _)| host);
+    }
+    Some(
+        authority
+            .rsplit_once(':')
+            .map_or(authority, |(host, _)| host),
+    )
+}
+
+/// The four forms a request target can take.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Form {
+    /// `/path?query`, used for most requests.
+    Origin,
+    /// `http://host/path?query`, used for requests to proxies.
+    Absolute,
+    /// `host:port`, only used by `CONNECT`.
+    Authority,
+    /// `*`, only used by a server-wide `OPTIONS`.
+    Asterisk,
+}
+
+/// The decoded `key=value` pairs of a query string, in order.
+///
+/// Keys may repeat; [`Query::get`] returns the first value and
+/// [`Query::get_all`] every value.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Query {
+    pairs: Vec<(String, String)>,
+}
+
+pub struct InvalidUri {
+    _priv: (),
+}
+
+impl Uri {
+    /// Parses a request target held in `src` without copying it.
+    pub fn from_shared(src: Bytes) -> Result<Uri, InvalidUri> {
+        if src.is_empty() || !src.iter().all(|b| (0x21..0x7f).contains(b)) {
+            return Err(InvalidUri::new());
+        }
+        // Safety: checked to be visible ASCII above, so every slice taken below
+        // is valid UTF-8.
+        let slice = |start: usize, end: usize| unsafe {
+            ByteStr::from_utf8_unchecked(src.slice(start..end))
+        };
+
+        if &src[..] == b"*" {
+            return Ok(Uri {
+                form: Form::Asterisk,
+                scheme: None,
+                authority: None,
+                path: slice(0, 1),
+                query: None,
+            });
+        }
+
+        if src[0] == b'/' {
+            let (path, query) = split_query(&src, 0)?;
+            return Ok(Uri {
+                form: Form::Origin,
+                scheme: None,
+                authority: None,
+                path: slice(path.0, path.1),
+                query: query.map(|(start, end)| slice(start, end)),
+            });
+        }
+
+        if let Some(scheme_end) = scheme_len(&src) {
+            if src[scheme_end..].starts_with(b"://") {
+                let auth_start = scheme_end + 3;
+                let auth_end = src[auth_start..]
+                    .iter()
+                    .position(|&b| b == b'/' || b == b'?')
+                    .map_or(src.len(), |i| auth_start + i);
+                check_authority(&src[auth_start..auth_end])?;
+                let (path, query) = split_query(&src, auth_end)?;
+                let path = if path.0 == path.1 {
+                    ByteStr::from_static("/")
+                } else {
+                    slice(path.0, path.1)
+                };
+                return Ok(Uri {
+                    form: Form::Absolute,
+                    scheme: Some(slice(0, scheme_end)),
+                    authority: Some(slice(auth_start, auth_end)),
+                    path,
+                    query: query.map(|(start, end)| slice(start, end)),
+                });
+            }
+        }
+
+        // Authority-form must name a port.
+        check_authority(&src)?;
+        match src.iter().rposition(|&b| b == b':') {
+            Some(colon)
+                if colon + 1 < src.len() && src[colon + 1..].iter().all(u8::is_ascii_digit) => {}
+            _ => return Err(InvalidUri::new()),
+        }
+        Ok(Uri {
+            form: Form::Authority,
+            scheme: None,
+            authority: Some(slice(0, src.len())),
+            path: ByteStr::new(),
+            query: None,
+        })
+    }
+
+    #[inline]
+    pub fn form(&self) -> Form {
+        self.form
+    }
+
+    pub fn scheme(&self) -> Option<&str> {
+        self.scheme.as_deref()
+    }
+
+    pub fn authority(&self) -> Option<&str> {
+        self.authority.as_deref()
+    }
+
+    /// The host of the authority, without brackets around IPv6 addresses.
+    pub fn host(&self) -> Option<&str> {
+        authority_host(self.authority()?)
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        let authority = self.authority()?;
+        let (host, port) = authority.rsplit_once(':')?;
+        if host.starts_with('[') && !host.ends_with(']') {
+            return None;
+        }
+        port.parse().ok()
+    }
+
+    /// The path as sent, still percent-encoded. Empty for authority-form.
+    #[inline]
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The query as sent, without the `?`.
+    pub fn query(&self) -> Option<&str> {
+        self.query.as_deref()
+    }
+
+    /// The percent-decoded path.
+    pub fn decoded_path(&self) -> Cow<'_, str> {
+        // Paths whose escapes do not decode to UTF-8 are rejected by the parser.
+        percent_decode(&self.path).unwrap_or(Cow::Borrowed(&self.path))
+    }
+
+    /// The decoded pairs of the query string.
+    pub fn query_pairs(&self) -> Query {
+        Query::parse(self.query().unwrap_or(""))
+    }
+}
+
+impl Query {
+    /// Parses an `application/x-www-form-urlencoded` query string: pairs are
+    /// separated by `&`, `+` stands for a space, and a key without `=` has an
+    /// empty value.
+    pub fn parse(query: &str) -> Query {
+        let pairs = query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (decode_form(key), decode_form(value))
+            })
+            .collect();
+        Query { pairs }
+    }
+
+    /// The first value of `key`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.pairs
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Every value of `key`, in order.
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.pairs
+            .iter()
+            .filter(move |(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+}
+
+/// Decodes `%HH` escapes. Fails on a malformed escape or if the result is
+/// not UTF-8.
+pub fn percent_decode(input: &str) -> Result<Cow<'_, str>, InvalidUri> {
+    if !input.contains('%') {
+        return Ok(Cow::Borrowed(input));
+    }
+    let bytes = decode_bytes(input.as_bytes(), false)?;
+    String::from_utf8(bytes)
+        .map(Cow::Owned)
+        .map_err(|_| InvalidUri::new())
+}
+
+fn decode_form(input: &str) -> String {
+    match decode_bytes(input.as_bytes(), true) {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        Err(_) => input.to_string(),
+    }
+}
+
+fn decode_bytes(input: &[u8], plus_as_space: bool) -> Result<Vec<u8>, InvalidUri> {
+    let mut out = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        match input[i] {
+            b'%' => {
+                let hex = input.get(i + 1..i + 3).ok_or_else(InvalidUri::new)?;
+                out.push((hex_value(hex[0])? << 4) | hex_value(hex[1])?);
+                i += 3;
+            }
+            b'+' if plus_as_space => {
+                out.push(b' ');
+                i += 1;
+            }
+            b => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn hex_value(b: u8) -> Result<u8, InvalidUri> {
+    match b {
+        b'0'..=b'9' => Ok(b - b'0'),
+        b'a'..=b'f' => Ok(b - b'a' + 10),
+        b'A'..=b'F' => Ok(b - b'A' + 10),
+        _ => Err(InvalidUri::new()),
+    }
+}
+
+/// Splits `src[start..]` into the path and the query ranges. The path must
+/// decode to UTF-8 and neither part may hold a fragment or a bad escape.
+#[allow(clippy::type_complexity)]
+fn split_query(
+    src: &[u8],
+    start: usize,
+) -> Result<((usize, usize), Option<(usize, usize)>), InvalidUri> {
+    if src[start..].contains(&b'#') {
+        return Err(InvalidUri::new());
+    }
+    let (path, query) = match src[start..].iter().position(|&b| b == b'?') {
+        Some(q) => ((start, start + q), Some((start + q + 1, src.len()))),
+        None => ((start, src.len()), None),
+    };
+    let path_bytes = decode_bytes(&src[path.0..path.1], false)?;
+    if std::str::from_utf8(&path_bytes).is_err() {
+        return Err(InvalidUri::new());
+    }
+    if let Some((q0, q1)) = query {
+        decode_bytes(&src[q0..q1], false)?;
+    }
+    Ok((path, query))
+}
+
+/// Length of a leading `scheme` (RFC 3986, section 3.1), if there is one.
+fn scheme_len(src: &[u8]) -> Option<usize> {
+    if !src.first()?.is_ascii_alphabetic() {
+        return None;
+    }
+    let len = src
+        .iter()
+        .position(|&b| !(b.is_ascii_alphanumeric() || b == b'+' || b == b'-' || b == b'.'))
+        .unwrap_or(src.len());
+    Some(len)
+}
+
+/// Checks `host [ ":" port ]`. User info is not accepted in requests.
+fn check_authority(authority: &[u8]) -> Result<(), InvalidUri> {
+    let valid = !authority.is_empty()
+        && authority
+            .iter()
+            .all(|&b| b.is_ascii_alphanumeric() || b"-._~!$&'()*+,;=:[]%".contains(&b));
+    if valid {
+        Ok(())
+    } else {
+        Err(InvalidUri::new())
+    }
+}
+
+impl FromStr for Uri {
+    type Err = InvalidUri;
+
+    fn from_str(s: &str) -> Result<Uri, InvalidUri> {
+        Uri::from_shared(Bytes::copy_from_slice(s.as_bytes()))
+    }
+}
+
+impl<'a> TryFrom<&'a str> for Uri {
+    type Error = InvalidUri;
+
+    fn try_from(s: &'a str) -> Result<Uri, InvalidUri> {
+        s.parse()
+    }
+}
+
+impl Default for Uri {
+    /// The origin-form target `/`.
+    fn default() -> Uri {
+        Uri {
+            form: Form::Origin,
+            scheme: None,
+            authority: None,
+            path: ByteStr::from_static("/"),
+            query: None,
+        }
+    }
+}
+
+impl fmt::Display for Uri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(scheme) = self.scheme() {
+            write!(f, "{}://", scheme)?;
+        }
+        if let Some(authority) = self.authority() {
+            f.write_str(authority)?;
+        }
+        f.write_str(self.path())?;
+        if let Some(query) = self.query() {
+            write!(f, "?{}", query)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Uri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl InvalidUri {
+    pub(crate) fn new() -> Self {
+        InvalidUri { _priv: () }
+    }
+}
+
+impl fmt::Debug for InvalidUri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InvalidUri").finish()
+    }
+}
+
+impl fmt::Display for InvalidUri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid request target")
+    }
+}
+
+impl Error for InvalidUri {}
+
+#[cfg(test)]
+mod tests {
+    use super::{percent_decode, Form, Query, Uri};
+
+    #[test]
+    fn parse_origin_form() {
+        let uri: Uri = "/echo/hello%20world?a=1&b=two+words&a=%33".parse().unwrap();
+        assert_eq!(uri.form(), Form::Origin);
+        assert_eq!(uri.path(), "/echo/hello%20world");
+        assert_eq!(uri.decoded_path(), "/echo/hello world");
+        assert_eq!(uri.query(), Some("a=1&b=two+words&a=%33"));
+
+        let query = uri.query_pairs();
+        assert_eq!(query.get("a"), Some("1"));
+        assert_eq!(query.get_all("a").collect::<Vec<_>>(), ["1", "3"]);
+        assert_eq!(query.get("b"), Some("two words"));
+        assert_eq!(query.len(), 3);
+    }
+
+    #[test]
+    fn parse_absolute_form() {
+        let uri: Uri = "http://[::1]:4221/files/a?x".parse().unwrap();
+        assert_eq!(uri.form(), Form::Absolute);
+        assert_eq!(uri.scheme(), Some("http"));
+        assert_eq!(uri.authority(), Some("[::1]:4221"));
+        assert_eq!(uri.host(), Some("::1"));
+        assert_eq!(uri.port(), Some(4221));
+        assert_eq!(uri.path(), "/files/a");
+        assert_eq!(uri.query(), Some("x"));
+
+        let uri: Uri = "http://example.com".parse().unwrap();
+        assert_eq!(uri.path(), "/");
+        assert_eq!(uri.host(), Some("example.com"));
+        assert_eq!(uri.port(), None);
+    }
+
+    #[test]
+    fn parse_authority_and_asterisk_forms() {
+        let uri: Uri = "example.com:443".parse().unwrap();
+        assert_eq!(uri.form(), Form::Authority);
+        assert_eq!(uri.host(), Some("example.com"));
+        assert_eq!(uri.port(), Some(443));
+        assert_eq!(uri.path(), "");
+
+        let uri: Uri = "*".parse().unwrap();
+        assert_eq!(uri.form(), Form::Asterisk);
+        assert_eq!(uri.path(), "*");
+    }
+
+    #[test]
+    fn reject_invalid_targets() {
+        for target in [
+            "",
+            "/a b",
+            "/a#frag",
+            "/bad%zz",
+            "/short%2",
+            "/latin%e9",
+            "example.com",
+            "http://",
+            "user@host:80",
+        ] {
+            assert!(target.parse::<Uri>().is_err(), "{:?} parsed", target);
+        }
+    }
+
+    #[test]
+    fn decode_helpers() {
+        assert_eq!(percent_decode("a%2Fb").unwrap(), "a/b");
+        assert_eq!(percent_decode("caf%C3%A9").unwrap(), "café");
+        assert!(percent_decode("%ff").is_err());
+
+        let query = Query::parse("flag&&k=%zz");
+        assert_eq!(query.get("flag"), Some(""));
+        assert_eq!(query.get("k"), Some("%zz"));
+    }
+}
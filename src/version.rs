@@ -2,6 +2,7 @@
 
 use core::fmt;
 
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
 pub struct Version(Http);
 
 impl Version {
@@ -0,0 +1,213 @@
+//! Name-based virtual hosts.
+
+use std::collections::HashMap;
+use std::future::ready;
+
+use anyhow::Result;
+
+use crate::handler::{BoxFuture, BoxHandler, Handler};
+use crate::request::Request;
+use crate::response::Response;
+use crate::status::StatusCode;
+
+/// Picks the handler for a request by the host it is for, as given by
+/// [`Request::host`].
+///
+/// Names are matched without regard to case. A name starting with `*.`
+/// matches every subdomain of the rest of the name, and the longest such
+/// match wins. Requests for hosts that match no name go to the fallback
+/// handler, or are answered with `421 Misdirected Request` if there is none.
+///
+/// ```ignore
+/// let mut hosts = VirtualHosts::new();
+/// hosts
+///     .add(["example.com", "*.example.com"], site)
+///     .fallback(default_site);
+/// ```
+#[derive(Default)]
+pub struct VirtualHosts {
+    hosts: HostTable<BoxHandler>,
+}
+
+impl VirtualHosts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serves the requests for any of `names` with `handler`. A name that
+    /// was already added keeps its first handler.
+    pub fn add<I>(&mut self, names: I, handler: impl Into<BoxHandler>) -> &mut Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        self.hosts.add(names, handler.into());
+        self
+    }
+
+    /// Serves the requests for hosts that match no name, and requests that
+    /// name no host, with `handler`.
+    pub fn fallback(&mut self, handler: impl Into<BoxHandler>) -> &mut Self {
+        self.hosts.fallback(handler.into());
+        self
+    }
+
+    /// The handler for requests to `host`.
+    pub fn select(&self, host: Option<&str>) -> Option<&BoxHandler> {
+        self.hosts.select(host)
+    }
+}
+
+impl Handler for VirtualHosts {
+    fn call(&self, req: Request) -> BoxFuture<'static, Result<Response>> {
+        match self.select(req.host()) {
+            Some(handler) => handler.call(req),
+            None => Box::pin(ready(Ok(Response::with_status(
+                StatusCode::MISDIRECTED_REQUEST,
+            )))),
+        }
+    }
+}
+
+/// Values looked up by host name, with the matching rules of
+/// [`VirtualHosts`].
+#[derive(Debug)]
+pub(crate) struct HostTable<T> {
+    values: Vec<T>,
+    exact: HashMap<String, usize>,
+    /// Wildcard names as `.example.com`, longest first.
+    wildcards: Vec<(String, usize)>,
+    fallback: Option<usize>,
+}
+
+impl<T> Default for HostTable<T> {
+    fn default() -> Self {
+        HostTable {
+            values: Vec::new(),
+            exact: HashMap::new(),
+            wildcards: Vec::new(),
+            fallback: None,
+        }
+    }
+}
+
+impl<T> HostTable<T> {
+    /// Maps each of `names` to `value`, unless it is mapped already.
+    pub(crate) fn add<I>(&mut self, names: I, value: T)
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let index = self.values.len();
+        self.values.push(value);
+        for name in names {
+            let name = normalize(name.as_ref());
+            match name.strip_prefix('*') {
+                Some(suffix) if suffix.starts_with('.') => {
+                    if !self.wildcards.iter().any(|(s, _)| s == suffix) {
+                        self.wildcards.push((suffix.to_string(), index));
+                    }
+                }
+                _ => {
+                    self.exact.entry(name).or_insert(index);
+                }
+            }
+        }
+        self.wildcards
+            .sort_by_key(|(suffix, _)| usize::MAX - suffix.len());
+    }
+
+    /// Maps hosts that match no name, and the lack of a host, to `value`.
+    pub(crate) fn fallback(&mut self, value: T) {
+        self.fallback = Some(self.values.len());
+        self.values.push(value);
+    }
+
+    pub(crate) fn select(&self, host: Option<&str>) -> Option<&T> {
+        let index = host.and_then(|host| {
+            let host = normalize(host);
+            self.exact.get(&host).copied().or_else(|| {
+                self.wildcards
+                    .iter()
+                    .find(|(suffix, _)| {
+                        host.len() > suffix.len() && host.ends_with(suffix.as_str())
+                    })
+                    .map(|&(_, index)| index)
+            })
+        });
+        index.or(self.fallback).map(|index| &self.values[index])
+    }
+}
+
+/// Host names compare in lowercase and without a trailing dot.
+fn normalize(host: &str) -> String {
+    host.strip_suffix('.').unwrap_or(host).to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VirtualHosts;
+    use crate::handler::sync;
+    use crate::request::Request;
+    use crate::response::Response;
+    use crate::status::StatusCode;
+    use crate::Handler;
+
+    fn answer(status: StatusCode) -> impl Handler {
+        sync(move |_| Ok(Response::with_status(status)))
+    }
+
+    async fn status(hosts: &VirtualHosts, host: Option<&str>) -> StatusCode {
+        let mut req = Request::default();
+        if let Some(host) = host {
+            req.headers_mut().insert("host", host.parse().unwrap());
+        }
+        hosts.call(req).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn select_hosts_by_name() {
+        let mut hosts = VirtualHosts::new();
+        hosts
+            .add(["example.com", "*.example.com"], answer(StatusCode::OK))
+            .add(["*.api.example.com"], answer(StatusCode::ACCEPTED))
+            .add(["::1"], answer(StatusCode::CREATED));
+
+        assert_eq!(status(&hosts, Some("example.com")).await, StatusCode::OK);
+        assert_eq!(
+            status(&hosts, Some("WWW.Example.com.:8080")).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(&hosts, Some("v1.api.example.com")).await,
+            StatusCode::ACCEPTED
+        );
+        assert_eq!(
+            status(&hosts, Some("[::1]:4221")).await,
+            StatusCode::CREATED
+        );
+        assert_eq!(
+            status(&hosts, Some("other.org")).await,
+            StatusCode::MISDIRECTED_REQUEST
+        );
+        assert_eq!(status(&hosts, None).await, StatusCode::MISDIRECTED_REQUEST);
+
+        hosts.fallback(answer(StatusCode::NO_CONTENT));
+        assert_eq!(
+            status(&hosts, Some("other.org")).await,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(status(&hosts, None).await, StatusCode::NO_CONTENT);
+        // Wildcards only match whole labels.
+        assert_eq!(
+            status(&hosts, Some("xexample.com")).await,
+            StatusCode::NO_CONTENT
+        );
+
+        let mut req = Request::default();
+        *req.uri_mut() = "http://api.example.com/".parse().unwrap();
+        req.headers_mut()
+            .insert("host", "ignored.org".parse().unwrap());
+        assert_eq!(hosts.call(req).await.unwrap().status(), StatusCode::OK);
+    }
+}